@@ -0,0 +1,242 @@
+//! Print the on-disk structures of an image, without mounting it
+/*!
+    Everything is read through the block cache straight from where the
+    super block says it is, so a damaged image can still be looked at.
+    Block ids that point outside of the data area are flagged, not followed.
+*/
+use easy_fs::{
+    get_block_cache, Bitmap, BlockDevice, DiskInode, EasyFileSystem, Geometry, SuperBlock,
+    BLOCK_UNWRITTEN, DIRENT_SZ, NAME_LENGTH_LIMIT,
+};
+use std::sync::Arc;
+
+/// number of characters in a line of a bitmap map
+const MAP_WIDTH: usize = 64;
+/// number of lines a bitmap map takes at most
+const MAP_LINES: usize = 16;
+/// number of block ids in a line of a block list
+const IDS_PER_LINE: usize = 8;
+
+/// where the areas of an image start, as its super block says
+struct Image {
+    block_device: Arc<dyn BlockDevice>,
+    super_block: SuperBlock,
+    geometry: Geometry,
+    inode_area_start: u32,
+    data_bitmap_start: u32,
+    data_area_start: u32,
+}
+
+impl Image {
+    fn new(block_device: Arc<dyn BlockDevice>, super_block: SuperBlock) -> Self {
+        let inode_area_start = 1 + super_block.inode_bitmap_blocks;
+        let data_bitmap_start = inode_area_start + super_block.inode_area_blocks;
+        Self {
+            block_device,
+            geometry: super_block.geometry(),
+            inode_area_start,
+            data_bitmap_start,
+            data_area_start: data_bitmap_start + super_block.data_bitmap_blocks,
+            super_block,
+        }
+    }
+
+    /// number of inodes, both the bitmap and the inode area have room for
+    fn inode_count(&self) -> u32 {
+        let in_area = self.super_block.inode_area_blocks * self.geometry.inodes_per_block as u32;
+        let in_bitmap = self.super_block.inode_bitmap_blocks * self.geometry.block_bits as u32;
+        in_area.min(in_bitmap)
+    }
+
+    fn inode_bitmap(&self) -> Bitmap {
+        Bitmap::new(1, self.super_block.inode_bitmap_blocks as usize, self.geometry.block_size)
+    }
+
+    fn data_bitmap(&self) -> Bitmap {
+        Bitmap::new(
+            self.data_bitmap_start as usize,
+            self.super_block.data_bitmap_blocks as usize,
+            self.geometry.block_size,
+        )
+    }
+
+    fn in_data_area(&self, block_id: u32) -> bool {
+        block_id >= self.data_area_start
+            && block_id < self.data_area_start + self.super_block.data_area_blocks
+    }
+
+    /// a block id as it is listed, flagged when unwritten or out of place
+    fn describe(&self, entry: u32) -> String {
+        let block_id = entry & !BLOCK_UNWRITTEN;
+        let mut text = block_id.to_string();
+        if entry & BLOCK_UNWRITTEN != 0 {
+            text.push('u');
+        }
+        if !self.in_data_area(block_id) {
+            text.push('!');
+        }
+        text
+    }
+
+    fn read_ids(&self, block_id: u32) -> Vec<u32> {
+        get_block_cache(block_id as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .read_slice(|ids: &[u32]| ids.to_vec())
+    }
+
+    fn read_disk_inode(&self, inode_id: u32) -> DiskInode {
+        let inodes_per_block = self.geometry.inodes_per_block as u32;
+        let block_id = self.inode_area_start + inode_id / inodes_per_block;
+        let offset = (inode_id % inodes_per_block) as usize * core::mem::size_of::<DiskInode>();
+        get_block_cache(block_id as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .read(offset, |disk_inode: &DiskInode| *disk_inode)
+    }
+}
+
+/// print a bitmap as a map of groups of bits: '.' all free, '#' all used, '+' some used
+fn print_map(image: &Image, name: &str, bitmap: &Bitmap, bits: usize) {
+    let used: Vec<bool> = (0..bits)
+        .map(|bit| bitmap.is_allocated(&image.block_device, bit))
+        .collect();
+    let count = used.iter().filter(|used| **used).count();
+    let group = bits.div_ceil(MAP_WIDTH * MAP_LINES).max(1);
+    println!("{}: {} of {} used, one mark for {} bits", name, count, bits, group);
+    let marks: Vec<char> = used
+        .chunks(group)
+        .map(|chunk| match chunk.iter().filter(|used| **used).count() {
+            0 => '.',
+            n if n == chunk.len() => '#',
+            _ => '+',
+        })
+        .collect();
+    for (i, line) in marks.chunks(MAP_WIDTH).enumerate() {
+        println!("  {:>8} {}", i * MAP_WIDTH * group, line.iter().collect::<String>());
+    }
+}
+
+/// print block ids a few to a line, each line starting with the inner id of its first
+fn print_ids(image: &Image, first_inner_id: usize, entries: &[u32]) {
+    for (i, line) in entries.chunks(IDS_PER_LINE).enumerate() {
+        let ids: Vec<String> = line.iter().map(|entry| image.describe(*entry)).collect();
+        println!("    {:>8}: {}", first_inner_id + i * IDS_PER_LINE, ids.join(" "));
+    }
+}
+
+/// print the index of an inode, return the entries of its data blocks in order
+fn print_index(image: &Image, disk_inode: &DiskInode) -> Vec<u32> {
+    let geo = &image.geometry;
+    let mut data_blocks = disk_inode.data_blocks(geo) as usize;
+    if data_blocks > geo.indirect2_bound {
+        println!("  size is beyond what the index can reach");
+        data_blocks = geo.indirect2_bound;
+    }
+    let direct = data_blocks.min(disk_inode.direct.len());
+    let mut entries = disk_inode.direct[..direct].to_vec();
+    println!("  direct:");
+    print_ids(image, 0, &entries);
+    if data_blocks > disk_inode.direct.len() {
+        println!("  indirect1 @ {}:", image.describe(disk_inode.indirect1));
+        if image.in_data_area(disk_inode.indirect1) {
+            let count = (data_blocks - direct).min(geo.indirect1_count);
+            let ids = image.read_ids(disk_inode.indirect1);
+            print_ids(image, direct, &ids[..count]);
+            entries.extend_from_slice(&ids[..count]);
+        }
+    }
+    if data_blocks > geo.indirect1_bound {
+        println!("  indirect2 @ {}:", image.describe(disk_inode.indirect2));
+        if image.in_data_area(disk_inode.indirect2) {
+            let left = data_blocks - geo.indirect1_bound;
+            let indirect1s = image.read_ids(disk_inode.indirect2);
+            for (a, indirect1) in indirect1s[..left.div_ceil(geo.indirect1_count)].iter().enumerate() {
+                let first = geo.indirect1_bound + a * geo.indirect1_count;
+                println!("   [{}] @ {}:", a, image.describe(*indirect1));
+                if image.in_data_area(*indirect1) {
+                    let count = (left - a * geo.indirect1_count).min(geo.indirect1_count);
+                    let ids = image.read_ids(*indirect1);
+                    print_ids(image, first, &ids[..count]);
+                    entries.extend_from_slice(&ids[..count]);
+                }
+            }
+        }
+    }
+    entries
+}
+
+/// print the raw directory entries held in `entries`, up to `size` bytes
+fn print_dir_entries(image: &Image, entries: &[u32], size: usize) {
+    let block_size = image.geometry.block_size;
+    let inode_bitmap = image.inode_bitmap();
+    println!("  entries:");
+    println!("    {:>6} {:>8} {:>8}  name", "slot", "offset", "inode");
+    for (i, entry) in entries.iter().enumerate() {
+        let block_id = entry & !BLOCK_UNWRITTEN;
+        if !image.in_data_area(block_id) {
+            println!("    block {} of the dir is out of the data area", i);
+            continue;
+        }
+        let data = get_block_cache(block_id as usize, block_size, Arc::clone(&image.block_device))
+            .lock()
+            .read_slice(|data: &[u8]| data.to_vec());
+        for (j, raw) in data.chunks(DIRENT_SZ).enumerate() {
+            let offset = i * block_size + j * DIRENT_SZ;
+            if offset >= size {
+                return;
+            }
+            let name_bytes = &raw[..NAME_LENGTH_LIMIT + 1];
+            let name_len = name_bytes.iter().position(|byte| *byte == 0);
+            let name = String::from_utf8_lossy(&name_bytes[..name_len.unwrap_or(name_bytes.len())]);
+            let inode_id = u32::from_le_bytes(raw[NAME_LENGTH_LIMIT + 1..].try_into().unwrap());
+            let mut notes = String::new();
+            if name_len.is_none() {
+                notes.push_str(" (name not terminated)");
+            }
+            if inode_id >= image.inode_count() {
+                notes.push_str(" (no such inode)");
+            } else if !inode_bitmap.is_allocated(&image.block_device, inode_id as usize) {
+                notes.push_str(" (inode is free)");
+            }
+            println!("    {:>6} {:>8} {:>8}  {:?}{}", offset / DIRENT_SZ, offset, inode_id, name, notes);
+        }
+    }
+}
+
+fn print_inode(image: &Image, inode_id: u32) {
+    if inode_id >= image.inode_count() {
+        println!("inode {}: out of the inode area, which holds {}", inode_id, image.inode_count());
+        return;
+    }
+    let allocated = image.inode_bitmap().is_allocated(&image.block_device, inode_id as usize);
+    let disk_inode = image.read_disk_inode(inode_id);
+    println!("inode {} ({}): {:?}", inode_id, if allocated { "used" } else { "free" }, disk_inode);
+    println!("  blocks: {} data, {} with the index",
+             disk_inode.data_blocks(&image.geometry),
+             DiskInode::total_blocks(disk_inode.size, &image.geometry));
+    let entries = print_index(image, &disk_inode);
+    if disk_inode.is_dir() {
+        print_dir_entries(image, &entries, disk_inode.size as usize);
+    }
+}
+
+/// Print the super block, the bitmaps, and then each inode of `inode_ids`
+/**
+    In block lists a 'u' marks a block allocated but not written yet,
+    and a '!' a block id outside of the data area.
+*/
+pub fn run(block_device: Arc<dyn BlockDevice>, inode_ids: &[u32]) {
+    let super_block = EasyFileSystem::raw_super_block(&block_device);
+    println!("{:#?}", super_block);
+    if !super_block.is_valid() {
+        println!("bad magic or block size, this is not an easy-fs image");
+        return;
+    }
+    let image = Image::new(block_device, super_block);
+    println!("areas: inode bitmap @ 1, inodes @ {}, data bitmap @ {}, data @ {}",
+             image.inode_area_start, image.data_bitmap_start, image.data_area_start);
+    print_map(&image, "inode bitmap", &image.inode_bitmap(), image.inode_count() as usize);
+    print_map(&image, "data bitmap", &image.data_bitmap(), super_block.data_area_blocks as usize);
+    for inode_id in inode_ids {
+        print_inode(&image, *inode_id);
+    }
+}
@@ -1,94 +1,581 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use std::fs::{read_dir, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
-use easy_fs::BlockDevice;
+use easy_fs::crypt::Key;
+use easy_fs::fat32::{Fat32FileSystem, LONG_NAME_LIMIT};
+use easy_fs::{
+    mount_root, probe, BlockDevice, EasyFileSystem, FsInode, FsType, Geometry, BLOCK_SIZES,
+    DEFAULT_BLOCK_SZ, NAME_LENGTH_LIMIT,
+};
 
-const BLOCK_SZ: usize = 512;
+mod inspect;
+mod shell;
+mod tree;
+
+/// size of the image in bytes, unless asked otherwise
+const IMAGE_SIZE: usize = 16 * 2048 * 512; // 16MiB
+/// size of a FAT32 image, unless asked otherwise
+const FAT_IMAGE_SIZE: usize = 64 << 20;
+/// extra room in percent an auto-sized image leaves for inodes and data
+const AUTO_SIZE_HEADROOM: u32 = 25;
+/// extra inodes and data blocks an auto-sized image leaves on top of that
+const AUTO_SIZE_SLACK: u32 = 16;
 
 // block device
 struct BlockFile(Mutex<File>);
 
 impl BlockDevice for BlockFile {
-    fn read_lock(&self, block_id: usize, buf: &mut [u8]) {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
         let mut file = self.0.lock().unwrap();
-        file.seek(SeekFrom::Start((block_id * BLOCK_SZ) as u64))
+        file.seek(SeekFrom::Start((block_id * buf.len()) as u64))
             .expect("Error when seeking!");
-        assert_eq!(file.read(buf).unwrap(), BLOCK_SZ,
+        assert_eq!(file.read(buf).unwrap(), buf.len(),
             "Not a complete block!");
     }
 
-    fn write_lock(&self, block_id: usize, buf: &[u8]) {
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
         let mut file = self.0.lock().unwrap();
-        file.seek(SeekFrom::Start((block_id as BLOCK_SZ) as u64))
+        file.seek(SeekFrom::Start((block_id * buf.len()) as u64))
             .expect("Error when seeking!");
-        assert_eq!(file.write(buf).unwrap(), BLOCK_SZ,
+        assert_eq!(file.write(buf).unwrap(), buf.len(),
             "Not a complete block!");
     }
 }
 
-fn easy_fs_pack() -> std::io::Result<()> {
+/// parse a key written as 64 hex digits
+fn parse_key(hex: &str) -> Key {
+    assert_eq!(hex.len(), 64, "A key is 64 hex digits!");
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).expect("Bad hex digit in key!");
+    }
+    key
+}
+
+/// parse a preallocated file written as name:size
+fn parse_prealloc(spec: &str) -> (&str, usize) {
+    let (name, size) = spec.rsplit_once(':').expect("Preallocation is name:size!");
+    (name, size.parse().expect("Bad preallocation size!"))
+}
+
+/// parse a size in bytes, with an optional K, M or G suffix
+fn parse_size(size: &str) -> usize {
+    let (digits, unit) = match size.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits.parse::<usize>().expect("Bad image size!") * unit
+}
+
+/// grow a count by the auto-size headroom
+fn with_headroom(count: u32) -> u32 {
+    count + count * AUTO_SIZE_HEADROOM / 100 + AUTO_SIZE_SLACK
+}
+
+fn easy_fs_pack(matches: &ArgMatches) -> std::io::Result<()> {
     // get app src_path/target_path
-    let matches = App::new("EasyFileSystem packer")
-        .arg(
-            Arg::with_name("source")
-                .short("s")
-                .long("source")
-                .takes_value(true)
-                .help("Executable source dir(with backslash)"),
-        )
-        .arg(
-            Arg::with_name("target")
-                .short("t")
-                .long("target")
-                .takes_value(true)
-                .help("Executable target dir(with backslash)"),
-        )
-        .get_matches();
-    let src_path = matches.value_of("source").unwrap();
+    let src_path = matches.value_of("source");
     let target_path = matches.value_of("target").unwrap();
-    println!("src_path = {}\ntarget_path = {}",
-             src_path, target_path);
-    
+    let block_size = matches
+        .value_of("block-size")
+        .map_or(DEFAULT_BLOCK_SZ, |s| s.parse().unwrap());
+    assert!(BLOCK_SIZES.contains(&block_size));
+    let geometry = Geometry::new(block_size);
+    let compressed_apps: Vec<&str> = matches
+        .values_of("compress")
+        .map_or(Vec::new(), |values| values.collect());
+    let key = matches.value_of("key").map(parse_key);
+    let preallocs: Vec<(&str, usize)> = matches
+        .values_of("prealloc")
+        .map_or(Vec::new(), |values| values.map(parse_prealloc).collect());
+    let dir_path = matches.value_of("dir");
+    println!("src_path = {}\ntarget_path = {}\nblock_size = {}",
+             src_path.unwrap_or("-"), target_path, block_size);
+
+    // collect name of apps, only the extension of the source file goes
+    let apps: Vec<_> = match src_path {
+        Some(src_path) => read_dir(src_path)
+            .unwrap()
+            .map(|dir_entry| {
+                let path = dir_entry.unwrap().path();
+                path.file_stem().unwrap().to_str().unwrap().to_string()
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    // load app data from host file system
+    let apps: Vec<(String, Vec<u8>)> = apps
+        .into_iter()
+        .map(|app| {
+            let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
+            let mut all_data: Vec<u8> = Vec::new();
+            host_file.read_to_end(&mut all_data).unwrap();
+            (app, all_data)
+        })
+        .collect();
+
+    // choose the geometry of the image
+    let inodes = matches.value_of("inodes").map(|n| n.parse::<u32>().expect("Bad inode count!"));
+    let (total_blocks, inode_bitmap_blocks) = if matches.is_present("auto-size") {
+        // what the root dir and everything in it take up, compressed apps
+        // are counted as they are before compression
+        let mut usage = tree::Usage::default();
+        let mut root_entries = apps.len() + preallocs.len();
+        for (_, data) in apps.iter() {
+            usage.add_file(data.len(), &geometry);
+        }
+        for (_, size) in preallocs.iter() {
+            usage.add_file(*size, &geometry);
+        }
+        if let Some(dir_path) = dir_path {
+            root_entries += tree::measure(Path::new(dir_path), &geometry, &mut usage)?;
+        }
+        usage.add_dir(root_entries, &geometry);
+        let inode_bitmap_blocks =
+            geometry.inode_bitmap_blocks(inodes.unwrap_or_else(|| with_headroom(usage.inodes)));
+        let data_blocks = with_headroom(usage.data_blocks);
+        (geometry.total_blocks(inode_bitmap_blocks, data_blocks), inode_bitmap_blocks)
+    } else {
+        let image_size = matches.value_of("size").map_or(IMAGE_SIZE, parse_size);
+        (
+            (image_size / block_size) as u32,
+            inodes.map_or(1, |inodes| geometry.inode_bitmap_blocks(inodes)),
+        )
+    };
+    println!("total_blocks = {}\ninodes = {}",
+             total_blocks, inode_bitmap_blocks as usize * geometry.block_bits);
+
     // create a block device file
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(16 * 2048 * 512).unwrap(); // 4MiB
+        f.set_len(total_blocks as u64 * block_size as u64).unwrap();
         f
     })));
 
-    // collect name of apps
-    let apps: Vec<_> = read_dir(src_path)
-        .unwrap()
-        .into_iter()
-        .map(|dir_entry| {
-            let mut name_with_ext = dir_entry
-                .unwrap()
-                .file_name()
-                .into_string()
-                .unwrap();
-            name_with_ext.drain(
-                name_with_ext.find('.').unwrap()..name_with_ext.len()
-            );
-            name_with_ext
-        })
-        .collect();
+    // create a filesystem whose size is the same as the image
+    let efs = match &key {
+        Some(key) => EasyFileSystem::create_encrypted(
+            block_file, total_blocks, inode_bitmap_blocks, block_size, key,
+        ),
+        None => EasyFileSystem::create(block_file, total_blocks, inode_bitmap_blocks, block_size),
+    };
+    let root_inode = EasyFileSystem::root_inode(&efs);
 
-    //TODO EasyFileSystem related operations
-    
-    Ok(());
+    for (app, all_data) in apps {
+        // create a file in easy-fs
+        let inode = root_inode.create(app.as_str()).unwrap();
+        // write data to easy-fs
+        if compressed_apps.contains(&app.as_str()) {
+            inode.write_compressed(all_data.as_slice());
+            println!("{}: stored compressed", app);
+        } else {
+            inode.write_at(0, all_data.as_slice());
+        }
+    }
+    if let Some(dir_path) = dir_path {
+        let mut counts = tree::Counts::default();
+        tree::import(&root_inode, Path::new(dir_path), &geometry, &mut counts)?;
+        println!("{}: {} files, {} dirs, {} bytes imported",
+                 dir_path, counts.files, counts.dirs, counts.bytes);
+    }
+    for (name, size) in preallocs {
+        root_inode.create(name).unwrap().fallocate(size);
+        println!("{}: {} bytes preallocated", name, size);
+    }
+
+    Ok(())
 }
 
-fn main() {
-    println!("easy-fs-fuse");
+/// open an existing image, decrypting it with `key`
+fn open_image(image_path: &str, key: Option<&Key>) -> std::io::Result<Arc<EasyFileSystem>> {
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(true).open(image_path)?,
+    )));
+    Ok(match key {
+        Some(key) => EasyFileSystem::open_with_key(block_file, key),
+        None => EasyFileSystem::open(block_file),
+    })
 }
 
+/// mount an existing image of any filesystem, return its root and longest name
+/**
+    Only easy-fs knows about keys, so with a key the image is easy-fs.
+*/
+fn open_root(image_path: &str, key: Option<&Key>) -> std::io::Result<(Arc<dyn FsInode>, usize)> {
+    if key.is_some() {
+        let efs = open_image(image_path, key)?;
+        return Ok((EasyFileSystem::root_inode(&efs), NAME_LENGTH_LIMIT));
+    }
+    let block_file: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(true).open(image_path)?,
+    )));
+    let name_limit = match probe(&block_file) {
+        Some(FsType::Easy) => NAME_LENGTH_LIMIT,
+        Some(FsType::Ext2) | Some(FsType::Fat32) => LONG_NAME_LIMIT,
+        None => {
+            return Err(std::io::Error::other(format!("{}: no known filesystem", image_path)));
+        }
+    };
+    Ok((mount_root(block_file).unwrap(), name_limit))
+}
 
+/// rebuild the tree of an existing image on the host
+fn easy_fs_unpack(matches: &ArgMatches) -> std::io::Result<()> {
+    let key = matches.value_of("key").map(parse_key);
+    let (root, _) = open_root(matches.value_of("image").unwrap(), key.as_ref())?;
+    let dir_path = matches.value_of("dir").unwrap();
+    let mut counts = tree::Counts::default();
+    tree::export(&*root, Path::new(dir_path), &mut counts)?;
+    println!("{}: {} files, {} dirs, {} bytes unpacked",
+             dir_path, counts.files, counts.dirs, counts.bytes);
+    Ok(())
+}
 
+/// print the on-disk structures of an existing image
+fn easy_fs_inspect(matches: &ArgMatches) -> std::io::Result<()> {
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        File::open(matches.value_of("image").unwrap())?,
+    )));
+    let inode_ids: Vec<u32> = matches
+        .values_of("inode")
+        .map_or(Vec::new(), |values| {
+            values.map(|id| id.parse().expect("Bad inode number!")).collect()
+        });
+    inspect::run(block_file, &inode_ids);
+    Ok(())
+}
 
+/// grow an existing image in place, then read every file back
+fn easy_fs_resize(matches: &ArgMatches) -> std::io::Result<()> {
+    let image_path = matches.value_of("image").unwrap();
+    let key = matches.value_of("key").map(parse_key);
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(true).open(image_path)?,
+    )));
+    let device: Arc<dyn BlockDevice> = block_file.clone();
+    let mount = || match &key {
+        Some(key) => EasyFileSystem::open_with_key(Arc::clone(&device), key),
+        None => EasyFileSystem::open(Arc::clone(&device)),
+    };
+    let mut before = Vec::new();
+    let super_block = {
+        let efs = mount();
+        tree::digest(&EasyFileSystem::root_inode(&efs), "", &mut before);
+        efs.super_block()
+    };
+    let geometry = super_block.geometry();
+    let total_blocks = matches
+        .value_of("size")
+        .map_or(super_block.total_blocks, |size| (parse_size(size) / geometry.block_size) as u32);
+    let inode_bitmap_blocks = matches
+        .value_of("inodes")
+        .map_or(super_block.inode_bitmap_blocks, |inodes| {
+            geometry.inode_bitmap_blocks(inodes.parse().expect("Bad inode count!"))
+        })
+        .max(super_block.inode_bitmap_blocks);
+    // the device has to be large enough before the filesystem grows on it
+    let image_size = total_blocks as u64 * geometry.block_size as u64;
+    {
+        let file = block_file.0.lock().unwrap();
+        if file.metadata()?.len() < image_size {
+            file.set_len(image_size)?;
+        }
+    }
+    if !EasyFileSystem::grow(Arc::clone(&device), total_blocks, inode_bitmap_blocks) {
+        println!("{}: nothing to grow", image_path);
+        return Ok(());
+    }
+    let efs = mount();
+    let mut after = Vec::new();
+    tree::digest(&EasyFileSystem::root_inode(&efs), "", &mut after);
+    if after != before {
+        return Err(std::io::Error::other("files differ after the grow"));
+    }
+    let super_block = efs.super_block();
+    println!("{}: {} blocks, {} inodes, {} files read back",
+             image_path,
+             super_block.total_blocks,
+             super_block.inode_bitmap_blocks as usize * geometry.block_bits,
+             after.len());
+    Ok(())
+}
+
+/// compact the files of an existing image, then read every file back
+fn easy_fs_defrag(matches: &ArgMatches) -> std::io::Result<()> {
+    let image_path = matches.value_of("image").unwrap();
+    let key = matches.value_of("key").map(parse_key);
+    let efs = open_image(image_path, key.as_ref())?;
+    let mut before = Vec::new();
+    tree::digest(&EasyFileSystem::root_inode(&efs), "", &mut before);
+    println!("before: {}", efs.fragmentation());
+    let moved = efs.defrag();
+    println!("after: {}", efs.fragmentation());
+    let mut after = Vec::new();
+    tree::digest(&EasyFileSystem::root_inode(&efs), "", &mut after);
+    if after != before {
+        return Err(std::io::Error::other("files differ after the defrag"));
+    }
+    println!("{}: {} blocks moved, {} files read back", image_path, moved, after.len());
+    Ok(())
+}
+
+/// run shell commands on an existing image
+fn easy_fs_shell(matches: &ArgMatches) -> std::io::Result<()> {
+    let key = matches.value_of("key").map(parse_key);
+    let (root, name_limit) = open_root(matches.value_of("image").unwrap(), key.as_ref())?;
+    shell::run(root, name_limit)
+}
 
+/// make an empty FAT32 image
+fn easy_fs_mkfat(matches: &ArgMatches) -> std::io::Result<()> {
+    let image_path = matches.value_of("image").unwrap();
+    let size = matches.value_of("size").map_or(FAT_IMAGE_SIZE, parse_size);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(image_path)?;
+    file.set_len(size as u64)?;
+    let block_file = Arc::new(BlockFile(Mutex::new(file)));
+    let fs = Fat32FileSystem::format(block_file, (size / 512) as u32);
+    println!("{}: {} clusters of {} bytes", image_path, fs.free_clusters(), fs.cluster_size());
+    Ok(())
+}
+
+/// move a legacy image to the current format in place
+fn easy_fs_upgrade(matches: &ArgMatches) -> std::io::Result<()> {
+    let image_path = matches.value_of("image").unwrap();
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new().read(true).write(true).open(image_path)?,
+    )));
+    if EasyFileSystem::upgrade(block_file) {
+        println!("{}: upgraded", image_path);
+    } else {
+        println!("{}: already up to date", image_path);
+    }
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("EasyFileSystem packer")
+        .arg(
+            Arg::with_name("source")
+                .short("s")
+                .long("source")
+                .takes_value(true)
+                .help("Executable source dir(with backslash)"),
+        )
+        .arg(
+            Arg::with_name("target")
+                .short("t")
+                .long("target")
+                .takes_value(true)
+                .help("Executable target dir(with backslash)"),
+        )
+        .arg(
+            Arg::with_name("block-size")
+                .short("b")
+                .long("block-size")
+                .takes_value(true)
+                .possible_values(&["512", "1024", "2048", "4096"])
+                .help("Block size of the image in bytes"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .help("Size of the image in bytes, K, M and G suffixes allowed [default: 16M]"),
+        )
+        .arg(
+            Arg::with_name("inodes")
+                .short("i")
+                .long("inodes")
+                .takes_value(true)
+                .help("Room for at least this many inodes [default: as many as one bitmap block tracks]"),
+        )
+        .arg(
+            Arg::with_name("auto-size")
+                .short("a")
+                .long("auto-size")
+                .conflicts_with("size")
+                .help("Make the image just large enough for what is packed, with some headroom"),
+        )
+        .arg(
+            Arg::with_name("compress")
+                .short("c")
+                .long("compress")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Store this app compressed, can be given many times"),
+        )
+        .arg(
+            Arg::with_name("key")
+                .short("k")
+                .long("key")
+                .takes_value(true)
+                .help("Encrypt file data with this key, given as 64 hex digits"),
+        )
+        .arg(
+            Arg::with_name("prealloc")
+                .short("p")
+                .long("prealloc")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Create an empty file with blocks reserved, as name:size, can be given many times"),
+        )
+        .arg(
+            Arg::with_name("dir")
+                .short("d")
+                .long("dir")
+                .takes_value(true)
+                .help("Also import this host dir with everything under it, names kept whole"),
+        )
+        .subcommand(
+            SubCommand::with_name("upgrade")
+                .about("Move an image made by an older packer to the current format")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unpack")
+                .about("Rebuild the whole tree of an image of easy-fs, FAT32 or ext2 under a host dir")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .required(true)
+                        .help("Host dir to rebuild the tree in, made if missing"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .short("k")
+                        .long("key")
+                        .takes_value(true)
+                        .help("Key of an encrypted image, given as 64 hex digits"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("resize")
+                .about("Grow an image in place, then read every file back")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .takes_value(true)
+                        .help("New size of the image in bytes, K, M and G suffixes allowed"),
+                )
+                .arg(
+                    Arg::with_name("inodes")
+                        .short("i")
+                        .long("inodes")
+                        .takes_value(true)
+                        .help("Room for at least this many inodes"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .short("k")
+                        .long("key")
+                        .takes_value(true)
+                        .help("Key of an encrypted image, given as 64 hex digits"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("defrag")
+                .about("Move the blocks of every file into one run, reporting fragmentation before and after")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .short("k")
+                        .long("key")
+                        .takes_value(true)
+                        .help("Key of an encrypted image, given as 64 hex digits"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Print the super block, the bitmaps and chosen inodes of an image, mounting nothing")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("inode")
+                        .short("i")
+                        .long("inode")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Also print this inode with its block lists, and its entries for a dir, can be given many times"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mkfat")
+                .about("Make an empty FAT32 image, which shell and unpack can use")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("size")
+                        .long("size")
+                        .takes_value(true)
+                        .help("Size of the image in bytes, K, M and G suffixes allowed [default: 64M]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("shell")
+                .about("Run ls, cat, put, get, rm and mkdir on an image of easy-fs, FAT32 or ext2, from the terminal or a script on stdin")
+                .arg(
+                    Arg::with_name("image")
+                        .required(true)
+                        .help("Path of the image"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .short("k")
+                        .long("key")
+                        .takes_value(true)
+                        .help("Key of an encrypted image, given as 64 hex digits"),
+                ),
+        )
+        .get_matches();
+    match matches.subcommand() {
+        ("upgrade", Some(sub)) => easy_fs_upgrade(sub).expect("Error when upgrading easy-fs!"),
+        ("unpack", Some(sub)) => easy_fs_unpack(sub).expect("Error when unpacking easy-fs!"),
+        ("resize", Some(sub)) => easy_fs_resize(sub).expect("Error when resizing easy-fs!"),
+        ("defrag", Some(sub)) => easy_fs_defrag(sub).expect("Error when defragmenting easy-fs!"),
+        ("inspect", Some(sub)) => easy_fs_inspect(sub).expect("Error when inspecting easy-fs!"),
+        ("mkfat", Some(sub)) => easy_fs_mkfat(sub).expect("Error when making a FAT32 image!"),
+        ("shell", Some(sub)) => easy_fs_shell(sub).expect("Error in easy-fs shell!"),
+        _ => easy_fs_pack(&matches).expect("Error when packing easy-fs!"),
+    }
+}
@@ -0,0 +1,205 @@
+//! A shell over an existing image, everything goes through the [`FsInode`] of its root
+use easy_fs::FsInode;
+use std::fs::File;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::sync::Arc;
+
+const HELP: &str = "\
+ls [path]              list a directory
+cat <path>             print a file
+put <host> [path]      import a host file, replacing an existing one
+get <path> [host]      extract a file to the host
+rm <path>              remove a file or an empty directory
+mkdir <path>           make a directory
+help                   show this help
+exit                   leave the shell";
+
+/// Find the inode at `path`, relative to the root
+pub fn lookup(root: &Arc<dyn FsInode>, path: &str) -> Result<Arc<dyn FsInode>, String> {
+    let mut inode = Arc::clone(root);
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        if !inode.is_dir() {
+            return Err(format!("{}: not a directory", path));
+        }
+        inode = inode
+            .find(name)
+            .ok_or_else(|| format!("{}: no such file or directory", path))?;
+    }
+    Ok(inode)
+}
+
+/// Find the directory holding `path`, and the last name of `path`, to change it
+/**
+    A read-only image is refused before anything else.
+*/
+fn parent_and_name<'a>(
+    root: &Arc<dyn FsInode>,
+    path: &'a str,
+    name_limit: usize,
+) -> Result<(Arc<dyn FsInode>, &'a str), String> {
+    if root.is_read_only() {
+        return Err(String::from("read-only filesystem"));
+    }
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.is_empty() {
+        return Err(String::from("the root has no name"));
+    }
+    if name.len() > name_limit {
+        return Err(format!("{}: name longer than {} bytes", name, name_limit));
+    }
+    let dir = lookup(root, parent)?;
+    if !dir.is_dir() {
+        return Err(format!("{}: not a directory", parent));
+    }
+    Ok((dir, name))
+}
+
+/// Read the whole data of a file
+pub fn read_all(inode: &dyn FsInode) -> Vec<u8> {
+    let mut data = vec![0u8; inode.size()];
+    inode.read_at(0, &mut data);
+    data
+}
+
+fn ls(root: &Arc<dyn FsInode>, path: &str) -> Result<(), String> {
+    let inode = lookup(root, path)?;
+    if !inode.is_dir() {
+        println!("{:>10} {}", inode.size(), path);
+        return Ok(());
+    }
+    for name in inode.ls() {
+        let child = inode.find(&name).unwrap();
+        if child.is_dir() {
+            println!("{:>10} {}/", "-", name);
+        } else {
+            println!("{:>10} {}", child.size(), name);
+        }
+    }
+    Ok(())
+}
+
+fn cat(root: &Arc<dyn FsInode>, path: &str) -> Result<(), String> {
+    let inode = lookup(root, path)?;
+    if inode.is_dir() {
+        return Err(format!("{}: is a directory", path));
+    }
+    io::stdout().write_all(&read_all(&*inode)).map_err(|e| e.to_string())
+}
+
+fn put(root: &Arc<dyn FsInode>, host_path: &str, path: &str, name_limit: usize) -> Result<(), String> {
+    let mut data = Vec::new();
+    File::open(host_path)
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .map_err(|e| format!("{}: {}", host_path, e))?;
+    let (dir, name) = parent_and_name(root, path, name_limit)?;
+    let inode = match dir.find(name) {
+        Some(inode) if inode.is_dir() => return Err(format!("{}: is a directory", path)),
+        Some(inode) => {
+            inode.clear();
+            inode
+        }
+        None => dir
+            .create(name)
+            .ok_or_else(|| format!("{}: not a valid name here", path))?,
+    };
+    let written = inode.write_at(0, &data);
+    if written < data.len() {
+        return Err(format!("{}: only {} of {} bytes fit in the image", path, written, data.len()));
+    }
+    Ok(())
+}
+
+fn get(root: &Arc<dyn FsInode>, path: &str, host_path: &str) -> Result<(), String> {
+    let inode = lookup(root, path)?;
+    if inode.is_dir() {
+        return Err(format!("{}: is a directory", path));
+    }
+    File::create(host_path)
+        .and_then(|mut file| file.write_all(&read_all(&*inode)))
+        .map_err(|e| format!("{}: {}", host_path, e))
+}
+
+fn rm(root: &Arc<dyn FsInode>, path: &str, name_limit: usize) -> Result<(), String> {
+    let (dir, name) = parent_and_name(root, path, name_limit)?;
+    let inode = dir
+        .find(name)
+        .ok_or_else(|| format!("{}: no such file or directory", path))?;
+    if inode.is_dir() && !inode.ls().is_empty() {
+        return Err(format!("{}: directory not empty", path));
+    }
+    if !dir.unlink(name) {
+        return Err(format!("{}: cannot be removed", path));
+    }
+    Ok(())
+}
+
+fn mkdir(root: &Arc<dyn FsInode>, path: &str, name_limit: usize) -> Result<(), String> {
+    let (dir, name) = parent_and_name(root, path, name_limit)?;
+    if dir.find(name).is_some() {
+        return Err(format!("{}: already exists", path));
+    }
+    dir.create_dir(name)
+        .map(|_| ())
+        .ok_or_else(|| format!("{}: not a valid name here", path))
+}
+
+/// last name of a path, on the host or in the image
+fn base_name(path: &str) -> &str {
+    path.trim_end_matches('/').rsplit('/').next().unwrap()
+}
+
+/// Run one command, return false when the shell should stop
+fn execute(root: &Arc<dyn FsInode>, name_limit: usize, words: &[&str]) -> Result<bool, String> {
+    match words {
+        ["ls"] => ls(root, "/")?,
+        ["ls", path] => ls(root, path)?,
+        ["cat", path] => cat(root, path)?,
+        ["put", host_path] => put(root, host_path, base_name(host_path), name_limit)?,
+        ["put", host_path, path] => put(root, host_path, path, name_limit)?,
+        ["get", path] => get(root, path, base_name(path))?,
+        ["get", path, host_path] => get(root, path, host_path)?,
+        ["rm", path] => rm(root, path, name_limit)?,
+        ["mkdir", path] => mkdir(root, path, name_limit)?,
+        ["help"] => println!("{}", HELP),
+        ["exit"] | ["quit"] => return Ok(false),
+        _ => return Err(String::from("unknown command or wrong arguments, try help")),
+    }
+    Ok(true)
+}
+
+/// Run commands read from stdin
+/**
+    With a terminal the shell prompts and reports errors as it goes;
+    otherwise stdin is a script, which stops at its first failing command.
+    Names longer than `name_limit` bytes are refused before reaching the
+    filesystem.
+*/
+pub fn run(root: Arc<dyn FsInode>, name_limit: usize) -> io::Result<()> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut line = String::new();
+    loop {
+        if interactive {
+            print!("efs> ");
+            io::stdout().flush()?;
+        }
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('#') {
+            continue;
+        }
+        match execute(&root, name_limit, &words) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(message) if interactive => eprintln!("{}: {}", words[0], message),
+            Err(message) => {
+                return Err(io::Error::other(format!("{}: {}", words[0], message)));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+//! Copy whole directory trees between the host and an image
+use crate::shell::read_all;
+use easy_fs::{DiskInode, FsInode, Geometry, Inode, DIRENT_SZ, NAME_LENGTH_LIMIT};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// what a copy went through
+#[derive(Default)]
+pub struct Counts {
+    pub files: usize,
+    pub dirs: usize,
+    pub bytes: usize,
+}
+
+/// inodes and data blocks some files and dirs take up in an image
+#[derive(Default)]
+pub struct Usage {
+    pub inodes: u32,
+    pub data_blocks: u32,
+}
+
+impl Usage {
+    /// count one file of `size` bytes
+    pub fn add_file(&mut self, size: usize, geo: &Geometry) {
+        self.inodes += 1;
+        self.data_blocks += DiskInode::total_blocks(size as u32, geo);
+    }
+    /// count one dir holding `entries` entries
+    pub fn add_dir(&mut self, entries: usize, geo: &Geometry) {
+        self.add_file(entries * DIRENT_SZ, geo);
+    }
+}
+
+fn error(path: &Path, message: &str) -> io::Error {
+    io::Error::other(format!("{}: {}", path.display(), message))
+}
+
+/// Copy everything under the host dir `host` into the image dir `dir`
+/**
+    Names are kept whole, so they must be UTF-8 and fit in a directory
+    entry. Entries are taken in name order, which makes the image the
+    same from one run to the next. Symbolic links are skipped.
+*/
+pub fn import(dir: &Arc<Inode>, host: &Path, geo: &Geometry, counts: &mut Counts) -> io::Result<()> {
+    let mut entries = fs::read_dir(host)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|_| error(&path, "name is not UTF-8"))?;
+        if name.len() > NAME_LENGTH_LIMIT {
+            return Err(error(&path, &format!("name longer than {} bytes", NAME_LENGTH_LIMIT)));
+        }
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let child = dir
+                .create_dir(&name)
+                .ok_or_else(|| error(&path, "already exists in the image"))?;
+            counts.dirs += 1;
+            import(&child, &path, geo, counts)?;
+        } else if file_type.is_file() {
+            let data = fs::read(&path)?;
+            if data.len() > geo.indirect2_bound * geo.block_size {
+                return Err(error(&path, "too large for the block size"));
+            }
+            let child = dir
+                .create(&name)
+                .ok_or_else(|| error(&path, "already exists in the image"))?;
+            if child.write_at(0, &data) < data.len() {
+                return Err(error(&path, "does not fit in the image"));
+            }
+            counts.files += 1;
+            counts.bytes += data.len();
+        } else {
+            eprintln!("{}: skipped, not a file or directory", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Count what everything under the host dir `host` takes up once imported
+/**
+    The dir itself is left to the caller, who gets its number of entries.
+*/
+pub fn measure(host: &Path, geo: &Geometry, usage: &mut Usage) -> io::Result<usize> {
+    let mut entries = 0;
+    for entry in fs::read_dir(host)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let children = measure(&entry.path(), geo, usage)?;
+            usage.add_dir(children, geo);
+        } else if file_type.is_file() {
+            usage.add_file(entry.metadata()?.len() as usize, geo);
+        } else {
+            continue;
+        }
+        entries += 1;
+    }
+    Ok(entries)
+}
+
+/// Rebuild the image dir `dir` under the host dir `host`, on any filesystem
+pub fn export(dir: &dyn FsInode, host: &Path, counts: &mut Counts) -> io::Result<()> {
+    fs::create_dir_all(host)?;
+    for name in dir.ls() {
+        let child = dir.find(&name).unwrap();
+        let path = host.join(&name);
+        // a damaged image must not write outside of `host`
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(error(&path, "bad name in the image"));
+        }
+        if child.is_dir() {
+            counts.dirs += 1;
+            export(&*child, &path, counts)?;
+        } else {
+            let data = read_all(&*child);
+            File::create(&path)?.write_all(&data)?;
+            counts.files += 1;
+            counts.bytes += data.len();
+        }
+    }
+    Ok(())
+}
+
+/// FNV-1a hash of some data
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Read every file under the image dir `dir`, listing its path, size and hash
+pub fn digest(dir: &Inode, prefix: &str, digests: &mut Vec<(String, usize, u64)>) {
+    for name in dir.ls() {
+        let child = dir.find(&name).unwrap();
+        let path = format!("{}/{}", prefix, name);
+        if child.is_dir() {
+            digest(&child, &path, digests);
+        } else {
+            let data = read_all(&*child);
+            digests.push((path, data.len(), fnv1a(&data)));
+        }
+    }
+}
@@ -0,0 +1,24 @@
+//! Helpers shared by the tests, which run the packer binary
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// a fresh scratch dir for one test
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// run the packer, which has to succeed, and return what it printed
+pub fn fuse(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_easy-fs-fuse"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
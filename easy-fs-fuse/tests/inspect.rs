@@ -0,0 +1,43 @@
+mod common;
+
+use common::{fuse, scratch};
+use std::fs;
+
+/// the number printed after `key` in the output of inspect
+fn number_after(output: &str, key: &str) -> usize {
+    let rest = &output[output.find(key).unwrap() + key.len()..];
+    rest.split(|c: char| !c.is_ascii_digit()).next().unwrap().parse().unwrap()
+}
+
+#[test]
+fn inspect_shows_inodes_and_damage() {
+    let dir = scratch("inspect_shows_inodes_and_damage");
+    let source = dir.join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("a"), [1u8; 20 * 512]).unwrap();
+    let image_dir = format!("{}/", dir.display());
+    fuse(&["-t", &image_dir, "--size", "1M", "-d", source.to_str().unwrap(), "-p", "later:1000"]);
+    let image_path = dir.join("fs.img");
+    let image = image_path.to_str().unwrap();
+    let output = fuse(&["inspect", image, "-i", "0", "-i", "1"]);
+    assert!(output.contains("inode bitmap: 4 of 4096 used"), "{}", output);
+    assert!(output.contains("\"a\""));
+    assert!(output.contains("\"sub\""));
+    assert!(output.contains("\"later\""));
+    let data_start = number_after(&output, "data @ ");
+    // the first data block of the root dir holds its entries, "a" comes first
+    assert!(output.contains(&format!("0: {}\n", data_start)));
+    let later = fuse(&["inspect", image, "-i", "3"]);
+    assert!(later.contains("u "), "{}", later);
+
+    // point the entry of "a" at a missing inode, and the first block of "a" at the super block
+    let mut bytes = fs::read(&image_path).unwrap();
+    let entry = data_start * 512 + 28;
+    bytes[entry..entry + 4].copy_from_slice(&5000u32.to_le_bytes());
+    let direct0 = 2 * 512 + 128 + 4;
+    bytes[direct0..direct0 + 4].copy_from_slice(&0u32.to_le_bytes());
+    fs::write(&image_path, &bytes).unwrap();
+    let output = fuse(&["inspect", image, "-i", "0", "-i", "1"]);
+    assert!(output.contains("\"a\" (no such inode)"), "{}", output);
+    assert!(output.contains("0: 0! "), "{}", output);
+}
@@ -0,0 +1,247 @@
+mod common;
+
+use common::{fuse, scratch};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// every file and dir under `dir`, with the data of the files
+fn contents(dir: &Path) -> Vec<(PathBuf, Option<Vec<u8>>)> {
+    let mut all = Vec::new();
+    let mut entries: Vec<_> = fs::read_dir(dir).unwrap().map(|e| e.unwrap().path()).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            all.push((path.clone(), None));
+            all.extend(contents(&path));
+        } else {
+            all.push((path.clone(), Some(fs::read(&path).unwrap())));
+        }
+    }
+    all.into_iter()
+        .map(|(path, data)| (path.strip_prefix(dir).unwrap_or(&path).to_path_buf(), data))
+        .collect()
+}
+
+fn host_tree(root: &Path) {
+    fs::create_dir_all(root.join("docs/deep/deeper")).unwrap();
+    fs::create_dir_all(root.join("empty")).unwrap();
+    // names that only differ after the first '.'
+    fs::write(root.join("a.b.txt"), b"first").unwrap();
+    fs::write(root.join("a.c"), b"second").unwrap();
+    fs::write(root.join("nothing"), b"").unwrap();
+    let big: Vec<u8> = (0..300_000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+    fs::write(root.join("docs/deep/big.bin"), &big).unwrap();
+    fs::write(root.join("docs/deep/deeper/.hidden"), b"dot file").unwrap();
+}
+
+#[test]
+fn tree_round_trips() {
+    let dir = scratch("tree_round_trips");
+    let source = dir.join("source");
+    host_tree(&source);
+    let image_dir = format!("{}/", dir.display());
+    for block_size in ["512", "4096"] {
+        fuse(&["-t", &image_dir, "-b", block_size, "-d", source.to_str().unwrap()]);
+        let unpacked = dir.join(format!("unpacked{}", block_size));
+        fuse(&["unpack", dir.join("fs.img").to_str().unwrap(), unpacked.to_str().unwrap()]);
+        assert_eq!(contents(&unpacked), contents(&source));
+    }
+}
+
+#[test]
+fn encrypted_tree_round_trips() {
+    let dir = scratch("encrypted_tree_round_trips");
+    let source = dir.join("source");
+    host_tree(&source);
+    let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    let image_dir = format!("{}/", dir.display());
+    fuse(&["-t", &image_dir, "-k", key, "-d", source.to_str().unwrap()]);
+    let unpacked = dir.join("unpacked");
+    fuse(&["unpack", dir.join("fs.img").to_str().unwrap(), unpacked.to_str().unwrap(), "-k", key]);
+    assert_eq!(contents(&unpacked), contents(&source));
+}
+
+#[test]
+fn long_names_are_refused() {
+    let dir = scratch("long_names_are_refused");
+    let source = dir.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("a_name_that_is_much_too_long_for_easy_fs"), b"x").unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_easy-fs-fuse"))
+        .args(["-t", &format!("{}/", dir.display()), "-d", source.to_str().unwrap()])
+        .output()
+        .unwrap()
+        .status;
+    assert!(!status.success());
+}
+
+#[test]
+fn auto_size_fits_more_than_the_default_image() {
+    let dir = scratch("auto_size_fits_more_than_the_default_image");
+    let source = dir.join("source");
+    // thousands of small files, and more data than the default 16 MiB
+    for d in 0..50 {
+        let sub = source.join(format!("dir{}", d));
+        fs::create_dir_all(&sub).unwrap();
+        for f in 0..90 {
+            fs::write(sub.join(format!("file{}", f)), format!("{} {}", d, f)).unwrap();
+        }
+    }
+    let big: Vec<u8> = (0..20 << 20).map(|i: u32| (i % 253) as u8).collect();
+    fs::write(source.join("big"), &big).unwrap();
+    let image_dir = format!("{}/", dir.display());
+    fuse(&["-t", &image_dir, "-b", "1024", "--auto-size", "-d", source.to_str().unwrap()]);
+    let size = fs::metadata(dir.join("fs.img")).unwrap().len();
+    assert!(size > 20 << 20 && size < 34 << 20, "{} bytes", size);
+    let unpacked = dir.join("unpacked");
+    fuse(&["unpack", dir.join("fs.img").to_str().unwrap(), unpacked.to_str().unwrap()]);
+    assert_eq!(contents(&unpacked), contents(&source));
+}
+
+#[test]
+fn explicit_geometry_is_used() {
+    let dir = scratch("explicit_geometry_is_used");
+    let image_dir = format!("{}/", dir.display());
+    fuse(&["-t", &image_dir, "-b", "1024", "--size", "8M", "--inodes", "20000"]);
+    let image = fs::read(dir.join("fs.img")).unwrap();
+    assert_eq!(image.len(), 8 << 20);
+    let field = |i: usize| u32::from_le_bytes(image[i * 4..i * 4 + 4].try_into().unwrap());
+    // total_blocks, inode_bitmap_blocks, then block_size
+    assert_eq!(field(1), 8 * 1024);
+    assert_eq!(field(2), 3);
+    assert_eq!(field(6), 1024);
+}
+
+#[test]
+fn resize_keeps_the_tree() {
+    let dir = scratch("resize_keeps_the_tree");
+    let source = dir.join("source");
+    host_tree(&source);
+    let image_dir = format!("{}/", dir.display());
+    let image = dir.join("fs.img");
+    fuse(&["-t", &image_dir, "--auto-size", "-d", source.to_str().unwrap()]);
+    let output = fuse(&["resize", image.to_str().unwrap(), "--size", "8M", "--inodes", "10000"]);
+    assert!(output.contains("16384 blocks, 12288 inodes, 5 files read back"), "{}", output);
+    assert_eq!(fs::metadata(&image).unwrap().len(), 8 << 20);
+    let unpacked = dir.join("unpacked");
+    fuse(&["unpack", image.to_str().unwrap(), unpacked.to_str().unwrap()]);
+    assert_eq!(contents(&unpacked), contents(&source));
+}
+
+#[test]
+fn defrag_joins_the_runs_of_a_file() {
+    let dir = scratch("defrag_joins_the_runs_of_a_file");
+    let source = dir.join("source");
+    host_tree(&source);
+    let image_dir = format!("{}/", dir.display());
+    let image = dir.join("fs.img");
+    fuse(&["-t", &image_dir, "--size", "1M", "-d", source.to_str().unwrap()]);
+    // leave a hole between two files, then write a file larger than the hole
+    let small = dir.join("small");
+    let large = dir.join("large");
+    fs::write(&small, vec![1u8; 4 * 512]).unwrap();
+    fs::write(&large, (0..40 * 512).map(|i| (i % 253) as u8).collect::<Vec<u8>>()).unwrap();
+    let mut shell = Command::new(env!("CARGO_BIN_EXE_easy-fs-fuse"))
+        .args(["shell", image.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let script = format!(
+        "put {small} a\nput {small} b\nput {small} c\nrm b\nput {large} d\n",
+        small = small.display(),
+        large = large.display(),
+    );
+    shell.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    assert!(shell.wait().unwrap().success());
+    let output = fuse(&["defrag", image.to_str().unwrap()]);
+    let before = output.lines().find(|line| line.starts_with("before:")).unwrap();
+    let after = output.lines().find(|line| line.starts_with("after:")).unwrap();
+    assert!(!before.starts_with("before: 0 of"), "{}", output);
+    assert!(after.starts_with("after: 0 of"), "{}", output);
+    let unpacked = dir.join("unpacked");
+    fuse(&["unpack", image.to_str().unwrap(), unpacked.to_str().unwrap()]);
+    assert_eq!(fs::read(unpacked.join("d")).unwrap(), fs::read(&large).unwrap());
+}
+
+#[test]
+fn fat32_image_takes_long_names() {
+    let dir = scratch("fat32_image_takes_long_names");
+    let image = dir.join("fat.img");
+    let output = fuse(&["mkfat", image.to_str().unwrap(), "--size", "40M"]);
+    assert!(output.ends_with("clusters of 512 bytes\n"), "{}", output);
+    // a name easy-fs could not hold
+    let long_name = "a-file-name-far-longer-than-an-easy-fs-entry.txt";
+    let data: Vec<u8> = (0..70_000u32).map(|i| (i * 13) as u8).collect();
+    let source = dir.join("source");
+    fs::create_dir_all(source.join("Docs/Deep")).unwrap();
+    let host_file = source.join("Docs/Deep").join(long_name);
+    fs::write(&host_file, &data).unwrap();
+    let mut shell = Command::new(env!("CARGO_BIN_EXE_easy-fs-fuse"))
+        .args(["shell", image.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let script = format!(
+        "mkdir Docs\nmkdir Docs/Deep\nput {host} Docs/Deep/{long}\nput {host} gone\nrm gone\n",
+        host = host_file.display(),
+        long = long_name,
+    );
+    shell.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    assert!(shell.wait().unwrap().success());
+    let unpacked = dir.join("unpacked");
+    fuse(&["unpack", image.to_str().unwrap(), unpacked.to_str().unwrap()]);
+    assert_eq!(contents(&unpacked), contents(&source));
+}
+
+/// run a shell script on `image`, return whether it succeeded and its errors
+fn shell_script(image: &Path, script: &str) -> (bool, String) {
+    let mut shell = Command::new(env!("CARGO_BIN_EXE_easy-fs-fuse"))
+        .args(["shell", image.to_str().unwrap()])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    shell.stdin.take().unwrap().write_all(script.as_bytes()).unwrap();
+    let output = shell.wait_with_output().unwrap();
+    (output.status.success(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn shell_tells_why_a_change_failed() {
+    let dir = scratch("shell_tells_why_a_change_failed");
+    let image_dir = format!("{}/", dir.display());
+    let image = dir.join("fs.img");
+    fuse(&["-t", &image_dir, "--size", "1M"]);
+    let small = dir.join("small");
+    let large = dir.join("large");
+    fs::write(&small, b"small").unwrap();
+    fs::write(&large, vec![7u8; 2 << 20]).unwrap();
+    let (ok, errors) = shell_script(&image, &format!("mkdir d\nput {} d/f\nrm d\n", small.display()));
+    assert!(!ok);
+    assert!(errors.contains("d: directory not empty"), "{}", errors);
+    let (ok, errors) = shell_script(&image, &format!("put {} big\n", large.display()));
+    assert!(!ok);
+    assert!(errors.contains("bytes fit in the image"), "{}", errors);
+    let (ok, errors) = shell_script(&image, "rm d/f\nrm d\nrm big\n");
+    assert!(ok, "{}", errors);
+}
+
+#[test]
+fn import_fails_when_the_image_is_full() {
+    let dir = scratch("import_fails_when_the_image_is_full");
+    let source = dir.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("big"), vec![7u8; 2 << 20]).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_easy-fs-fuse"))
+        .args(["-t", &format!("{}/", dir.display()), "--size", "1M", "-d", source.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let errors = String::from_utf8_lossy(&output.stderr);
+    assert!(errors.contains("big: does not fit in the image"), "{}", errors);
+}
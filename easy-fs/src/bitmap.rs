@@ -3,53 +3,57 @@
   Bitmap layout:
                 /  block_0  /  block_1  /  block_2  /
                 /u64u64../
-              => [u64; 64]
+              => [u64; block_size / 8]
 */
 use super::{
     get_block_cache,
     BlockDevice,
-    BLOCK_SZ,
 };
 
 use alloc::sync::Arc;
 
-/// bimap_block
-type BitmapBlock = [u64; 64];
-/// number of bits in a block
-const BLOCK_BITS: usize = BLOCK_SZ * 8;
-
 /// struct Bitmap => it may contains many blocks
 /// Bitmap is used to allocate or reclaim blocks
 pub struct Bitmap {
     start_block_id: usize,
     //number of blocks
     blocks: usize,
+    block_size: usize,
 }
 
 /// decompose bitmap_area_inner_bit into (block_pos, bits64_pos, inner_pos)
-fn decomposition(mut bit: usize) -> (usize, usize, usize) {
-    let block_pos = bit / BLOCK_BITS;
-    let bits64_pos = (bit % BLOCK_BITS) / 64;
-    let inner_pos = (bit % BLOCK_BITS) % 64;
+fn decomposition(bit: usize, block_bits: usize) -> (usize, usize, usize) {
+    let block_pos = bit / block_bits;
+    let bits64_pos = (bit % block_bits) / 64;
+    let inner_pos = (bit % block_bits) % 64;
     (block_pos, bits64_pos, inner_pos)
 }
 
 impl Bitmap {
-    pub fn new(start_block_id: usize, blocks: usize) -> Self {
+    /// create a bitmap of `blocks` blocks starting at `start_block_id`
+    pub fn new(start_block_id: usize, blocks: usize, block_size: usize) -> Self {
         Self {
             start_block_id,
             blocks,
+            block_size,
         }
     }
- 
-    /// allocate a new {inode/data}_block from block device 
+
+    /// number of bits in a block
+    fn block_bits(&self) -> usize {
+        self.block_size * 8
+    }
+
+    /// allocate a new {inode/data}_block from block device
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
+        let block_bits = self.block_bits();
         for block_id in 0..self.blocks {
             let pos = get_block_cache(
-                block_id + self.start_block_id as usize,
+                block_id + self.start_block_id,
+                self.block_size,
                 Arc::clone(block_device),
             ).lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
+            .modify_slice(|bitmap_block: &mut [u64]| {
                 if let Some((bits64_pos, inner_pos)) = bitmap_block
                     .iter()
                     .enumerate()
@@ -60,7 +64,7 @@ impl Bitmap {
                     // modify cache
                     bitmap_block[bits64_pos] |= 1u64 << inner_pos;
                     // returns the offset bit relative to the entire bitmap
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize)
+                    Some(block_id * block_bits + bits64_pos * 64 + inner_pos)
                 } else {
                     None
                 }
@@ -72,20 +76,44 @@ impl Bitmap {
         None
     }
 
+    /// allocate the given `bit`, return false if it is taken already
+    pub fn alloc_at(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit, self.block_bits());
+        get_block_cache(block_pos + self.start_block_id, self.block_size, Arc::clone(block_device))
+            .lock()
+            .modify_slice(|bitmap_block: &mut [u64]| {
+                if bitmap_block[bits64_pos] & (1u64 << inner_pos) != 0 {
+                    return false;
+                }
+                bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                true
+            })
+    }
+
     /// deallocate a {inode/data}_block
     /// the passed parameter `bit` is the offset relative to the entire bitmap
     pub fn dealloc(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
-        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
-        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit, self.block_bits());
+        get_block_cache(block_pos + self.start_block_id, self.block_size, Arc::clone(block_device))
             .lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
+            .modify_slice(|bitmap_block: &mut [u64]| {
                 assert!(bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0);
                 bitmap_block[bits64_pos] -= 1u64 << inner_pos;
             });
     }
 
+    /// whether `bit` is allocated
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit, self.block_bits());
+        get_block_cache(block_pos + self.start_block_id, self.block_size, Arc::clone(block_device))
+            .lock()
+            .read_slice(|bitmap_block: &[u64]| {
+                bitmap_block[bits64_pos] & (1u64 << inner_pos) != 0
+            })
+    }
+
     /// get max number of allocatable blocks
     pub fn maximum(&self) -> usize {
-        self.blocks * BLOCK_BITS
+        self.blocks * self.block_bits()
     }
 }
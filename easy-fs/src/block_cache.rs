@@ -1,12 +1,16 @@
 //!BlockCache => Memory
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use lazy_static::*;
 use spin::Mutex;
-use super::{BlockDevice, BLOCK_SZ};
+use super::BlockDevice;
 
+/// A block cached in memory
 pub struct BlockCache {
-    cache: [u8; BLOCK_SZ],
+    /// backed by u64 so that every on-disk struct inside is aligned
+    cache: Vec<u64>,
     block_id: usize,
     modified: bool,
     block_device: Arc<dyn BlockDevice>,
@@ -14,66 +18,112 @@ pub struct BlockCache {
 
 impl BlockCache {
     /// Load a new BlockCache form disk
-    pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>)
+    pub fn new(block_id: usize, block_size: usize, block_device: Arc<dyn BlockDevice>)
         -> Self
     {
-        let mut cache: [u8; BLOCK_SZ] = [0u8; BLOCK_SZ];
-        block_device.read_lock(block_id, &mut cache);
-        Self {
-            cache,
+        let mut cache = Self {
+            cache: vec![0u64; block_size / 8],
             block_id,
             modified: false,
-            block_device,
+            block_device: Arc::clone(&block_device),
+        };
+        block_device.read_block(block_id, cache.bytes_mut());
+        cache
+    }
+
+    /// Size of the cached block in bytes
+    pub fn block_size(&self) -> usize {
+        self.cache.len() * 8
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self.cache.as_ptr() as *const u8, self.block_size())
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(self.cache.as_mut_ptr() as *mut u8, self.block_size())
         }
     }
 
     /// Get the address of an offset inside the cache block data
     fn addr_of_offset(&self, offset: usize) -> usize {
-        &self.cache[offset] as *const _ as usize
+        &self.bytes()[offset] as *const _ as usize
     }
 
+    /// Get an immutable reference to a `T` at `offset`
     pub fn get_ref<T>(&self, offset: usize) -> &T
     where
         T: Sized,
     {
         let type_size: usize = core::mem::size_of::<T>();
-        assert!(offset + type_size <= BLOCK_SZ);
+        assert!(offset + type_size <= self.block_size());
         let addr: usize = self.addr_of_offset(offset);
         unsafe {
             &*(addr as *const T)
         }
     }
-    
+
+    /// Get a mutable reference to a `T` at `offset`, the block becomes dirty
     pub fn get_mut<T>(&mut self, offset: usize) -> &mut T
     where
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        assert!(offset + type_size <= BLOCK_SZ);
+        assert!(offset + type_size <= self.block_size());
+        self.modified = true;
         let addr: usize = self.addr_of_offset(offset);
         unsafe {
             &mut *(addr as *mut T)
         }
     }
 
+    /// Read a `T` at `offset` through the closure
     pub fn read<T, V>(&self, offset: usize,
                       f: impl FnOnce(&T) -> V) -> V
     {
         f(self.get_ref(offset))
     }
 
+    /// Modify a `T` at `offset` through the closure
     pub fn modify<T, V>(&mut self, offset: usize,
                         f: impl FnOnce(&mut T) -> V) -> V
     {
         f(self.get_mut(offset))
     }
 
+    /// Read the whole block as a slice of `T`
+    /**
+        On-disk arrays whose length depends on the block size
+        (indirect blocks, bitmap blocks, data blocks) are accessed this way.
+    */
+    pub fn read_slice<T, V>(&self, f: impl FnOnce(&[T]) -> V) -> V {
+        let len = self.block_size() / core::mem::size_of::<T>();
+        let slice = unsafe {
+            core::slice::from_raw_parts(self.cache.as_ptr() as *const T, len)
+        };
+        f(slice)
+    }
+
+    /// Modify the whole block as a slice of `T`, the block becomes dirty
+    pub fn modify_slice<T, V>(&mut self, f: impl FnOnce(&mut [T]) -> V) -> V {
+        let len = self.block_size() / core::mem::size_of::<T>();
+        self.modified = true;
+        let slice = unsafe {
+            core::slice::from_raw_parts_mut(self.cache.as_mut_ptr() as *mut T, len)
+        };
+        f(slice)
+    }
+
+    /// Write the block back to disk if it has been modified
     pub fn sync(&mut self) {
         if self.modified {
             self.modified = false;
             self.block_device.write_block(
-                self.block_id, 
-                &self.cache
+                self.block_id,
+                self.bytes()
             );
         }
     }
@@ -87,42 +137,67 @@ impl Drop for BlockCache {
 }
 
 const BLOCK_CACHE_SIZE: usize = 16;
+/// Manager of all cached blocks, evicts unused blocks when full
+/**
+    Blocks are keyed by (device, block_id) so that several devices,
+    e.g. the RamDisks of the host tests, can be cached at the same time.
+*/
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    queue: VecDeque<(usize, usize, Arc<Mutex<BlockCache>>)>,
+}
+
+/// identify a device by the address of its data
+fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    Arc::as_ptr(block_device) as *const () as usize
+}
+
+impl Default for BlockCacheManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl BlockCacheManager {
+    /// Create an empty manager
     pub fn new() -> Self {
-        queue: VecDeque::new(),
+        Self {
+            queue: VecDeque::new(),
+        }
     }
 
+    /// Get the cache of `block_id`, loading it from `block_device` if needed
     pub fn get_block_cache(
         &mut self,
         block_id: usize,
+        block_size: usize,
         block_device: Arc<dyn BlockDevice>
     ) -> Arc<Mutex<BlockCache>> {
+        let device_id = device_id(&block_device);
         if let Some(pair) = self.queue.iter()
-            .find(|pair| pair.0 == block_id)
+            .find(|pair| pair.0 == device_id && pair.1 == block_id)
         {
-            Arc::clone(&pair.1)
+            Arc::clone(&pair.2)
         } else {
             // the maximum number of cache blocks is exceeded.
-            if self.queue.len() == BLOCK_CACHE_SIZE {
+            // when every cached block is in use, e.g. by many threads at
+            // once, the queue grows and shrinks back on later misses
+            while self.queue.len() >= BLOCK_CACHE_SIZE {
                 if let Some((idx, _)) = self
                     .queue.iter().enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
+                    .find(|(_, pair)| Arc::strong_count(&pair.2) == 1)
                 {
                     self.queue.drain(idx..=idx);
                 } else {
-                    panic!("Run out of BlockCache!");
+                    break;
                 }
             }
-            
+
             let block_cache = Arc::new(Mutex::new(BlockCache::new(
                 block_id,
+                block_size,
                 Arc::clone(&block_device),
             )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
+            self.queue.push_back((device_id, block_id, Arc::clone(&block_cache)));
             block_cache
         }
     }
@@ -134,19 +209,33 @@ lazy_static! {
         Mutex::new(BlockCacheManager::new());
 }
 
+/// Get the cache of `block_id` from the global manager
 pub fn get_block_cache(
     block_id: usize,
+    block_size: usize,
     block_device: Arc<dyn BlockDevice>,
 ) -> Arc<Mutex<BlockCache>> {
-    BLOCK_CACHE_MANAGER
+    let cache = BLOCK_CACHE_MANAGER
         .lock()
-        .get_block_cache(block_id, block_device)
+        .get_block_cache(block_id, block_size, block_device);
+    assert_eq!(cache.lock().block_size(), block_size,
+        "block {} is cached with another block size", block_id);
+    cache
 }
 
 /// Sync all block_cache to block_device
+/**
+    The manager is not locked while the blocks are, so that a thread holding
+    a block and asking for another one cannot deadlock with the sync.
+*/
 pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
+    let caches: Vec<Arc<Mutex<BlockCache>>> = BLOCK_CACHE_MANAGER
+        .lock()
+        .queue
+        .iter()
+        .map(|(_, _, cache)| Arc::clone(cache))
+        .collect();
+    for cache in caches {
         cache.lock().sync();
     }
 }
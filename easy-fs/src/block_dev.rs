@@ -1,5 +1,13 @@
+//!BlockDevice => the interface between easy-fs and the real storage
 use core::any::Any;
 
+/// A block device addressed in units of `buf.len()` bytes
+/**
+    The block size of easy-fs is chosen when the image is created,
+    so a device does not know it in advance: block `block_id` lives at
+    byte offset `block_id * buf.len()`, and `buf.len()` is always one of
+    the supported block sizes.
+*/
 pub trait BlockDevice: Send + Sync + Any {
     ///Read data from block to buffer
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
@@ -0,0 +1,282 @@
+//!Compress => chunked LZ4-style compression of file contents
+/*!
+  Stored layout of a compressed file:
+        [CompressedHeader][chunk_end; chunks][chunk_0][chunk_1]...
+  The file is cut into chunks of `chunk_size` bytes which are compressed
+  independently, so `read_at` only decompresses the chunks it touches.
+  `chunk_end[i]` is the end of chunk i relative to the first chunk;
+  a chunk that does not shrink is stored as is, which is detected by
+  its stored length being equal to its raw length.
+*/
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Number of raw bytes in a chunk
+pub const CHUNK_SZ: usize = 4096;
+
+/// header at the beginning of a compressed file
+#[repr(C)]
+struct CompressedHeader {
+    raw_size: u32,
+    chunk_size: u32,
+}
+
+const HEADER_SZ: usize = core::mem::size_of::<CompressedHeader>();
+
+/* LZ4 block format */
+const MIN_MATCH: usize = 4;
+/// the last 5 bytes are always literals
+const LAST_LITERALS: usize = 5;
+/// a match never starts in the last 12 bytes
+const MFLIMIT: usize = 12;
+const MAX_DISTANCE: usize = 65535;
+const HASH_LOG: usize = 12;
+
+fn read_u32(src: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([src[pos], src[pos + 1], src[pos + 2], src[pos + 3]])
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+/// write a length in the LZ4 way: 255 ... 255 remainder
+fn push_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+/// emit literals followed by a match, or only literals if `match_len` is 0
+fn push_sequence(out: &mut Vec<u8>, literals: &[u8], offset: u16, match_len: usize) {
+    let lit_len = literals.len();
+    let match_code = if match_len == 0 { 0 } else { match_len - MIN_MATCH };
+    let token = ((lit_len.min(15) as u8) << 4) | match_code.min(15) as u8;
+    out.push(token);
+    if lit_len >= 15 {
+        push_length(out, lit_len - 15);
+    }
+    out.extend_from_slice(literals);
+    if match_len == 0 {
+        return;
+    }
+    out.extend_from_slice(&offset.to_le_bytes());
+    if match_code >= 15 {
+        push_length(out, match_code - 15);
+    }
+}
+
+/// Compress `src` into an LZ4 block
+pub fn compress(src: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(src.len() + src.len() / 255 + 16);
+    // position + 1 of the last 4 bytes with the same hash, 0 means none
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+    if src.len() > MFLIMIT {
+        let limit = src.len() - MFLIMIT;
+        let match_limit = src.len() - LAST_LITERALS;
+        while pos < limit {
+            let seq = read_u32(src, pos);
+            let h = hash(seq);
+            let candidate = table[h];
+            table[h] = pos + 1;
+            if candidate != 0 {
+                let candidate = candidate - 1;
+                if pos - candidate <= MAX_DISTANCE && read_u32(src, candidate) == seq {
+                    let mut len = MIN_MATCH;
+                    while pos + len < match_limit && src[candidate + len] == src[pos + len] {
+                        len += 1;
+                    }
+                    push_sequence(&mut out, &src[anchor..pos], (pos - candidate) as u16, len);
+                    pos += len;
+                    anchor = pos;
+                    continue;
+                }
+            }
+            pos += 1;
+        }
+    }
+    push_sequence(&mut out, &src[anchor..], 0, 0);
+    out
+}
+
+/// read a length in the LZ4 way, starting from the 4 bits in the token
+fn read_length(src: &[u8], pos: &mut usize, mut len: usize) -> Option<usize> {
+    if len == 15 {
+        loop {
+            let byte = *src.get(*pos)?;
+            *pos += 1;
+            len += byte as usize;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+/// Decompress an LZ4 block into `dst`, return the number of bytes produced
+/// or `None` if the block is corrupted
+pub fn decompress(src: &[u8], dst: &mut [u8]) -> Option<usize> {
+    let mut s = 0;
+    let mut d = 0;
+    loop {
+        let token = *src.get(s)?;
+        s += 1;
+        // literals
+        let lit_len = read_length(src, &mut s, (token >> 4) as usize)?;
+        if s + lit_len > src.len() || d + lit_len > dst.len() {
+            return None;
+        }
+        dst[d..d + lit_len].copy_from_slice(&src[s..s + lit_len]);
+        s += lit_len;
+        d += lit_len;
+        // the last sequence has no match
+        if s == src.len() {
+            return Some(d);
+        }
+        // match
+        if s + 2 > src.len() {
+            return None;
+        }
+        let offset = u16::from_le_bytes([src[s], src[s + 1]]) as usize;
+        s += 2;
+        let match_len = read_length(src, &mut s, (token & 0xf) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > d || d + match_len > dst.len() {
+            return None;
+        }
+        // byte by byte, the match may overlap what it produces
+        for i in d..d + match_len {
+            dst[i] = dst[i - offset];
+        }
+        d += match_len;
+    }
+}
+
+/// Build the stored form of a compressed file holding `data`
+pub fn pack(data: &[u8]) -> Vec<u8> {
+    let chunks = data.len().div_ceil(CHUNK_SZ);
+    let mut ends: Vec<u32> = Vec::with_capacity(chunks);
+    let mut body: Vec<u8> = Vec::new();
+    for chunk in data.chunks(CHUNK_SZ) {
+        let compressed = compress(chunk);
+        if compressed.len() < chunk.len() {
+            body.extend_from_slice(&compressed);
+        } else {
+            body.extend_from_slice(chunk);
+        }
+        ends.push(body.len() as u32);
+    }
+    let mut stored = Vec::with_capacity(HEADER_SZ + chunks * 4 + body.len());
+    stored.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    stored.extend_from_slice(&(CHUNK_SZ as u32).to_le_bytes());
+    for end in ends {
+        stored.extend_from_slice(&end.to_le_bytes());
+    }
+    stored.extend_from_slice(&body);
+    stored
+}
+
+/// Read the u32 at `pos` of the stored file, None past its end
+fn stored_u32(read_raw: &dyn Fn(usize, &mut [u8]) -> usize, pos: usize) -> Option<u32> {
+    let mut bytes = [0u8; 4];
+    (read_raw(pos, &mut bytes) == 4).then(|| u32::from_le_bytes(bytes))
+}
+
+/// Read and check the header of a compressed file
+/**
+    None if the stored bytes cannot be a compressed file: chunks hold
+    from 1 to `CHUNK_SZ` bytes, and the end of every chunk is stored.
+*/
+fn read_header(read_raw: &dyn Fn(usize, &mut [u8]) -> usize) -> Option<CompressedHeader> {
+    let header = CompressedHeader {
+        raw_size: stored_u32(read_raw, 0)?,
+        chunk_size: stored_u32(read_raw, 4)?,
+    };
+    let chunk_size = header.chunk_size as usize;
+    if chunk_size == 0 || chunk_size > CHUNK_SZ {
+        return None;
+    }
+    let chunks = (header.raw_size as usize).div_ceil(chunk_size);
+    if chunks > 0 {
+        stored_u32(read_raw, HEADER_SZ + (chunks - 1) * 4)?;
+    }
+    Some(header)
+}
+
+/// Where chunk `chunk_id` is stored, relative to the first chunk
+fn chunk_range(read_raw: &dyn Fn(usize, &mut [u8]) -> usize, chunk_id: usize) -> Option<(usize, usize)> {
+    let start = match chunk_id {
+        0 => 0,
+        _ => stored_u32(read_raw, HEADER_SZ + (chunk_id - 1) * 4)?,
+    };
+    let end = stored_u32(read_raw, HEADER_SZ + chunk_id * 4)?;
+    (start <= end).then_some((start as usize, end as usize))
+}
+
+/// Size of the data held by a compressed file, None if it is corrupted
+/**
+    `read_raw(pos, buf)` reads the stored bytes of the file.
+*/
+pub fn raw_size(read_raw: &dyn Fn(usize, &mut [u8]) -> usize) -> Option<usize> {
+    read_header(read_raw).map(|header| header.raw_size as usize)
+}
+
+/// Read the data of a compressed file at `offset`, like `DiskInode::read_at`
+/**
+    The read stops short at the first chunk that is corrupted,
+    and reads nothing if the header is.
+*/
+pub fn read_at(
+    offset: usize,
+    buf: &mut [u8],
+    read_raw: &dyn Fn(usize, &mut [u8]) -> usize,
+) -> usize {
+    let Some(header) = read_header(read_raw) else {
+        return 0;
+    };
+    let raw_size = header.raw_size as usize;
+    let chunk_size = header.chunk_size as usize;
+    let end = (offset + buf.len()).min(raw_size);
+    if offset >= end {
+        return 0;
+    }
+    let chunks = raw_size.div_ceil(chunk_size);
+    let body_start = HEADER_SZ + chunks * 4;
+    let mut chunk_buf = vec![0u8; chunk_size];
+    let mut stored_buf: Vec<u8> = Vec::new();
+    let mut start = offset;
+    let mut read_size = 0;
+    while start < end {
+        let chunk_id = start / chunk_size;
+        let chunk_raw_len = chunk_size.min(raw_size - chunk_id * chunk_size);
+        // a chunk that does not shrink is stored as is, never longer
+        let Some((chunk_start, chunk_end)) = chunk_range(read_raw, chunk_id)
+            .filter(|(chunk_start, chunk_end)| chunk_end - chunk_start <= chunk_raw_len)
+        else {
+            break;
+        };
+        // load and decompress the chunk
+        stored_buf.resize(chunk_end - chunk_start, 0);
+        if read_raw(body_start + chunk_start, &mut stored_buf) < stored_buf.len() {
+            break;
+        }
+        let raw = &mut chunk_buf[..chunk_raw_len];
+        if stored_buf.len() == chunk_raw_len {
+            raw.copy_from_slice(&stored_buf);
+        } else if decompress(&stored_buf, raw) != Some(chunk_raw_len) {
+            break;
+        }
+        // copy the wanted part
+        let inner_start = start % chunk_size;
+        let inner_end = (end - chunk_id * chunk_size).min(chunk_raw_len);
+        let len = inner_end - inner_start;
+        buf[read_size..read_size + len].copy_from_slice(&raw[inner_start..inner_end]);
+        read_size += len;
+        start += len;
+    }
+    read_size
+}
@@ -0,0 +1,103 @@
+//!Crypt => at-rest encryption of file contents
+/*!
+  File data is encrypted with ChaCha20 (RFC 8439) under a per-filesystem key.
+  Every data block of a file has its own key stream, selected by the nonce
+  [inode_id, inner block index, 0], so blocks can be read and written at
+  random. Metadata (super block, bitmaps, inodes, index blocks, directories)
+  stays in plain text.
+
+  The nonce of a block does not change when the block is rewritten: this
+  protects an image that is lost or shipped, but someone who sees two
+  versions of the same block learns the XOR of their contents.
+*/
+
+/// A 256-bit filesystem key
+pub type Key = [u8; 32];
+
+/// nonce word used for the key check value, no real block has it
+const KEY_CHECK_NONCE: u32 = u32::MAX;
+
+/// Size of a ChaCha20 block
+const CHACHA_BLOCK_SZ: usize = 64;
+
+/// ChaCha20 with a fixed key
+#[derive(Clone)]
+pub struct Cipher {
+    key: [u32; 8],
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+impl Cipher {
+    /// Create a cipher from a key
+    pub fn new(key: &Key) -> Self {
+        let mut words = [0u32; 8];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = u32::from_le_bytes([key[4 * i], key[4 * i + 1], key[4 * i + 2], key[4 * i + 3]]);
+        }
+        Self { key: words }
+    }
+
+    /// One ChaCha20 key stream block
+    pub fn block(&self, counter: u32, nonce: [u32; 3]) -> [u8; CHACHA_BLOCK_SZ] {
+        let mut init = [0u32; 16];
+        // "expand 32-byte k"
+        init[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+        init[4..12].copy_from_slice(&self.key);
+        init[12] = counter;
+        init[13..].copy_from_slice(&nonce);
+        let mut state = init;
+        for _ in 0..10 {
+            // column rounds
+            quarter_round(&mut state, 0, 4, 8, 12);
+            quarter_round(&mut state, 1, 5, 9, 13);
+            quarter_round(&mut state, 2, 6, 10, 14);
+            quarter_round(&mut state, 3, 7, 11, 15);
+            // diagonal rounds
+            quarter_round(&mut state, 0, 5, 10, 15);
+            quarter_round(&mut state, 1, 6, 11, 12);
+            quarter_round(&mut state, 2, 7, 8, 13);
+            quarter_round(&mut state, 3, 4, 9, 14);
+        }
+        let mut out = [0u8; CHACHA_BLOCK_SZ];
+        for i in 0..16 {
+            let word = state[i].wrapping_add(init[i]);
+            out[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        out
+    }
+
+    /// Encrypt or decrypt in place `data`, which sits at `offset`
+    /// inside data block `block_index` of inode `inode_id`
+    pub fn apply(&self, inode_id: u32, block_index: u32, offset: usize, data: &mut [u8]) {
+        let nonce = [inode_id, block_index, 0];
+        let mut pos = offset;
+        let mut done = 0;
+        while done < data.len() {
+            let stream = self.block((pos / CHACHA_BLOCK_SZ) as u32, nonce);
+            let inner = pos % CHACHA_BLOCK_SZ;
+            let len = (CHACHA_BLOCK_SZ - inner).min(data.len() - done);
+            for (byte, key) in data[done..done + len].iter_mut().zip(&stream[inner..inner + len]) {
+                *byte ^= key;
+            }
+            done += len;
+            pos += len;
+        }
+    }
+
+    /// A value recorded in the super block to tell a wrong key at open time
+    pub fn key_check(&self) -> [u8; 16] {
+        let mut check = [0u8; 16];
+        check.copy_from_slice(&self.block(0, [KEY_CHECK_NONCE; 3])[..16]);
+        check
+    }
+}
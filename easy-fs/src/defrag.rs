@@ -0,0 +1,238 @@
+//! Compacting file data into contiguous runs
+/*!
+  Defragmenting is done offline, with no handle open on the filesystem
+  but the directories pinned by the inode cache, which are let go first.
+  Inodes are laid out one after the other from the start of the data
+  area, in inode order: the data blocks of an inode in file order, then
+  its index blocks, so that reading a file goes through one run of blocks.
+  A block in the way is first moved to the last free block, which is why
+  a single free block is enough.
+*/
+use super::{block_cache_sync_all, get_block_cache, DiskInode, EasyFileSystem, BLOCK_UNWRITTEN};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+
+/// How scattered the data of a filesystem is
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fragmentation {
+    /// inodes holding data
+    pub inodes: usize,
+    /// inodes whose data is split in more than one run
+    pub fragmented: usize,
+    /// runs of consecutive data blocks, over all inodes
+    pub extents: usize,
+    /// data blocks, over all inodes
+    pub data_blocks: usize,
+    /// runs of consecutive free blocks in the data area
+    pub free_extents: usize,
+}
+
+impl Display for Fragmentation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{} of {} inodes fragmented, {} extents for {} data blocks, free space in {} extents",
+            self.fragmented, self.inodes, self.extents, self.data_blocks, self.free_extents)
+    }
+}
+
+/// what points at a block
+#[derive(Clone, Copy)]
+enum Owner {
+    /// data block `inner_id` of an inode
+    Data(u32, u32),
+    /// the indirect1 block of an inode
+    Indirect1(u32),
+    /// the indirect2 block of an inode
+    Indirect2(u32),
+    /// the indirect1 block at some index of the indirect2 block of an inode
+    Indirect2Entry(u32, usize),
+}
+
+impl EasyFileSystem {
+    fn allocated_inodes(&self) -> Vec<u32> {
+        let inode_bitmap = self.inode_bitmap.lock();
+        (0..inode_bitmap.maximum())
+            .filter(|inode_id| inode_bitmap.is_allocated(&self.block_device, *inode_id))
+            .map(|inode_id| inode_id as u32)
+            .collect()
+    }
+
+    /// every block of an inode, in the order a defragmented inode has them
+    fn owners(&self, inode_id: u32, disk_inode: &DiskInode) -> Vec<Owner> {
+        let geo = &self.geometry;
+        let data_blocks = disk_inode.data_blocks(geo) as usize;
+        let mut owners: Vec<Owner> = (0..data_blocks as u32)
+            .map(|inner_id| Owner::Data(inode_id, inner_id))
+            .collect();
+        if data_blocks > disk_inode.direct.len() {
+            owners.push(Owner::Indirect1(inode_id));
+        }
+        if data_blocks > geo.indirect1_bound {
+            owners.push(Owner::Indirect2(inode_id));
+            let count = (data_blocks - geo.indirect1_bound).div_ceil(geo.indirect1_count);
+            owners.extend((0..count).map(|index| Owner::Indirect2Entry(inode_id, index)));
+        }
+        owners
+    }
+
+    /// the block an owner points at
+    fn block_of(&self, owner: Owner) -> u32 {
+        match owner {
+            Owner::Data(inode_id, inner_id) => self
+                .read_disk_inode(inode_id)
+                .get_block_id(inner_id, &self.geometry, &self.block_device),
+            Owner::Indirect1(inode_id) => self.read_disk_inode(inode_id).indirect1,
+            Owner::Indirect2(inode_id) => self.read_disk_inode(inode_id).indirect2,
+            Owner::Indirect2Entry(inode_id, index) => {
+                let indirect2 = self.read_disk_inode(inode_id).indirect2;
+                get_block_cache(indirect2 as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+                    .lock()
+                    .read_slice(|indirect2: &[u32]| indirect2[index])
+            }
+        }
+    }
+
+    /// make an owner point at `block_id`
+    fn point(&self, owner: Owner, block_id: u32) {
+        match owner {
+            Owner::Data(inode_id, inner_id) => {
+                let mut disk_inode = self.read_disk_inode(inode_id);
+                let entry = disk_inode.get_block_entry(inner_id, &self.geometry, &self.block_device);
+                disk_inode.set_block_entry(
+                    inner_id,
+                    (entry & BLOCK_UNWRITTEN) | block_id,
+                    &self.geometry,
+                    &self.block_device,
+                );
+                self.write_disk_inode(inode_id, &disk_inode);
+            }
+            Owner::Indirect1(inode_id) => {
+                let mut disk_inode = self.read_disk_inode(inode_id);
+                disk_inode.indirect1 = block_id;
+                self.write_disk_inode(inode_id, &disk_inode);
+            }
+            Owner::Indirect2(inode_id) => {
+                let mut disk_inode = self.read_disk_inode(inode_id);
+                disk_inode.indirect2 = block_id;
+                self.write_disk_inode(inode_id, &disk_inode);
+            }
+            Owner::Indirect2Entry(inode_id, index) => {
+                let indirect2 = self.read_disk_inode(inode_id).indirect2;
+                get_block_cache(indirect2 as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+                    .lock()
+                    .modify_slice(|indirect2: &mut [u32]| indirect2[index] = block_id);
+            }
+        }
+    }
+
+    /// move the block of `owner` to the free block `to`
+    fn move_block(&self, owner: Owner, from: u32, to: u32) {
+        let block_size = self.geometry.block_size;
+        assert!(self
+            .data_bitmap
+            .lock()
+            .alloc_at(&self.block_device, (to - self.data_area_start_block) as usize));
+        let data = get_block_cache(from as usize, block_size, Arc::clone(&self.block_device))
+            .lock()
+            .read_slice(|data: &[u8]| data.to_vec());
+        get_block_cache(to as usize, block_size, Arc::clone(&self.block_device))
+            .lock()
+            .modify_slice(|block: &mut [u8]| block.copy_from_slice(&data));
+        self.point(owner, to);
+        self.dealloc_data(from);
+    }
+
+    /// Measure how scattered file data is
+    pub fn fragmentation(&self) -> Fragmentation {
+        self.sync_inodes();
+        let geo = &self.geometry;
+        let mut fragmentation = Fragmentation::default();
+        for inode_id in self.allocated_inodes() {
+            let disk_inode = self.read_disk_inode(inode_id);
+            let data_blocks = disk_inode.data_blocks(geo);
+            if data_blocks == 0 {
+                continue;
+            }
+            let blocks: Vec<u32> = (0..data_blocks)
+                .map(|inner_id| disk_inode.get_block_id(inner_id, geo, &self.block_device))
+                .collect();
+            let extents = 1 + blocks.windows(2).filter(|pair| pair[1] != pair[0] + 1).count();
+            fragmentation.inodes += 1;
+            fragmentation.data_blocks += blocks.len();
+            fragmentation.extents += extents;
+            if extents > 1 {
+                fragmentation.fragmented += 1;
+            }
+        }
+        let data_bitmap = self.data_bitmap.lock();
+        let data_area_blocks = self.super_block().data_area_blocks as usize;
+        let mut in_free_extent = false;
+        for bit in 0..data_area_blocks {
+            let free = !data_bitmap.is_allocated(&self.block_device, bit);
+            if free && !in_free_extent {
+                fragmentation.free_extents += 1;
+            }
+            in_free_extent = free;
+        }
+        fragmentation
+    }
+
+    /// Move every inode's blocks into one run, return how many blocks moved
+    /**
+        No handle may be open on the filesystem while it is defragmented.
+    */
+    pub fn defrag(&self) -> usize {
+        assert!(!self.is_read_only(), "EFS is mounted read-only!");
+        self.inode_cache.unpin_all();
+        assert!(self.inode_cache.is_empty(), "EFS has open handles!");
+        let data_area_blocks = self.super_block().data_area_blocks;
+        let inodes: Vec<(u32, DiskInode)> = self
+            .allocated_inodes()
+            .into_iter()
+            .map(|inode_id| (inode_id, self.read_disk_inode(inode_id)))
+            .collect();
+        // who uses each block of the data area, and which are free
+        let mut used: BTreeMap<u32, Owner> = BTreeMap::new();
+        for (inode_id, disk_inode) in inodes.iter() {
+            for owner in self.owners(*inode_id, disk_inode) {
+                used.insert(self.block_of(owner), owner);
+            }
+        }
+        let mut free: BTreeSet<u32> = {
+            let data_bitmap = self.data_bitmap.lock();
+            (0..data_area_blocks)
+                .filter(|bit| !data_bitmap.is_allocated(&self.block_device, *bit as usize))
+                .map(|bit| self.data_area_start_block + bit)
+                .collect()
+        };
+        let mut moved = 0;
+        let mut next = self.data_area_start_block;
+        for (inode_id, disk_inode) in inodes.iter() {
+            for owner in self.owners(*inode_id, disk_inode) {
+                let wanted = next;
+                next += 1;
+                let current = self.block_of(owner);
+                if current == wanted {
+                    continue;
+                }
+                // make room, then move in
+                if let Some(other) = used.remove(&wanted) {
+                    let spare = free.pop_last().expect("No free block to defragment EFS!");
+                    self.move_block(other, wanted, spare);
+                    used.insert(spare, other);
+                    moved += 1;
+                } else {
+                    free.remove(&wanted);
+                }
+                self.move_block(owner, current, wanted);
+                used.remove(&current);
+                used.insert(wanted, owner);
+                free.insert(current);
+                moved += 1;
+            }
+        }
+        block_cache_sync_all();
+        moved
+    }
+}
@@ -6,8 +6,398 @@
 */
 use super::{
     block_cache_sync_all, get_block_cache,
-    Bitmap, BlockDevice, DiskInode, DiskInodeType, SuperBlock,
+    crypt::{Cipher, Key},
+    inode_cache::InodeCache,
+    Bitmap, BlockDevice, DiskInode, DiskInodeType, Geometry, Inode, SuperBlock,
+    FEATURE_INCOMPAT_BLOCK_SIZE, FEATURE_INCOMPAT_COMPRESSION, FEATURE_INCOMPAT_ENCRYPTION,
+    FEATURE_INCOMPAT_SUPPORTED, FEATURE_INCOMPAT_UNWRITTEN, FEATURE_RO_COMPAT_SUPPORTED,
+    MIN_BLOCK_SZ,
 };
-use crate::BLOCK_SZ;
 use alloc::sync::Arc;
+use alloc::vec;
 use spin::Mutex;
+
+/// An easy file system on a block device
+pub struct EasyFileSystem {
+    /// real device
+    pub block_device: Arc<dyn BlockDevice>,
+    /// inode bitmap
+    pub inode_bitmap: Mutex<Bitmap>,
+    /// data bitmap
+    pub data_bitmap: Mutex<Bitmap>,
+    /// block size and the constants derived from it
+    pub geometry: Geometry,
+    inode_area_start_block: u32,
+    pub(crate) data_area_start_block: u32,
+    /// encrypts file data, `None` if the image is not encrypted
+    cipher: Option<Cipher>,
+    /// set when the image uses features this code must not write
+    read_only: bool,
+    /// the vfs inode of every inode number that has a handle
+    pub(crate) inode_cache: InodeCache,
+}
+
+impl EasyFileSystem {
+    /// Create a filesystem of `total_blocks` blocks of `block_size` bytes on a device
+    /**
+        `block_size` must be one of [`crate::BLOCK_SIZES`]; it is recorded in
+        the super block so that `open` can find it again.
+    */
+    pub fn create(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        block_size: usize,
+    ) -> Arc<Self> {
+        Self::format(block_device, total_blocks, inode_bitmap_blocks, block_size, None)
+    }
+
+    /// Create a filesystem like `create` whose file data is encrypted by `key`
+    /**
+        The same key must be given to `open_with_key` to mount it again.
+    */
+    pub fn create_encrypted(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        block_size: usize,
+        key: &Key,
+    ) -> Arc<Self> {
+        let cipher = Cipher::new(key);
+        Self::format(block_device, total_blocks, inode_bitmap_blocks, block_size, Some(cipher))
+    }
+
+    fn format(
+        block_device: Arc<dyn BlockDevice>,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+        block_size: usize,
+        cipher: Option<Cipher>,
+    ) -> Arc<Self> {
+        let geometry = Geometry::new(block_size);
+        // calculate block size of areas & create bitmaps
+        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize, block_size);
+        let (inode_area_blocks, data_bitmap_blocks, data_area_blocks) =
+            geometry.areas(total_blocks, inode_bitmap_blocks);
+        let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
+        let data_bitmap = Bitmap::new(
+            (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
+            data_bitmap_blocks as usize,
+            block_size,
+        );
+        let efs = Self {
+            block_device: Arc::clone(&block_device),
+            inode_bitmap: Mutex::new(inode_bitmap),
+            data_bitmap: Mutex::new(data_bitmap),
+            geometry,
+            inode_area_start_block: 1 + inode_bitmap_blocks,
+            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            cipher,
+            read_only: false,
+            inode_cache: InodeCache::new(),
+        };
+        // clear all blocks
+        for i in 0..total_blocks {
+            get_block_cache(i as usize, block_size, Arc::clone(&block_device))
+                .lock()
+                .modify_slice(|data_block: &mut [u8]| {
+                    for byte in data_block.iter_mut() {
+                        *byte = 0;
+                    }
+                });
+        }
+        // initialize SuperBlock
+        get_block_cache(0, block_size, Arc::clone(&block_device)).lock().modify(
+            0,
+            |super_block: &mut SuperBlock| {
+                super_block.initialize(
+                    total_blocks,
+                    inode_bitmap_blocks,
+                    inode_area_blocks,
+                    data_bitmap_blocks,
+                    data_area_blocks,
+                    block_size as u32,
+                );
+                if let Some(cipher) = &efs.cipher {
+                    super_block.set_encrypted(cipher.key_check());
+                }
+            },
+        );
+        // write back immediately
+        // create a inode for root node "/"
+        assert_eq!(efs.alloc_inode(), Some(0));
+        let (root_inode_block_id, root_inode_offset) = efs.get_disk_inode_pos(0);
+        get_block_cache(root_inode_block_id as usize, block_size, Arc::clone(&block_device))
+            .lock()
+            .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
+                disk_inode.initialize(DiskInodeType::Directory);
+            });
+        block_cache_sync_all();
+        Arc::new(efs)
+    }
+
+    /// Open a block device as a filesystem
+    /**
+        The super block is read straight from the device before the block
+        size is known, then every later access goes through the block cache.
+        An image using unknown incompat features is refused; a legacy image,
+        or one with unknown ro-compat features, is mounted read-only.
+    */
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        Self::mount(block_device, None)
+    }
+
+    /// Open a block device holding a filesystem encrypted by `key`
+    pub fn open_with_key(block_device: Arc<dyn BlockDevice>, key: &Key) -> Arc<Self> {
+        Self::mount(block_device, Some(Cipher::new(key)))
+    }
+
+    fn mount(block_device: Arc<dyn BlockDevice>, cipher: Option<Cipher>) -> Arc<Self> {
+        Self::finish_resize(&block_device);
+        let super_block = Self::read_super_block(&block_device);
+        match (super_block.key_check(), &cipher) {
+            (None, None) => {}
+            (None, Some(_)) => panic!("EFS is not encrypted!"),
+            (Some(_), None) => panic!("EFS is encrypted, a key is needed!"),
+            (Some(check), Some(cipher)) => {
+                assert_eq!(check, cipher.key_check(), "Wrong key for EFS!")
+            }
+        }
+        let unknown_incompat = super_block.feature_incompat & !FEATURE_INCOMPAT_SUPPORTED;
+        assert!(unknown_incompat == 0,
+            "EFS uses unsupported features {:#x}!", unknown_incompat);
+        // a legacy image does not tell which features it uses: it has to
+        // be upgraded before it is written
+        let read_only = super_block.is_legacy()
+            || super_block.feature_ro_compat & !FEATURE_RO_COMPAT_SUPPORTED != 0;
+        Arc::new(Self::from_super_block(block_device, &super_block, cipher, read_only))
+    }
+
+    /// Read the super block straight from the device
+    pub(crate) fn read_super_block(block_device: &Arc<dyn BlockDevice>) -> SuperBlock {
+        let super_block = Self::raw_super_block(block_device);
+        assert!(super_block.is_valid(), "Error loading EFS!");
+        super_block
+    }
+
+    /// Read the super block without checking it, e.g. to look at a broken image
+    pub fn raw_super_block(block_device: &Arc<dyn BlockDevice>) -> SuperBlock {
+        let mut raw = vec![0u64; MIN_BLOCK_SZ / 8];
+        let raw_bytes = unsafe {
+            core::slice::from_raw_parts_mut(raw.as_mut_ptr() as *mut u8, MIN_BLOCK_SZ)
+        };
+        block_device.read_block(0, raw_bytes);
+        unsafe { *(raw.as_ptr() as *const SuperBlock) }
+    }
+
+    fn from_super_block(
+        block_device: Arc<dyn BlockDevice>,
+        super_block: &SuperBlock,
+        cipher: Option<Cipher>,
+        read_only: bool,
+    ) -> Self {
+        let geometry = super_block.geometry();
+        let block_size = geometry.block_size;
+        let inode_total_blocks =
+            super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
+        Self {
+            block_device,
+            inode_bitmap: Mutex::new(Bitmap::new(
+                1,
+                super_block.inode_bitmap_blocks as usize,
+                block_size,
+            )),
+            data_bitmap: Mutex::new(Bitmap::new(
+                (1 + inode_total_blocks) as usize,
+                super_block.data_bitmap_blocks as usize,
+                block_size,
+            )),
+            geometry,
+            inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
+            data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+            cipher,
+            read_only,
+            inode_cache: InodeCache::new(),
+        }
+    }
+
+    /// Move an image made before feature fields existed to the current format
+    /**
+        The features in use are found by looking at every inode, then
+        recorded in the super block, which is the only block written.
+        Returns false if the image is already up to date.
+    */
+    pub fn upgrade(block_device: Arc<dyn BlockDevice>) -> bool {
+        let super_block = Self::read_super_block(&block_device);
+        if !super_block.is_legacy() {
+            return false;
+        }
+        let efs = Self::from_super_block(block_device, &super_block, None, true);
+        let geometry = efs.geometry;
+        let mut feature_incompat = 0;
+        if geometry.block_size != MIN_BLOCK_SZ {
+            feature_incompat |= FEATURE_INCOMPAT_BLOCK_SIZE;
+        }
+        if super_block.key_check().is_some() {
+            feature_incompat |= FEATURE_INCOMPAT_ENCRYPTION;
+        }
+        let inode_bitmap = efs.inode_bitmap.lock();
+        for inode_id in 0..inode_bitmap.maximum() {
+            if !inode_bitmap.is_allocated(&efs.block_device, inode_id) {
+                continue;
+            }
+            let disk_inode = efs.read_disk_inode(inode_id as u32);
+            if disk_inode.is_compressed() {
+                feature_incompat |= FEATURE_INCOMPAT_COMPRESSION;
+            }
+            if (0..disk_inode.data_blocks(&geometry))
+                .any(|inner_id| disk_inode.is_unwritten(inner_id, &geometry, &efs.block_device))
+            {
+                feature_incompat |= FEATURE_INCOMPAT_UNWRITTEN;
+            }
+        }
+        get_block_cache(0, geometry.block_size, Arc::clone(&efs.block_device))
+            .lock()
+            .modify(0, |super_block: &mut SuperBlock| super_block.upgrade(feature_incompat));
+        block_cache_sync_all();
+        true
+    }
+
+    /// Whether the filesystem is mounted read-only
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Read the super block
+    pub fn super_block(&self) -> SuperBlock {
+        get_block_cache(0, self.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| *super_block)
+    }
+
+    /// Record in the super block that an incompat feature is in use
+    pub fn enable_feature_incompat(&self, feature: u32) {
+        assert!(!self.read_only, "EFS is mounted read-only!");
+        let cache = get_block_cache(0, self.geometry.block_size, Arc::clone(&self.block_device));
+        let mut cache = cache.lock();
+        if cache.read(0, |super_block: &SuperBlock| super_block.feature_incompat & feature) != feature {
+            cache.modify(0, |super_block: &mut SuperBlock| super_block.feature_incompat |= feature);
+        }
+    }
+
+    /// Get a copy of a disk inode by id
+    pub fn read_disk_inode(&self, inode_id: u32) -> DiskInode {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, |disk_inode: &DiskInode| *disk_inode)
+    }
+
+    /// Store a disk inode by id
+    pub(crate) fn write_disk_inode(&self, inode_id: u32, disk_inode: &DiskInode) {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, |stored: &mut DiskInode| *stored = *disk_inode);
+    }
+
+    /// Get the root inode of the filesystem
+    pub fn root_inode(efs: &Arc<Self>) -> Arc<Inode> {
+        Self::get_inode(efs, 0)
+    }
+
+    /// Get the vfs inode of `inode_id`
+    /**
+        Every handle of the same inode is the same `Inode`, loaded
+        from disk only when the inode has no handle left.
+    */
+    pub fn get_inode(efs: &Arc<Self>, inode_id: u32) -> Arc<Inode> {
+        efs.inode_cache.get(efs, inode_id)
+    }
+
+    /// Keep at most `count` recently looked up directories in the inode cache
+    pub fn set_pinned_dirs(&self, count: usize) {
+        self.inode_cache.set_pin_limit(count);
+    }
+
+    /// Number of inodes in memory, with a vfs handle or pinned
+    pub fn cached_inodes(&self) -> usize {
+        self.inode_cache.len()
+    }
+
+    /// Write back the dirty metadata of every inode with a handle
+    pub fn sync_inodes(&self) {
+        for inode in self.inode_cache.handles() {
+            inode.sync();
+        }
+    }
+
+    /// Get inode by id
+    pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
+        let inode_size = core::mem::size_of::<DiskInode>();
+        let inodes_per_block = self.geometry.inodes_per_block as u32;
+        let block_id = self.inode_area_start_block + inode_id / inodes_per_block;
+        (
+            block_id,
+            (inode_id % inodes_per_block) as usize * inode_size,
+        )
+    }
+
+    /// Get the inode id stored at a position of the inode area
+    pub fn get_inode_id(&self, block_id: u32, block_offset: usize) -> u32 {
+        let inode_size = core::mem::size_of::<DiskInode>();
+        (block_id - self.inode_area_start_block) * self.geometry.inodes_per_block as u32
+            + (block_offset / inode_size) as u32
+    }
+
+    /// The cipher of file data if the filesystem is encrypted
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_ref()
+    }
+
+    /// Get data block by id
+    pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
+        self.data_area_start_block + data_block_id
+    }
+
+    /// Allocate a new inode, None if every inode is in use
+    pub fn alloc_inode(&self) -> Option<u32> {
+        self.inode_bitmap.lock().alloc(&self.block_device).map(|bit| bit as u32)
+    }
+
+    /// Deallocate an inode
+    pub fn dealloc_inode(&self, inode_id: u32) {
+        self.inode_bitmap.lock().dealloc(&self.block_device, inode_id as usize)
+    }
+
+    /// Allocate a data block, None if the data area is full
+    /**
+        The last bitmap block may have bits past the end of the data area:
+        since the lowest free bit is taken first, getting one of them means
+        that every block of the data area is in use.
+    */
+    pub fn alloc_data(&self) -> Option<u32> {
+        let data_bitmap = self.data_bitmap.lock();
+        let bit = data_bitmap.alloc(&self.block_device)?;
+        if bit >= self.super_block().data_area_blocks as usize {
+            data_bitmap.dealloc(&self.block_device, bit);
+            return None;
+        }
+        Some(bit as u32 + self.data_area_start_block)
+    }
+
+    /// Deallocate a data block
+    pub fn dealloc_data(&self, block_id: u32) {
+        get_block_cache(block_id as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .modify_slice(|data_block: &mut [u8]| {
+                data_block.iter_mut().for_each(|p| {
+                    *p = 0;
+                })
+            });
+        self.data_bitmap.lock().dealloc(
+            &self.block_device,
+            (block_id - self.data_area_start_block) as usize,
+        )
+    }
+}
@@ -0,0 +1,399 @@
+//!A read-only ext2 driver
+/*!
+  Reads images made by the standard Linux tools, e.g. `mke2fs -t ext2 -d`,
+  through the same [`BlockDevice`] trait and block cache as easy-fs: the
+  cache is used in units of the ext2 block size, which has to be one of
+  [`crate::BLOCK_SIZES`].
+
+  Only what reading needs is supported: the block groups, the inode
+  table and the classic block map with its three levels of indirection.
+  Directories are read as plain lists of entries, which also works on the
+  indexed directories of `dir_index`. Any incompat feature but `filetype`
+  makes the image refuse to mount.
+*/
+use super::{get_block_cache, BlockDevice, FsInode, BLOCK_SIZES};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// magic number of ext2 (and ext3/ext4) super blocks
+pub const EXT2_MAGIC: u16 = 0xef53;
+/// byte offset of the super block on the device
+const SUPER_BLOCK_OFFSET: usize = 1024;
+/// inode number of the root directory
+const ROOT_INO: u32 = 2;
+/// directory entries record the type of the inode
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+/// incompat features this driver can read
+const FEATURE_INCOMPAT_SUPPORTED: u32 = FEATURE_INCOMPAT_FILETYPE;
+/// files may be larger than 4 GiB, `size_high` holds the upper bits
+const FEATURE_RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+/// inode size of revision 0 images, which do not record it
+const GOOD_OLD_INODE_SIZE: u16 = 128;
+/// number of direct blocks in an inode
+const DIRECT_BLOCKS: usize = 12;
+
+const S_IFMT: u16 = 0xf000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFREG: u16 = 0x8000;
+
+/// The ext2 super block, up to the fields this driver uses
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Ext2SuperBlock {
+    /// number of inodes
+    pub inodes_count: u32,
+    /// number of blocks
+    pub blocks_count: u32,
+    r_blocks_count: u32,
+    /// number of free blocks
+    pub free_blocks_count: u32,
+    /// number of free inodes
+    pub free_inodes_count: u32,
+    /// block holding the super block, 1 for 1 KiB blocks, else 0
+    pub first_data_block: u32,
+    /// block size is `1024 << log_block_size`
+    pub log_block_size: u32,
+    log_frag_size: u32,
+    /// number of blocks in a block group
+    pub blocks_per_group: u32,
+    frags_per_group: u32,
+    /// number of inodes in a block group
+    pub inodes_per_group: u32,
+    mtime: u32,
+    wtime: u32,
+    mnt_count: u16,
+    max_mnt_count: u16,
+    /// [`EXT2_MAGIC`]
+    pub magic: u16,
+    state: u16,
+    errors: u16,
+    minor_rev_level: u16,
+    lastcheck: u32,
+    checkinterval: u32,
+    creator_os: u32,
+    /// 0 for the original format, 1 for the dynamic one with features
+    pub rev_level: u32,
+    def_resuid: u16,
+    def_resgid: u16,
+    first_ino: u32,
+    /// bytes of an inode in the inode table
+    pub inode_size: u16,
+    block_group_nr: u16,
+    /// features an older code can ignore
+    pub feature_compat: u32,
+    /// features an older code cannot read
+    pub feature_incompat: u32,
+    /// features an older code can read but must not write
+    pub feature_ro_compat: u32,
+}
+
+impl Ext2SuperBlock {
+    /// Whether this is an ext2 super block
+    pub fn is_valid(&self) -> bool {
+        self.magic == EXT2_MAGIC
+    }
+
+    /// number of bytes in a block
+    pub fn block_size(&self) -> usize {
+        1024 << self.log_block_size.min(16)
+    }
+
+    fn inode_size(&self) -> u16 {
+        if self.rev_level == 0 {
+            GOOD_OLD_INODE_SIZE
+        } else {
+            self.inode_size
+        }
+    }
+}
+
+/// A block group descriptor
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GroupDesc {
+    block_bitmap: u32,
+    inode_bitmap: u32,
+    inode_table: u32,
+    free_blocks_count: u16,
+    free_inodes_count: u16,
+    used_dirs_count: u16,
+    pad: u16,
+    reserved: [u32; 3],
+}
+
+/// An ext2 inode, the first 128 bytes of every inode size
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Ext2DiskInode {
+    mode: u16,
+    uid: u16,
+    size: u32,
+    atime: u32,
+    ctime: u32,
+    mtime: u32,
+    dtime: u32,
+    gid: u16,
+    links_count: u16,
+    blocks: u32,
+    flags: u32,
+    osd1: u32,
+    block: [u32; 15],
+    generation: u32,
+    file_acl: u32,
+    /// upper 32 bits of the size of a regular file
+    size_high: u32,
+    faddr: u32,
+    osd2: [u8; 12],
+}
+
+/// A read-only ext2 filesystem on a block device
+pub struct Ext2FileSystem {
+    /// real device
+    pub block_device: Arc<dyn BlockDevice>,
+    /// number of bytes in a block
+    pub block_size: usize,
+    inodes_per_group: u32,
+    inode_size: usize,
+    groups: Vec<GroupDesc>,
+    large_file: bool,
+    filetype: bool,
+}
+
+impl Ext2FileSystem {
+    /// Read the super block straight from the device, without checking it
+    pub fn raw_super_block(block_device: &Arc<dyn BlockDevice>) -> Ext2SuperBlock {
+        let mut raw = vec![0u64; SUPER_BLOCK_OFFSET / 8];
+        let raw_bytes = unsafe {
+            core::slice::from_raw_parts_mut(raw.as_mut_ptr() as *mut u8, SUPER_BLOCK_OFFSET)
+        };
+        block_device.read_block(1, raw_bytes);
+        unsafe { *(raw.as_ptr() as *const Ext2SuperBlock) }
+    }
+
+    /// Open a block device holding an ext2 filesystem
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        let super_block = Self::raw_super_block(&block_device);
+        assert!(super_block.is_valid(), "Error loading ext2!");
+        let block_size = super_block.block_size();
+        assert!(BLOCK_SIZES.contains(&block_size),
+            "ext2 blocks of {} bytes are not supported!", block_size);
+        let unknown_incompat = if super_block.rev_level == 0 {
+            0
+        } else {
+            super_block.feature_incompat & !FEATURE_INCOMPAT_SUPPORTED
+        };
+        assert!(unknown_incompat == 0,
+            "ext2 uses unsupported features {:#x}!", unknown_incompat);
+        let group_count = (super_block.blocks_count - super_block.first_data_block)
+            .div_ceil(super_block.blocks_per_group) as usize;
+        // the descriptor table is in the block after the super block
+        let table_start = super_block.first_data_block as usize + 1;
+        let per_block = block_size / core::mem::size_of::<GroupDesc>();
+        let groups = (0..group_count)
+            .map(|group| {
+                get_block_cache(table_start + group / per_block, block_size, Arc::clone(&block_device))
+                    .lock()
+                    .read(
+                        group % per_block * core::mem::size_of::<GroupDesc>(),
+                        |desc: &GroupDesc| *desc,
+                    )
+            })
+            .collect();
+        Arc::new(Self {
+            block_device,
+            block_size,
+            inodes_per_group: super_block.inodes_per_group,
+            inode_size: super_block.inode_size() as usize,
+            groups,
+            large_file: super_block.rev_level > 0
+                && super_block.feature_ro_compat & FEATURE_RO_COMPAT_LARGE_FILE != 0,
+            filetype: super_block.rev_level > 0
+                && super_block.feature_incompat & FEATURE_INCOMPAT_FILETYPE != 0,
+        })
+    }
+
+    /// Get the root inode of the filesystem
+    pub fn root_inode(fs: &Arc<Self>) -> Arc<Ext2Inode> {
+        Self::get_inode(fs, ROOT_INO)
+    }
+
+    /// Get inode `ino`, numbered from 1 like in ext2
+    pub fn get_inode(fs: &Arc<Self>, ino: u32) -> Arc<Ext2Inode> {
+        let group = ((ino - 1) / fs.inodes_per_group) as usize;
+        let index = ((ino - 1) % fs.inodes_per_group) as usize;
+        let offset = index * fs.inode_size;
+        let block_id = fs.groups[group].inode_table as usize + offset / fs.block_size;
+        let disk_inode = get_block_cache(block_id, fs.block_size, Arc::clone(&fs.block_device))
+            .lock()
+            .read(offset % fs.block_size, |disk_inode: &Ext2DiskInode| *disk_inode);
+        Arc::new(Ext2Inode {
+            ino,
+            disk_inode,
+            fs: Arc::clone(fs),
+        })
+    }
+
+    /// entry `index` of the block of block ids `table`, 0 for a hole
+    fn indirect(&self, table: u32, index: usize) -> u32 {
+        if table == 0 {
+            return 0;
+        }
+        get_block_cache(table as usize, self.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .read_slice(|ids: &[u32]| ids[index])
+    }
+}
+
+/// An inode of an ext2 filesystem
+pub struct Ext2Inode {
+    ino: u32,
+    disk_inode: Ext2DiskInode,
+    fs: Arc<Ext2FileSystem>,
+}
+
+impl Ext2Inode {
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> bool {
+        self.disk_inode.mode & S_IFMT == S_IFDIR
+    }
+
+    /// Whether current inode is a regular file
+    pub fn is_file(&self) -> bool {
+        self.disk_inode.mode & S_IFMT == S_IFREG
+    }
+
+    /// Size of the data in current inode
+    pub fn size(&self) -> usize {
+        let mut size = self.disk_inode.size as u64;
+        if self.fs.large_file && self.is_file() {
+            size |= (self.disk_inode.size_high as u64) << 32;
+        }
+        size as usize
+    }
+
+    /// Block holding data block `index` of current inode, 0 for a hole
+    fn block_id(&self, index: usize) -> u32 {
+        let per_block = self.fs.block_size / 4;
+        let block = &self.disk_inode.block;
+        if index < DIRECT_BLOCKS {
+            return block[index];
+        }
+        let index = index - DIRECT_BLOCKS;
+        if index < per_block {
+            return self.fs.indirect(block[12], index);
+        }
+        let index = index - per_block;
+        if index < per_block * per_block {
+            let indirect1 = self.fs.indirect(block[13], index / per_block);
+            return self.fs.indirect(indirect1, index % per_block);
+        }
+        let index = index - per_block * per_block;
+        let indirect2 = self.fs.indirect(block[14], index / (per_block * per_block));
+        let indirect1 = self.fs.indirect(indirect2, index / per_block % per_block);
+        self.fs.indirect(indirect1, index % per_block)
+    }
+
+    /// Read data from current inode, holes read as zeros
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let block_size = self.fs.block_size;
+        let end = (offset + buf.len()).min(self.size());
+        let mut pos = offset;
+        while pos < end {
+            let block_offset = pos % block_size;
+            let len = (block_size - block_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match self.block_id(pos / block_size) {
+                0 => dst.fill(0),
+                block_id => {
+                    get_block_cache(block_id as usize, block_size, Arc::clone(&self.fs.block_device))
+                        .lock()
+                        .read_slice(|data: &[u8]| {
+                            dst.copy_from_slice(&data[block_offset..block_offset + len])
+                        });
+                }
+            }
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+
+    /// Every entry of current directory as (name, inode number)
+    fn entries(&self) -> Vec<(String, u32)> {
+        assert!(self.is_dir());
+        let mut data = vec![0u8; self.size()];
+        self.read_at(0, &mut data);
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let ino = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+            let rec_len = u16::from_le_bytes(data[pos + 4..pos + 6].try_into().unwrap()) as usize;
+            let name_len = if self.fs.filetype {
+                data[pos + 6] as usize
+            } else {
+                u16::from_le_bytes(data[pos + 6..pos + 8].try_into().unwrap()) as usize
+            };
+            if rec_len < 8 || pos + 8 + name_len > data.len() {
+                break;
+            }
+            if ino != 0 {
+                let name = &data[pos + 8..pos + 8 + name_len];
+                entries.push((String::from_utf8_lossy(name).into_owned(), ino));
+            }
+            pos += rec_len;
+        }
+        entries
+    }
+
+    /// Find inode under current inode by name
+    pub fn find(&self, name: &str) -> Option<Arc<Ext2Inode>> {
+        self.entries()
+            .into_iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, ino)| Ext2FileSystem::get_inode(&self.fs, ino))
+    }
+
+    /// List inodes under current inode, but "." and ".."
+    pub fn ls(&self) -> Vec<String> {
+        self.entries()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name != "." && name != "..")
+            .collect()
+    }
+}
+
+impl FsInode for Ext2Inode {
+    fn inode_id(&self) -> u32 {
+        self.ino
+    }
+
+    fn nlink(&self) -> u32 {
+        self.disk_inode.links_count as u32
+    }
+
+    fn is_dir(&self) -> bool {
+        Ext2Inode::is_dir(self)
+    }
+
+    fn is_file(&self) -> bool {
+        Ext2Inode::is_file(self)
+    }
+
+    fn size(&self) -> usize {
+        Ext2Inode::size(self)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        Ext2Inode::read_at(self, offset, buf)
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        Ext2Inode::find(self, name).map(|inode| inode as Arc<dyn FsInode>)
+    }
+
+    fn ls(&self) -> Vec<String> {
+        Ext2Inode::ls(self)
+    }
+}
@@ -0,0 +1,1053 @@
+//!A FAT32 driver
+/*!
+  Reads and writes FAT32 volumes, e.g. made by `mkfs.vfat -F 32` and
+  filled by `mtools`, through the same [`BlockDevice`] trait and block
+  cache as easy-fs: the cache is used in units of the sector size, which
+  has to be one of [`crate::BLOCK_SIZES`].
+
+  File data lives in chains of clusters linked by the file allocation
+  table, every copy of which is kept the same. Names are long names
+  (VFAT), stored in UCS-2 entries before the 8.3 entry of a file; a name
+  that is already a valid upper case 8.3 name is stored without them.
+  Lookups ignore ASCII case, like other FAT drivers do.
+
+  FAT has no inodes: a file is known by where its 8.3 entry is, and every
+  access reads that entry again, so that all handles see the same file.
+  Writes are serialized by a lock of the whole volume.
+*/
+use super::{block_cache_sync_all, get_block_cache, BlockDevice, FsInode, BLOCK_SIZES, MIN_BLOCK_SZ};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+/// the last two bytes of a boot sector
+const BOOT_SIGNATURE: u16 = 0xaa55;
+const FSINFO_LEAD_SIGNATURE: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xaa55_0000;
+/// free count or next free cluster not known
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+/// only the low 28 bits of a FAT32 entry are used
+const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+/// entries from this one on end a chain
+const FAT_EOC_MIN: u32 = 0x0fff_fff8;
+/// the end of chain mark written by this driver
+const FAT_EOC: u32 = 0x0fff_ffff;
+/// first cluster of the data area
+const FIRST_CLUSTER: u32 = 2;
+/// the FAT copy in use is chosen by `ext_flags` instead of all being mirrored
+const EXT_FLAGS_NO_MIRROR: u16 = 0x80;
+
+/// size of a directory entry
+const DIR_ENTRY_SZ: usize = 32;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// attributes of a long name entry
+const ATTR_LONG_NAME: u8 = 0x0f;
+/// flag of the long name entry holding the end of the name
+const LAST_LONG_ENTRY: u8 = 0x40;
+/// first name byte of a deleted entry
+const DELETED: u8 = 0xe5;
+/// first name byte standing for a real 0xe5
+const KANJI_E5: u8 = 0x05;
+/// the base name of an 8.3 entry is to be shown in lower case
+const NT_LOWER_BASE: u8 = 0x08;
+/// the extension of an 8.3 entry is to be shown in lower case
+const NT_LOWER_EXT: u8 = 0x10;
+/// number of characters in a long name entry
+const LONG_ENTRY_CHARS: usize = 13;
+/// where the characters of a long name entry are
+const LONG_ENTRY_OFFSETS: [usize; LONG_ENTRY_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// longest long name
+pub const LONG_NAME_LIMIT: usize = 255;
+/// there is no clock, new entries are dated 1980-01-01
+const FAT_DATE: u16 = (1 << 5) | 1;
+/// characters a long name cannot hold
+const INVALID_LONG_CHARS: &str = "\"*/:<>?\\|";
+/// characters an 8.3 name cannot hold on top of those
+const INVALID_SHORT_CHARS: &str = "+,;=[] .";
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn le32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn put16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The fields of the boot sector this driver uses
+/**
+    Several fields are not aligned, so the sector is parsed byte by
+    byte instead of being read as a struct.
+*/
+#[derive(Clone, Copy, Debug)]
+pub struct BootSector {
+    /// number of bytes in a sector
+    pub bytes_per_sector: u16,
+    /// number of sectors in a cluster, a power of two
+    pub sectors_per_cluster: u8,
+    /// number of sectors before the first FAT
+    pub reserved_sectors: u16,
+    /// number of copies of the FAT
+    pub fats: u8,
+    /// entries of the fixed root directory of FAT12/16, 0 on FAT32
+    pub root_entries: u16,
+    /// number of sectors of the volume
+    pub total_sectors: u32,
+    /// sectors of the FAT of FAT12/16, 0 on FAT32
+    pub fat_sectors_16: u16,
+    /// sectors of one FAT
+    pub fat_sectors: u32,
+    /// mirroring of the FATs and the active one
+    pub ext_flags: u16,
+    /// first cluster of the root directory
+    pub root_cluster: u32,
+    /// sector of the FSInfo structure
+    pub fs_info: u16,
+    /// [`BOOT_SIGNATURE`] on a valid volume
+    pub signature: u16,
+}
+
+impl BootSector {
+    fn parse(raw: &[u8]) -> Self {
+        let total_16 = le16(raw, 19);
+        Self {
+            bytes_per_sector: le16(raw, 11),
+            sectors_per_cluster: raw[13],
+            reserved_sectors: le16(raw, 14),
+            fats: raw[16],
+            root_entries: le16(raw, 17),
+            total_sectors: if total_16 != 0 { total_16 as u32 } else { le32(raw, 32) },
+            fat_sectors_16: le16(raw, 22),
+            fat_sectors: le32(raw, 36),
+            ext_flags: le16(raw, 40),
+            root_cluster: le32(raw, 44),
+            fs_info: le16(raw, 48),
+            signature: le16(raw, 510),
+        }
+    }
+
+    /// Whether this is the boot sector of a FAT32 volume this driver can use
+    pub fn is_valid(&self) -> bool {
+        self.signature == BOOT_SIGNATURE
+            && BLOCK_SIZES.contains(&(self.bytes_per_sector as usize))
+            && self.sectors_per_cluster.is_power_of_two()
+            && self.reserved_sectors > 0
+            && self.fats > 0
+            // FAT12/16 have a fixed root directory and a 16 bit FAT size
+            && self.root_entries == 0
+            && self.fat_sectors_16 == 0
+            && self.fat_sectors > 0
+    }
+}
+
+/// what the allocator knows of the free clusters
+struct FreeClusters {
+    count: u32,
+    /// where to look for a free cluster first
+    next: u32,
+}
+
+/// A FAT32 volume on a block device
+pub struct Fat32FileSystem {
+    /// real device
+    pub block_device: Arc<dyn BlockDevice>,
+    /// number of bytes in a sector
+    pub sector_size: usize,
+    sectors_per_cluster: u32,
+    fat_start: u32,
+    fat_sectors: u32,
+    /// the FAT copies written, all of them unless mirroring is off
+    fats_written: Vec<u32>,
+    /// the FAT copy read
+    fat_read: u32,
+    data_start: u32,
+    /// number of clusters in the data area
+    clusters: u32,
+    root_cluster: u32,
+    /// sector of FSInfo, 0 if there is none
+    fs_info: u32,
+    free: Mutex<FreeClusters>,
+    /// shared by reads, taken alone by writes
+    lock: RwLock<()>,
+}
+
+impl Fat32FileSystem {
+    /// Read the boot sector straight from the device, without checking it
+    pub fn raw_boot_sector(block_device: &Arc<dyn BlockDevice>) -> BootSector {
+        let mut raw = [0u8; MIN_BLOCK_SZ];
+        block_device.read_block(0, &mut raw);
+        BootSector::parse(&raw)
+    }
+
+    /// Create a FAT32 volume of `total_sectors` sectors of 512 bytes on a device
+    /**
+        Clusters are sized like `mkfs.vfat` does for FAT32: a sector each
+        up to 260 MiB, then larger as the volume grows.
+    */
+    pub fn format(block_device: Arc<dyn BlockDevice>, total_sectors: u32) -> Arc<Self> {
+        let sector_size = MIN_BLOCK_SZ;
+        let sectors_per_cluster: u32 = match total_sectors {
+            0..=532_480 => 1,
+            532_481..=16_777_216 => 8,
+            16_777_217..=33_554_432 => 16,
+            33_554_433..=67_108_864 => 32,
+            _ => 64,
+        };
+        let reserved_sectors = 32u32;
+        let fats = 2u32;
+        // from the FAT specification, may waste a few FAT sectors
+        let fat_sectors = (total_sectors - reserved_sectors)
+            .div_ceil((128 * sectors_per_cluster * 2 + fats) / 2);
+        let data_start = reserved_sectors + fats * fat_sectors;
+        assert!(data_start + sectors_per_cluster < total_sectors,
+            "{} sectors are too few for FAT32!", total_sectors);
+        let clusters = (total_sectors - data_start) / sectors_per_cluster;
+        let write = |sector: u32, f: &dyn Fn(&mut [u8])| {
+            get_block_cache(sector as usize, sector_size, Arc::clone(&block_device))
+                .lock()
+                .modify_slice(|bytes: &mut [u8]| f(bytes));
+        };
+        let boot_sector = |bytes: &mut [u8]| {
+            bytes.fill(0);
+            bytes[..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+            bytes[3..11].copy_from_slice(b"EASYFS  ");
+            put16(bytes, 11, sector_size as u16);
+            bytes[13] = sectors_per_cluster as u8;
+            put16(bytes, 14, reserved_sectors as u16);
+            bytes[16] = fats as u8;
+            bytes[21] = 0xf8;
+            put16(bytes, 24, 32);
+            put16(bytes, 26, 64);
+            put32(bytes, 32, total_sectors);
+            put32(bytes, 36, fat_sectors);
+            put32(bytes, 44, FIRST_CLUSTER);
+            put16(bytes, 48, 1);
+            put16(bytes, 50, 6);
+            bytes[64] = 0x80;
+            bytes[66] = 0x29;
+            put32(bytes, 67, 0x4546_5321);
+            bytes[71..82].copy_from_slice(b"NO NAME    ");
+            bytes[82..90].copy_from_slice(b"FAT32   ");
+            put16(bytes, 510, BOOT_SIGNATURE);
+        };
+        let fs_info = |bytes: &mut [u8]| {
+            bytes.fill(0);
+            put32(bytes, 0, FSINFO_LEAD_SIGNATURE);
+            put32(bytes, 484, FSINFO_STRUCT_SIGNATURE);
+            put32(bytes, 488, clusters - 1);
+            put32(bytes, 492, FIRST_CLUSTER + 1);
+            put32(bytes, 508, FSINFO_TRAIL_SIGNATURE);
+        };
+        // the boot sector and FSInfo, with their backups at 6 and 7
+        write(0, &boot_sector);
+        write(1, &fs_info);
+        write(6, &boot_sector);
+        write(7, &fs_info);
+        for sector in reserved_sectors..data_start {
+            write(sector, &|bytes: &mut [u8]| bytes.fill(0));
+        }
+        for fat in 0..fats {
+            // the media byte, a clean volume, and the root directory
+            write(reserved_sectors + fat * fat_sectors, &|bytes: &mut [u8]| {
+                put32(bytes, 0, 0x0fff_fff8);
+                put32(bytes, 4, FAT_EOC);
+                put32(bytes, 8, FAT_EOC);
+            });
+        }
+        for sector in data_start..data_start + sectors_per_cluster {
+            write(sector, &|bytes: &mut [u8]| bytes.fill(0));
+        }
+        block_cache_sync_all();
+        Self::open(block_device)
+    }
+
+    /// Open a block device holding a FAT32 volume
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        let boot_sector = Self::raw_boot_sector(&block_device);
+        assert!(boot_sector.is_valid(), "Error loading FAT32!");
+        let sector_size = boot_sector.bytes_per_sector as usize;
+        let sectors_per_cluster = boot_sector.sectors_per_cluster as u32;
+        let fat_start = boot_sector.reserved_sectors as u32;
+        let data_start = fat_start + boot_sector.fats as u32 * boot_sector.fat_sectors;
+        // as many clusters as the data area and the FAT both hold
+        let clusters = ((boot_sector.total_sectors - data_start) / sectors_per_cluster)
+            .min((boot_sector.fat_sectors * sector_size as u32 / 4).saturating_sub(FIRST_CLUSTER));
+        let (fats_written, fat_read) = if boot_sector.ext_flags & EXT_FLAGS_NO_MIRROR != 0 {
+            let active = (boot_sector.ext_flags & 0xf) as u32;
+            (vec![active], active)
+        } else {
+            ((0..boot_sector.fats as u32).collect(), 0)
+        };
+        let fs = Self {
+            block_device,
+            sector_size,
+            sectors_per_cluster,
+            fat_start,
+            fat_sectors: boot_sector.fat_sectors,
+            fats_written,
+            fat_read,
+            data_start,
+            clusters,
+            root_cluster: boot_sector.root_cluster,
+            fs_info: boot_sector.fs_info as u32,
+            free: Mutex::new(FreeClusters { count: 0, next: FIRST_CLUSTER }),
+            lock: RwLock::new(()),
+        };
+        // FSInfo is only a hint, the FAT is what counts
+        let count = (FIRST_CLUSTER..FIRST_CLUSTER + clusters)
+            .filter(|cluster| fs.fat_entry(*cluster) == 0)
+            .count() as u32;
+        fs.free.lock().count = count;
+        Arc::new(fs)
+    }
+
+    /// Get the root directory of the volume
+    pub fn root_inode(fs: &Arc<Self>) -> Arc<FatInode> {
+        Arc::new(FatInode {
+            fs: Arc::clone(fs),
+            entry: None,
+            is_dir: true,
+        })
+    }
+
+    /// Number of free clusters
+    pub fn free_clusters(&self) -> u32 {
+        self.free.lock().count
+    }
+
+    /// Number of bytes in a cluster
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * self.sector_size
+    }
+
+    fn first_sector_of(&self, cluster: u32) -> u32 {
+        self.data_start + (cluster - FIRST_CLUSTER) * self.sectors_per_cluster
+    }
+
+    fn is_data_cluster(&self, cluster: u32) -> bool {
+        (FIRST_CLUSTER..FIRST_CLUSTER + self.clusters).contains(&cluster)
+    }
+
+    /// sector and byte offset of the entry of `cluster` in FAT copy `fat`
+    fn fat_pos(&self, fat: u32, cluster: u32) -> (usize, usize) {
+        let offset = cluster as usize * 4;
+        let sector = self.fat_start + fat * self.fat_sectors + (offset / self.sector_size) as u32;
+        (sector as usize, offset % self.sector_size)
+    }
+
+    /// The FAT entry of `cluster`
+    fn fat_entry(&self, cluster: u32) -> u32 {
+        let (sector, offset) = self.fat_pos(self.fat_read, cluster);
+        get_block_cache(sector, self.sector_size, Arc::clone(&self.block_device))
+            .lock()
+            .read(offset, |entry: &u32| *entry & FAT_ENTRY_MASK)
+    }
+
+    /// Set the FAT entry of `cluster` in every FAT copy written
+    fn set_fat_entry(&self, cluster: u32, value: u32) {
+        for fat in self.fats_written.iter() {
+            let (sector, offset) = self.fat_pos(*fat, cluster);
+            get_block_cache(sector, self.sector_size, Arc::clone(&self.block_device))
+                .lock()
+                .modify(offset, |entry: &mut u32| {
+                    *entry = (*entry & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK)
+                });
+        }
+    }
+
+    /// The clusters of the chain starting at `first`, none if `first` is 0
+    fn chain(&self, first: u32) -> Vec<u32> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while self.is_data_cluster(cluster) && chain.len() < self.clusters as usize {
+            chain.push(cluster);
+            cluster = self.fat_entry(cluster);
+            if cluster >= FAT_EOC_MIN {
+                break;
+            }
+        }
+        chain
+    }
+
+    /// Allocate a zeroed cluster ending a chain, None if the volume is full
+    fn alloc_cluster(&self) -> Option<u32> {
+        let mut free = self.free.lock();
+        if free.count == 0 {
+            return None;
+        }
+        let start = if self.is_data_cluster(free.next) { free.next } else { FIRST_CLUSTER };
+        let cluster = (start..FIRST_CLUSTER + self.clusters)
+            .chain(FIRST_CLUSTER..start)
+            .find(|cluster| self.fat_entry(*cluster) == 0)
+            .expect("Broken FAT32 free cluster count!");
+        self.set_fat_entry(cluster, FAT_EOC);
+        free.count -= 1;
+        free.next = cluster + 1;
+        self.write_fs_info(&free);
+        drop(free);
+        let first_sector = self.first_sector_of(cluster);
+        for sector in first_sector..first_sector + self.sectors_per_cluster {
+            get_block_cache(sector as usize, self.sector_size, Arc::clone(&self.block_device))
+                .lock()
+                .modify_slice(|bytes: &mut [u8]| bytes.fill(0));
+        }
+        Some(cluster)
+    }
+
+    /// Make the chain of `chain` at least `count` clusters long
+    /**
+        The chain stops short of `count` if the volume fills up on the way.
+    */
+    fn extend_chain(&self, chain: &mut Vec<u32>, count: usize) {
+        while chain.len() < count {
+            let Some(cluster) = self.alloc_cluster() else {
+                return;
+            };
+            if let Some(last) = chain.last() {
+                self.set_fat_entry(*last, cluster);
+            }
+            chain.push(cluster);
+        }
+    }
+
+    /// Free every cluster of the chain starting at `first`
+    fn free_chain(&self, first: u32) {
+        let chain = self.chain(first);
+        let mut free = self.free.lock();
+        for cluster in chain.iter() {
+            self.set_fat_entry(*cluster, 0);
+        }
+        free.count += chain.len() as u32;
+        if let Some(first) = chain.first() {
+            free.next = free.next.min(*first);
+        }
+        self.write_fs_info(&free);
+    }
+
+    /// Record the free cluster count in FSInfo, for other drivers
+    fn write_fs_info(&self, free: &FreeClusters) {
+        if self.fs_info == 0 {
+            return;
+        }
+        get_block_cache(self.fs_info as usize, self.sector_size, Arc::clone(&self.block_device))
+            .lock()
+            .modify_slice(|bytes: &mut [u8]| {
+                if le32(bytes, 0) == FSINFO_LEAD_SIGNATURE && le32(bytes, 484) == FSINFO_STRUCT_SIGNATURE {
+                    put32(bytes, 488, free.count);
+                    put32(bytes, 492, if self.is_data_cluster(free.next) { free.next } else { FSINFO_UNKNOWN });
+                }
+            });
+    }
+
+    /// sector and byte offset of byte `pos` of the data of `chain`
+    fn data_pos(&self, chain: &[u32], pos: usize) -> (usize, usize) {
+        let cluster = chain[pos / self.cluster_size()];
+        let in_cluster = pos % self.cluster_size();
+        let sector = self.first_sector_of(cluster) as usize + in_cluster / self.sector_size;
+        (sector, in_cluster % self.sector_size)
+    }
+
+    /// Read the data of `chain` from `offset` up to `end`
+    fn read_chain(&self, chain: &[u32], offset: usize, end: usize, buf: &mut [u8]) -> usize {
+        let end = end.min(offset + buf.len());
+        let mut pos = offset;
+        while pos < end {
+            let (sector, sector_offset) = self.data_pos(chain, pos);
+            let len = (self.sector_size - sector_offset).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            get_block_cache(sector, self.sector_size, Arc::clone(&self.block_device))
+                .lock()
+                .read_slice(|bytes: &[u8]| dst.copy_from_slice(&bytes[sector_offset..sector_offset + len]));
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+
+    /// Write `buf` in the data of `chain` at `offset`, which has room for it
+    fn write_chain(&self, chain: &[u32], offset: usize, buf: &[u8]) {
+        let end = offset + buf.len();
+        let mut pos = offset;
+        while pos < end {
+            let (sector, sector_offset) = self.data_pos(chain, pos);
+            let len = (self.sector_size - sector_offset).min(end - pos);
+            let src = &buf[pos - offset..pos - offset + len];
+            get_block_cache(sector, self.sector_size, Arc::clone(&self.block_device))
+                .lock()
+                .modify_slice(|bytes: &mut [u8]| bytes[sector_offset..sector_offset + len].copy_from_slice(src));
+            pos += len;
+        }
+    }
+
+    fn read_entry(&self, (sector, offset): (usize, usize)) -> [u8; DIR_ENTRY_SZ] {
+        get_block_cache(sector, self.sector_size, Arc::clone(&self.block_device))
+            .lock()
+            .read(offset, |entry: &[u8; DIR_ENTRY_SZ]| *entry)
+    }
+
+    fn write_entry(&self, (sector, offset): (usize, usize), entry: &[u8; DIR_ENTRY_SZ]) {
+        get_block_cache(sector, self.sector_size, Arc::clone(&self.block_device))
+            .lock()
+            .modify(offset, |stored: &mut [u8; DIR_ENTRY_SZ]| *stored = *entry);
+    }
+}
+
+/// Checksum of an 8.3 name, kept by the long name entries in front of it
+fn short_name_checksum(short_name: &[u8]) -> u8 {
+    short_name[..11]
+        .iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// The name an 8.3 entry shows
+fn short_name_of(entry: &[u8; DIR_ENTRY_SZ]) -> String {
+    let lower = |bytes: &[u8], flag: u8| -> String {
+        bytes
+            .iter()
+            .map(|byte| {
+                let c = *byte as char;
+                if entry[12] & flag != 0 { c.to_ascii_lowercase() } else { c }
+            })
+            .collect()
+    };
+    let mut base = entry[..8].to_vec();
+    if base[0] == KANJI_E5 {
+        base[0] = DELETED;
+    }
+    let base_len = base.iter().rposition(|byte| *byte != b' ').map_or(0, |i| i + 1);
+    let ext_len = entry[8..11].iter().rposition(|byte| *byte != b' ').map_or(0, |i| i + 1);
+    let mut name = lower(&base[..base_len], NT_LOWER_BASE);
+    if ext_len > 0 {
+        name.push('.');
+        name.push_str(&lower(&entry[8..8 + ext_len], NT_LOWER_EXT));
+    }
+    name
+}
+
+/// The 11 bytes of `name` if it can be stored as an 8.3 entry alone
+fn as_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let valid = |part: &str, max: usize| {
+        part.len() <= max
+            && part.bytes().all(|byte| {
+                byte.is_ascii_graphic()
+                    && !byte.is_ascii_lowercase()
+                    && !INVALID_LONG_CHARS.contains(byte as char)
+                    && !INVALID_SHORT_CHARS.contains(byte as char)
+            })
+    };
+    if base.is_empty() || !valid(base, 8) || !valid(ext, 3) || name.ends_with('.') {
+        return None;
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short_name)
+}
+
+/// An 8.3 name for `name` different from every one of `taken`, like "LONGNA~1.TXT"
+fn numbered_short_name(name: &str, taken: &[[u8; 11]]) -> [u8; 11] {
+    let clean = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| {
+                if c.is_ascii() && !INVALID_SHORT_CHARS.contains(c) && !INVALID_LONG_CHARS.contains(c) {
+                    c.to_ascii_uppercase() as u8
+                } else {
+                    b'_'
+                }
+            })
+            .take(max)
+            .collect()
+    };
+    let (base, ext) = match name.rsplit_once('.') {
+        Some((base, ext)) if !base.is_empty() => (clean(base, 8), clean(ext, 3)),
+        _ => (clean(name, 8), Vec::new()),
+    };
+    for n in 1u32.. {
+        let tail = alloc::format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short_name = [b' '; 11];
+        short_name[..keep].copy_from_slice(&base[..keep]);
+        short_name[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short_name[8..8 + ext.len()].copy_from_slice(&ext);
+        if !taken.contains(&short_name) {
+            return short_name;
+        }
+    }
+    unreachable!()
+}
+
+/// Whether `name` can be the long name of a new entry
+fn is_valid_long_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= LONG_NAME_LIMIT
+        && !name.ends_with('.')
+        && !name.ends_with(' ')
+        && name.chars().all(|c| c >= ' ' && !INVALID_LONG_CHARS.contains(c))
+}
+
+/// The long name entries of `name`, in the order they are stored
+fn long_entries(name: &str, checksum: u8) -> Vec<[u8; DIR_ENTRY_SZ]> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    let count = units.len().div_ceil(LONG_ENTRY_CHARS);
+    // a name that does not fill its last entry ends with 0, then 0xffff
+    if !units.len().is_multiple_of(LONG_ENTRY_CHARS) {
+        units.push(0);
+    }
+    units.resize(count * LONG_ENTRY_CHARS, 0xffff);
+    (0..count)
+        .rev()
+        .map(|index| {
+            let mut entry = [0u8; DIR_ENTRY_SZ];
+            entry[0] = (index + 1) as u8 | if index + 1 == count { LAST_LONG_ENTRY } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (i, offset) in LONG_ENTRY_OFFSETS.iter().enumerate() {
+                put16(&mut entry, *offset, units[index * LONG_ENTRY_CHARS + i]);
+            }
+            entry
+        })
+        .collect()
+}
+
+/// An entry of a directory as it is found on disk
+struct DirSlot {
+    /// the long name if there is one, else the 8.3 name
+    name: String,
+    /// the 8.3 entry
+    entry: [u8; DIR_ENTRY_SZ],
+    /// index of the 8.3 entry in the directory
+    index: usize,
+    /// index of the first long name entry, `index` if there is none
+    first: usize,
+}
+
+impl DirSlot {
+    fn first_cluster(&self) -> u32 {
+        (le16(&self.entry, 20) as u32) << 16 | le16(&self.entry, 26) as u32
+    }
+
+    fn is_dir(&self) -> bool {
+        self.entry[11] & ATTR_DIRECTORY != 0
+    }
+
+    /// the volume label, which only the root directory has
+    fn is_label(&self) -> bool {
+        self.entry[11] & ATTR_VOLUME_ID != 0
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        !self.is_label()
+            && (self.name.eq_ignore_ascii_case(name)
+                || short_name_of(&self.entry).eq_ignore_ascii_case(name))
+    }
+}
+
+/// A file or a directory of a FAT32 volume
+pub struct FatInode {
+    fs: Arc<Fat32FileSystem>,
+    /// sector and byte offset of the 8.3 entry, `None` for the root directory
+    entry: Option<(usize, usize)>,
+    is_dir: bool,
+}
+
+impl FatInode {
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// Whether current inode is a regular file
+    pub fn is_file(&self) -> bool {
+        !self.is_dir
+    }
+
+    /// first cluster and size of a file as its entry says
+    fn start_and_size(&self) -> (u32, usize) {
+        match self.entry {
+            None => (self.fs.root_cluster, 0),
+            Some(pos) => {
+                let entry = self.fs.read_entry(pos);
+                let first = (le16(&entry, 20) as u32) << 16 | le16(&entry, 26) as u32;
+                // ".." of a directory under the root points at cluster 0
+                let first = if first == 0 && self.is_dir { self.fs.root_cluster } else { first };
+                (first, le32(&entry, 28) as usize)
+            }
+        }
+    }
+
+    /// Record the first cluster and the size of a file in its entry
+    fn set_start_and_size(&self, first: u32, size: usize) {
+        let pos = self.entry.unwrap();
+        let mut entry = self.fs.read_entry(pos);
+        put16(&mut entry, 20, (first >> 16) as u16);
+        put16(&mut entry, 26, first as u16);
+        put32(&mut entry, 28, size as u32);
+        put16(&mut entry, 24, FAT_DATE);
+        self.fs.write_entry(pos, &entry);
+    }
+
+    /// Size of the data in current inode, that of its clusters for a directory
+    pub fn size(&self) -> usize {
+        let _guard = self.fs.lock.read();
+        self.size_unlocked()
+    }
+
+    fn size_unlocked(&self) -> usize {
+        let (first, size) = self.start_and_size();
+        if self.is_dir {
+            self.fs.chain(first).len() * self.fs.cluster_size()
+        } else {
+            size
+        }
+    }
+
+    /// Read data from current inode
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let _guard = self.fs.lock.read();
+        let (first, _) = self.start_and_size();
+        let size = self.size_unlocked();
+        self.fs.read_chain(&self.fs.chain(first), offset, size, buf)
+    }
+
+    /// Write data to current inode, growing it if needed
+    /**
+        Returns how many bytes were written: none to a directory, which is
+        only written through its entries, and none past 4 GiB, the largest
+        size an entry holds. A full volume stops the file at the end of its
+        last cluster.
+    */
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        if self.is_dir || offset > u32::MAX as usize {
+            return 0;
+        }
+        let buf = &buf[..buf.len().min(u32::MAX as usize - offset)];
+        let _guard = self.fs.lock.write();
+        let (first, size) = self.start_and_size();
+        let cluster_size = self.fs.cluster_size();
+        let mut chain = self.fs.chain(first);
+        self.fs.extend_chain(&mut chain, (offset + buf.len()).div_ceil(cluster_size));
+        let end = (offset + buf.len()).min(chain.len() * cluster_size);
+        // the tail of the last cluster may hold anything
+        let zeros = vec![0u8; cluster_size];
+        let mut pos = size;
+        while pos < offset.min(end) {
+            let len = (offset.min(end) - pos).min(cluster_size);
+            self.fs.write_chain(&chain, pos, &zeros[..len]);
+            pos += len;
+        }
+        let written = end.saturating_sub(offset);
+        self.fs.write_chain(&chain, offset, &buf[..written]);
+        self.set_start_and_size(chain.first().copied().unwrap_or(0), size.max(end));
+        block_cache_sync_all();
+        written
+    }
+
+    /// Free the data of current file, nothing for a directory
+    /**
+        A directory is emptied by unlinking its entries instead.
+    */
+    pub fn clear(&self) {
+        if self.is_dir {
+            return;
+        }
+        let _guard = self.fs.lock.write();
+        let (first, _) = self.start_and_size();
+        self.fs.free_chain(first);
+        self.set_start_and_size(0, 0);
+        block_cache_sync_all();
+    }
+
+    /// Every entry of current directory, "." and ".." and the label included
+    fn slots(&self) -> Vec<DirSlot> {
+        let (first, _) = self.start_and_size();
+        let chain = self.fs.chain(first);
+        let mut data = vec![0u8; chain.len() * self.fs.cluster_size()];
+        self.fs.read_chain(&chain, 0, data.len(), &mut data);
+        let mut slots = Vec::new();
+        // long name entries met since the last 8.3 entry:
+        // checksum, count, order of the next one, characters
+        let mut long: Option<(u8, usize, usize, Vec<u16>)> = None;
+        for (index, raw) in data.chunks(DIR_ENTRY_SZ).enumerate() {
+            let entry: [u8; DIR_ENTRY_SZ] = raw.try_into().unwrap();
+            match entry[0] {
+                0 => break,
+                DELETED => {
+                    long = None;
+                    continue;
+                }
+                _ => {}
+            }
+            if entry[11] & 0x3f == ATTR_LONG_NAME {
+                let order = (entry[0] & !LAST_LONG_ENTRY) as usize;
+                if entry[0] & LAST_LONG_ENTRY != 0 {
+                    long = Some((entry[13], order, order, vec![0xffff; order * LONG_ENTRY_CHARS]));
+                }
+                match long.as_mut() {
+                    Some((checksum, _, next, units)) if *checksum == entry[13] && order >= 1 && order == *next => {
+                        for (i, offset) in LONG_ENTRY_OFFSETS.iter().enumerate() {
+                            units[(order - 1) * LONG_ENTRY_CHARS + i] = le16(&entry, *offset);
+                        }
+                        *next -= 1;
+                    }
+                    _ => long = None,
+                }
+                continue;
+            }
+            let long_name = long.take().and_then(|(checksum, count, next, units)| {
+                if checksum != short_name_checksum(&entry) || next != 0 {
+                    return None;
+                }
+                let end = units.iter().position(|unit| *unit == 0 || *unit == 0xffff).unwrap_or(units.len());
+                Some((
+                    char::decode_utf16(units[..end].iter().copied())
+                        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                        .collect::<String>(),
+                    count,
+                ))
+            });
+            let (name, first) = match long_name {
+                Some((name, count)) => (name, index - count),
+                None => (short_name_of(&entry), index),
+            };
+            slots.push(DirSlot { name, entry, index, first });
+        }
+        slots
+    }
+
+    /// Find inode under current inode by name, ignoring ASCII case
+    pub fn find(&self, name: &str) -> Option<Arc<FatInode>> {
+        let _guard = self.fs.lock.read();
+        let (first, _) = self.start_and_size();
+        let chain = self.fs.chain(first);
+        self.slots()
+            .into_iter()
+            .find(|slot| slot.matches(name))
+            .map(|slot| self.child(&chain, &slot))
+    }
+
+    fn child(&self, chain: &[u32], slot: &DirSlot) -> Arc<FatInode> {
+        Arc::new(FatInode {
+            fs: Arc::clone(&self.fs),
+            entry: Some(self.fs.data_pos(chain, slot.index * DIR_ENTRY_SZ)),
+            is_dir: slot.is_dir(),
+        })
+    }
+
+    /// List inodes under current inode, but "." and ".."
+    pub fn ls(&self) -> Vec<String> {
+        let _guard = self.fs.lock.read();
+        self.slots()
+            .into_iter()
+            .filter(|slot| !slot.is_label())
+            .map(|slot| slot.name)
+            .filter(|name| name != "." && name != "..")
+            .collect()
+    }
+
+    /// Create a file under current inode by name
+    /**
+        None if the name is taken or not a valid long name, or if the
+        volume has no cluster left for it.
+    */
+    pub fn create(&self, name: &str) -> Option<Arc<FatInode>> {
+        self.create_entry(name, false)
+    }
+
+    /// Create a directory under current inode by name
+    pub fn create_dir(&self, name: &str) -> Option<Arc<FatInode>> {
+        self.create_entry(name, true)
+    }
+
+    fn create_entry(&self, name: &str, is_dir: bool) -> Option<Arc<FatInode>> {
+        assert!(self.is_dir);
+        if !is_valid_long_name(name) {
+            return None;
+        }
+        let _guard = self.fs.lock.write();
+        let slots = self.slots();
+        if slots.iter().any(|slot| slot.matches(name)) {
+            return None;
+        }
+        let (short_name, mut entries) = match as_short_name(name) {
+            Some(short_name) => (short_name, Vec::new()),
+            None => {
+                let taken: Vec<[u8; 11]> = slots
+                    .iter()
+                    .map(|slot| slot.entry[..11].try_into().unwrap())
+                    .collect();
+                let short_name = numbered_short_name(name, &taken);
+                (short_name, long_entries(name, short_name_checksum(&short_name)))
+            }
+        };
+        let mut entry = [0u8; DIR_ENTRY_SZ];
+        entry[..11].copy_from_slice(&short_name);
+        entry[11] = if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE };
+        put16(&mut entry, 16, FAT_DATE);
+        put16(&mut entry, 18, FAT_DATE);
+        put16(&mut entry, 24, FAT_DATE);
+        let (dir_first, _) = self.start_and_size();
+        let mut dir_cluster = None;
+        if is_dir {
+            let cluster = self.fs.alloc_cluster()?;
+            dir_cluster = Some(cluster);
+            put16(&mut entry, 20, (cluster >> 16) as u16);
+            put16(&mut entry, 26, cluster as u16);
+            let mut dot = entry;
+            dot[..11].copy_from_slice(b".          ");
+            // ".." of a directory under the root points at cluster 0
+            let parent = if dir_first == self.fs.root_cluster { 0 } else { dir_first };
+            let mut dot_dot = dot;
+            dot_dot[..11].copy_from_slice(b"..         ");
+            put16(&mut dot_dot, 20, (parent >> 16) as u16);
+            put16(&mut dot_dot, 26, parent as u16);
+            let first_sector = self.fs.first_sector_of(cluster) as usize;
+            self.fs.write_entry((first_sector, 0), &dot);
+            self.fs.write_entry((first_sector, DIR_ENTRY_SZ), &dot_dot);
+        }
+        entries.push(entry);
+        // the first run of free entries long enough, else the end of the directory
+        let mut chain = self.fs.chain(dir_first);
+        let used: Vec<bool> = {
+            let mut used = vec![false; chain.len() * self.fs.cluster_size() / DIR_ENTRY_SZ];
+            for slot in slots.iter() {
+                used[slot.first..=slot.index].iter_mut().for_each(|used| *used = true);
+            }
+            used
+        };
+        let mut start = 0;
+        for (index, used) in used.iter().enumerate() {
+            if *used {
+                start = index + 1;
+            } else if index + 1 - start == entries.len() {
+                break;
+            }
+        }
+        let end = start + entries.len();
+        let per_cluster = self.fs.cluster_size() / DIR_ENTRY_SZ;
+        self.fs.extend_chain(&mut chain, end.div_ceil(per_cluster));
+        if chain.len() < end.div_ceil(per_cluster) {
+            // the volume is full, clusters added to the directory stay zeroed
+            if let Some(cluster) = dir_cluster {
+                self.fs.free_chain(cluster);
+            }
+            block_cache_sync_all();
+            return None;
+        }
+        for (index, entry) in (start..end).zip(entries.iter()) {
+            self.fs.write_entry(self.fs.data_pos(&chain, index * DIR_ENTRY_SZ), entry);
+        }
+        block_cache_sync_all();
+        Some(Arc::new(FatInode {
+            fs: Arc::clone(&self.fs),
+            entry: Some(self.fs.data_pos(&chain, (end - 1) * DIR_ENTRY_SZ)),
+            is_dir,
+        }))
+    }
+
+    /// Remove the entry `name` from current inode and free its clusters
+    /**
+        Returns false if there is no such entry, or if it is a directory
+        that is not empty.
+    */
+    pub fn unlink(&self, name: &str) -> bool {
+        assert!(self.is_dir);
+        let _guard = self.fs.lock.write();
+        let slots = self.slots();
+        let Some(slot) = slots
+            .iter()
+            .find(|slot| slot.matches(name) && slot.name != "." && slot.name != "..")
+        else {
+            return false;
+        };
+        let (dir_first, _) = self.start_and_size();
+        let chain = self.fs.chain(dir_first);
+        if slot.is_dir() {
+            let child = self.child(&chain, slot);
+            if child.slots().iter().any(|slot| !slot.is_label() && slot.name != "." && slot.name != "..") {
+                return false;
+            }
+        }
+        self.fs.free_chain(slot.first_cluster());
+        for index in slot.first..=slot.index {
+            let pos = self.fs.data_pos(&chain, index * DIR_ENTRY_SZ);
+            let mut entry = self.fs.read_entry(pos);
+            entry[0] = DELETED;
+            self.fs.write_entry(pos, &entry);
+        }
+        block_cache_sync_all();
+        true
+    }
+}
+
+impl FsInode for FatInode {
+    /// Where the 8.3 entry is, counted in entries from the start of the
+    /// volume; 0 for the root directory, which has none
+    fn inode_id(&self) -> u32 {
+        self.entry.map_or(0, |(sector, offset)| {
+            ((sector * self.fs.sector_size + offset) / DIR_ENTRY_SZ) as u32
+        })
+    }
+
+    fn is_dir(&self) -> bool {
+        FatInode::is_dir(self)
+    }
+
+    fn is_file(&self) -> bool {
+        FatInode::is_file(self)
+    }
+
+    fn size(&self) -> usize {
+        FatInode::size(self)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        FatInode::read_at(self, offset, buf)
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        FatInode::find(self, name).map(|inode| inode as Arc<dyn FsInode>)
+    }
+
+    fn ls(&self) -> Vec<String> {
+        FatInode::ls(self)
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        FatInode::write_at(self, offset, buf)
+    }
+
+    fn clear(&self) {
+        FatInode::clear(self)
+    }
+
+    fn create(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        FatInode::create(self, name).map(|inode| inode as Arc<dyn FsInode>)
+    }
+
+    fn create_dir(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        FatInode::create_dir(self, name).map(|inode| inode as Arc<dyn FsInode>)
+    }
+
+    fn unlink(&self, name: &str) -> bool {
+        FatInode::unlink(self, name)
+    }
+}
@@ -0,0 +1,101 @@
+//!A filesystem seen through its inodes
+/*!
+  [`FsInode`] is what users of a filesystem need from an inode, so that
+  the kernel works the same on every filesystem it can mount: easy-fs
+  implements it with [`crate::Inode`], read-only ext2 with
+  [`crate::ext2::Ext2Inode`] and FAT32 with [`crate::fat32::FatInode`].
+  A device is mounted by [`mount_root`], which picks the driver from the
+  magic number of the image.
+*/
+use super::{ext2::Ext2FileSystem, fat32::Fat32FileSystem, BlockDevice, EasyFileSystem};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// An inode of any supported filesystem
+/**
+    The writing methods have defaults for read-only filesystems,
+    which write nothing and create nothing.
+*/
+pub trait FsInode: Send + Sync {
+    /// The inode number of current inode
+    fn inode_id(&self) -> u32;
+    /// Whether current inode is a directory
+    fn is_dir(&self) -> bool;
+    /// Whether current inode is a regular file
+    fn is_file(&self) -> bool;
+    /// Size of the data in current inode
+    fn size(&self) -> usize;
+    /// Read data from current inode
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// Find inode under current inode by name
+    fn find(&self, name: &str) -> Option<Arc<dyn FsInode>>;
+    /// List inodes under current inode
+    fn ls(&self) -> Vec<String>;
+    /// Number of directory entries naming current inode
+    /**
+        Filesystems without hard links count one for every inode.
+    */
+    fn nlink(&self) -> u32 {
+        1
+    }
+    /// Whether nothing can be written on the filesystem
+    fn is_read_only(&self) -> bool {
+        true
+    }
+    /// Write data to current inode, return how many bytes were written
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    /// Clear the data in current inode
+    fn clear(&self) {}
+    /// Create a file under current inode by name
+    fn create(&self, _name: &str) -> Option<Arc<dyn FsInode>> {
+        None
+    }
+    /// Create a directory under current inode by name
+    fn create_dir(&self, _name: &str) -> Option<Arc<dyn FsInode>> {
+        None
+    }
+    /// Remove the entry `name` from current inode
+    fn unlink(&self, _name: &str) -> bool {
+        false
+    }
+}
+
+/// The filesystems a device can hold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsType {
+    /// easy-fs
+    Easy,
+    /// ext2, read-only
+    Ext2,
+    /// FAT32
+    Fat32,
+}
+
+/// Tell which filesystem a device holds from its magic number
+pub fn probe(block_device: &Arc<dyn BlockDevice>) -> Option<FsType> {
+    if EasyFileSystem::raw_super_block(block_device).is_valid() {
+        Some(FsType::Easy)
+    } else if Ext2FileSystem::raw_super_block(block_device).is_valid() {
+        Some(FsType::Ext2)
+    } else if Fat32FileSystem::raw_boot_sector(block_device).is_valid() {
+        Some(FsType::Fat32)
+    } else {
+        None
+    }
+}
+
+/// Mount the filesystem a device holds and return its root
+/**
+    Returns `None` if no supported filesystem is found.
+    An encrypted easy-fs image has to be opened with its key instead.
+*/
+pub fn mount_root(block_device: Arc<dyn BlockDevice>) -> Option<Arc<dyn FsInode>> {
+    Some(match probe(&block_device)? {
+        FsType::Easy => EasyFileSystem::root_inode(&EasyFileSystem::open(block_device)),
+        FsType::Ext2 => Ext2FileSystem::root_inode(&Ext2FileSystem::open(block_device)),
+        FsType::Fat32 => Fat32FileSystem::root_inode(&Fat32FileSystem::open(block_device)),
+    })
+}
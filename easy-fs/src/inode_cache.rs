@@ -0,0 +1,150 @@
+//!Inode cache => one Inode per inode number
+/*!
+  Every vfs handle on the same inode is the same `Arc<Inode>`, so that the
+  openers of a file share its size and its lock. The cache only holds weak
+  references: an inode leaves it when its last handle drops, after writing
+  back its dirty metadata. Directories looked up recently are also pinned,
+  that is their [`InodeCore`] is held by the cache itself, so that walking
+  the same paths again does not reload them. A core does not own the
+  filesystem, so pinning makes no reference cycle with it.
+*/
+use super::vfs::InodeCore;
+use super::{EasyFileSystem, Inode};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+
+/// number of directories pinned when the filesystem does not choose
+pub const DEFAULT_PINNED_DIRS: usize = 8;
+
+/// Inodes of one filesystem with a vfs handle, keyed by inode number
+pub struct InodeCache {
+    inodes: Mutex<BTreeMap<u32, Weak<Inode>>>,
+    /// most recently looked up directory first
+    pinned: Mutex<VecDeque<Arc<InodeCore>>>,
+    pin_limit: AtomicUsize,
+}
+
+impl Default for InodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InodeCache {
+    /// Create an empty cache pinning `DEFAULT_PINNED_DIRS` directories
+    pub fn new() -> Self {
+        Self {
+            inodes: Mutex::new(BTreeMap::new()),
+            pinned: Mutex::new(VecDeque::new()),
+            pin_limit: AtomicUsize::new(DEFAULT_PINNED_DIRS),
+        }
+    }
+
+    /// Get the handle of `inode_id`, loading the inode if it has none
+    /**
+        An inode whose last handle is dropping is waited for, so that its
+        metadata is written back before it is loaded again.
+    */
+    pub fn get(&self, fs: &Arc<EasyFileSystem>, inode_id: u32) -> Arc<Inode> {
+        let inode = loop {
+            let mut inodes = self.inodes.lock();
+            match inodes.get(&inode_id) {
+                Some(weak) => {
+                    if let Some(inode) = weak.upgrade() {
+                        break inode;
+                    }
+                }
+                None => {
+                    let pinned = self.pinned.lock().iter().find(|core| core.id() == inode_id).cloned();
+                    // a pinned directory is not loaded again
+                    let inode = Arc::new(match pinned {
+                        Some(core) => Inode::wrap(core, Arc::clone(fs)),
+                        None => Inode::load(inode_id, Arc::clone(fs)),
+                    });
+                    inodes.insert(inode_id, Arc::downgrade(&inode));
+                    break inode;
+                }
+            }
+            drop(inodes);
+            core::hint::spin_loop();
+        };
+        if inode.is_dir() {
+            self.pin(&inode);
+        }
+        inode
+    }
+
+    /// Put a directory first in the pinned ones
+    fn pin(&self, inode: &Arc<Inode>) {
+        let limit = self.pin_limit.load(Ordering::Relaxed);
+        let core = inode.core();
+        let mut pinned = self.pinned.lock();
+        if let Some(index) = pinned.iter().position(|p| Arc::ptr_eq(p, core)) {
+            pinned.remove(index);
+        }
+        pinned.push_front(Arc::clone(core));
+        pinned.truncate(limit);
+    }
+
+    /// Pin at most `limit` directories, 0 turns pinning off
+    pub fn set_pin_limit(&self, limit: usize) {
+        self.pin_limit.store(limit, Ordering::Relaxed);
+        self.pinned.lock().truncate(limit);
+    }
+
+    /// Unpin every directory, keeping the limit
+    pub fn unpin_all(&self) {
+        self.pinned.lock().clear();
+    }
+
+    /// Every inode that has a handle
+    pub fn handles(&self) -> Vec<Arc<Inode>> {
+        self.inodes.lock().values().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Number of inodes in memory, those with a handle and the pinned ones
+    pub fn len(&self) -> usize {
+        let mut ids: Vec<u32> = self
+            .inodes
+            .lock()
+            .iter()
+            .filter(|(_, weak)| weak.strong_count() > 0)
+            .map(|(id, _)| *id)
+            .collect();
+        ids.extend(self.pinned.lock().iter().map(|core| core.id()));
+        ids.sort_unstable();
+        ids.dedup();
+        ids.len()
+    }
+
+    /// Whether no inode is in memory
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Take `inode` out of the cache, e.g. when its inode number is freed
+    /**
+        Later lookups of the same number load a new inode, while the
+        handles still held on `inode` keep it alive apart from the cache.
+    */
+    pub fn forget(&self, inode: &Arc<Inode>) {
+        {
+            let mut pinned = self.pinned.lock();
+            if let Some(index) = pinned.iter().position(|p| Arc::ptr_eq(p, inode.core())) {
+                pinned.remove(index);
+            }
+        }
+        self.release(inode.inode_id(), Arc::as_ptr(inode));
+    }
+
+    /// Drop the entry of `inode_id` if it is still the one of `inode`
+    pub(crate) fn release(&self, inode_id: u32, inode: *const Inode) {
+        let mut inodes = self.inodes.lock();
+        if inodes.get(&inode_id).is_some_and(|weak| Weak::as_ptr(weak) == inode) {
+            inodes.remove(&inode_id);
+        }
+    }
+}
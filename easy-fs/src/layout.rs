@@ -1,39 +1,183 @@
 //!Rom layout
 //![super_block][inode_bitmap][inode_area][data_bitmap][data_area]
+use super::{get_block_cache, BlockDevice, MIN_BLOCK_SZ};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
 
 /// magic number for sanity check
 const EFS_MAGIC: u32 = 0x3b800001;
+/// revision of the on-disk format, images of revision 0 have no feature fields
+pub const FORMAT_VERSION: u32 = 1;
+/// incompat feature: the block size is not 512
+pub const FEATURE_INCOMPAT_BLOCK_SIZE: u32 = 1 << 0;
+/// incompat feature: some files are stored compressed, see `compress.rs`
+pub const FEATURE_INCOMPAT_COMPRESSION: u32 = 1 << 1;
+/// incompat feature: file data is encrypted, see `crypt.rs`
+pub const FEATURE_INCOMPAT_ENCRYPTION: u32 = 1 << 2;
+/// incompat feature: some data blocks are allocated but unwritten
+pub const FEATURE_INCOMPAT_UNWRITTEN: u32 = 1 << 3;
+/// incompat feature: a grow of the image is under way, see `resize.rs`
+pub const FEATURE_INCOMPAT_RESIZE: u32 = 1 << 4;
+/// compat features this code knows, others are ignored
+pub const FEATURE_COMPAT_SUPPORTED: u32 = 0;
+/// ro-compat features this code knows, others only allow a read-only mount
+pub const FEATURE_RO_COMPAT_SUPPORTED: u32 = 0;
+/// incompat features this code knows, others forbid the mount
+pub const FEATURE_INCOMPAT_SUPPORTED: u32 = FEATURE_INCOMPAT_BLOCK_SIZE
+    | FEATURE_INCOMPAT_COMPRESSION
+    | FEATURE_INCOMPAT_ENCRYPTION
+    | FEATURE_INCOMPAT_UNWRITTEN
+    | FEATURE_INCOMPAT_RESIZE;
+/// block sizes that can be chosen when an image is created
+pub const BLOCK_SIZES: [usize; 4] = [512, 1024, 2048, 4096];
 /// disk_inode <=> data_block
 /** [INODE_*_COUNT]:
     The number of actual file data blocks represented by direct index and indirect index;
     [*_BOUND]:
     The index value in the block space pointed to by 'DiskNode'
-    is represented as the index block of the file
+    is represented as the index block of the file.
+    Counts and bounds of the indirect index depend on the block size,
+    see [`Geometry`].
 */
 const INODE_DIRECT_COUNT: usize = 28;
-const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
-const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
-const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
-const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
 /// the max length of inode name
-const NAME_LENGTH_LIMIT: usize = 27;
+pub const NAME_LENGTH_LIMIT: usize = 27;
+/// flag of a disk inode whose data is stored compressed, see `compress.rs`
+const INODE_FLAG_COMPRESSED: u8 = 1 << 0;
+/// high bit of a data block id in the index of a disk inode:
+/// the block is allocated but unwritten and reads as zeros
+pub const BLOCK_UNWRITTEN: u32 = 1 << 31;
+/// Size of a disk inode
+const DISK_INODE_SZ: usize = core::mem::size_of::<DiskInode>();
+
+/// Block size of a mounted image and the constants derived from it
+/**
+    Everything here is computed from `SuperBlock::block_size` when the
+    filesystem is created or opened, and then passed to the layout code.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    /// number of bytes in a block
+    pub block_size: usize,
+    /// number of block ids held by an indirect block
+    pub indirect1_count: usize,
+    /// number of data blocks reachable through indirect2
+    pub indirect2_count: usize,
+    /// first inner id that is not reachable through indirect1
+    pub indirect1_bound: usize,
+    /// first inner id that is not reachable through indirect2
+    pub indirect2_bound: usize,
+    /// number of bits in a bitmap block
+    pub block_bits: usize,
+    /// number of disk inodes in an inode block
+    pub inodes_per_block: usize,
+}
+
+impl Geometry {
+    /// derive the geometry of `block_size`, which must be one of [`BLOCK_SIZES`]
+    pub fn new(block_size: usize) -> Self {
+        assert!(BLOCK_SIZES.contains(&block_size),
+            "unsupported block size {}", block_size);
+        let indirect1_count = block_size / 4;
+        let indirect2_count = indirect1_count * indirect1_count;
+        let indirect1_bound = DIRECT_BOUND + indirect1_count;
+        Self {
+            block_size,
+            indirect1_count,
+            indirect2_count,
+            indirect1_bound,
+            indirect2_bound: indirect1_bound + indirect2_count,
+            block_bits: block_size * 8,
+            inodes_per_block: block_size / DISK_INODE_SZ,
+        }
+    }
+
+    /// largest size of a file, in bytes
+    /**
+        This is what the direct and indirect indexes can address, but no
+        more than a `u32` size can tell.
+    */
+    pub fn max_file_size(&self) -> usize {
+        (self.indirect2_bound * self.block_size).min(u32::MAX as usize)
+    }
+
+    /// number of inode bitmap blocks to ask for to get at least `inodes` inodes
+    pub fn inode_bitmap_blocks(&self, inodes: u32) -> u32 {
+        (inodes as usize).div_ceil(self.block_bits).max(1) as u32
+    }
+
+    /// sizes of the inode area, the data bitmap and the data area
+    /**
+        These are how `total_blocks` are shared out when a filesystem is
+        created with `inode_bitmap_blocks`.
+    */
+    pub fn areas(&self, total_blocks: u32, inode_bitmap_blocks: u32) -> (u32, u32, u32) {
+        let inode_num = inode_bitmap_blocks as usize * self.block_bits;
+        let inode_area_blocks = inode_num.div_ceil(self.inodes_per_block) as u32;
+        let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
+        assert!(total_blocks > 1 + inode_total_blocks,
+            "{} blocks cannot hold {} inodes", total_blocks, inode_num);
+        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
+        let data_bitmap_blocks = data_total_blocks.div_ceil(self.block_bits as u32 + 1);
+        (inode_area_blocks, data_bitmap_blocks, data_total_blocks - data_bitmap_blocks)
+    }
+
+    /// smallest total number of blocks leaving `data_blocks` in the data area
+    /**
+        This follows how the areas are laid out when a filesystem is
+        created with `inode_bitmap_blocks`.
+    */
+    pub fn total_blocks(&self, inode_bitmap_blocks: u32, data_blocks: u32) -> u32 {
+        let inode_num = inode_bitmap_blocks as usize * self.block_bits;
+        let inode_area_blocks = inode_num.div_ceil(self.inodes_per_block) as u32;
+        let data_bitmap_blocks = (data_blocks as usize).div_ceil(self.block_bits) as u32;
+        1 + inode_bitmap_blocks + inode_area_blocks + data_bitmap_blocks + data_blocks
+    }
+}
 
 /// super_block
+/**
+    Fields are only ever appended, an image written before a field existed
+    reads it as zero.
+*/
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct SuperBlock {
     magic: u32,
+    /// total number of blocks in the image
     pub total_blocks: u32,
+    /// number of blocks of the inode bitmap
     pub inode_bitmap_blocks: u32,
+    /// number of blocks of the inode area
     pub inode_area_blocks: u32,
+    /// number of blocks of the data bitmap
     pub data_bitmap_blocks: u32,
+    /// number of blocks of the data area
     pub data_area_blocks: u32,
+    /// number of bytes in a block, chosen when the image is created
+    pub block_size: u32,
+    /// whether file data is encrypted, see `crypt.rs`
+    encrypted: u32,
+    /// tells whether the key given at open time is the one of the image
+    key_check: [u8; 16],
+    /// revision of the format, see [`FORMAT_VERSION`]
+    pub version: u32,
+    /// features an older code can ignore
+    pub feature_compat: u32,
+    /// features an older code can read but must not write
+    pub feature_ro_compat: u32,
+    /// features an older code cannot read
+    pub feature_incompat: u32,
+    /// step a grow is at, zero when there is none
+    pub resize_state: u32,
+    /// first descriptor block of the grow under way
+    pub resize_journal: u32,
 }
 
 impl SuperBlock {
+    /// initialize a super block
     pub fn initialize(
         &mut self,
         total_blocks: u32,
@@ -41,18 +185,69 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        block_size: u32,
     ) {
         *self = Self {
+            magic: EFS_MAGIC,
             total_blocks,
             inode_bitmap_blocks,
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            block_size,
+            encrypted: 0,
+            key_check: [0; 16],
+            version: FORMAT_VERSION,
+            feature_compat: 0,
+            feature_ro_compat: 0,
+            feature_incompat: if block_size as usize == MIN_BLOCK_SZ {
+                0
+            } else {
+                FEATURE_INCOMPAT_BLOCK_SIZE
+            },
+            resize_state: 0,
+            resize_journal: 0,
         }
     }
 
+    /// mark file data as encrypted by a key whose check value is `key_check`
+    pub fn set_encrypted(&mut self, key_check: [u8; 16]) {
+        self.encrypted = 1;
+        self.key_check = key_check;
+        self.feature_incompat |= FEATURE_INCOMPAT_ENCRYPTION;
+    }
+
+    /// whether the image was made before feature fields existed
+    pub fn is_legacy(&self) -> bool {
+        self.version == 0
+    }
+
+    /// move a legacy image to the current revision with `feature_incompat`
+    pub fn upgrade(&mut self, feature_incompat: u32) {
+        self.version = FORMAT_VERSION;
+        self.feature_compat = 0;
+        self.feature_ro_compat = 0;
+        self.feature_incompat = feature_incompat;
+    }
+
+    /// the key check value if file data is encrypted
+    pub fn key_check(&self) -> Option<[u8; 16]> {
+        if self.encrypted != 0 {
+            Some(self.key_check)
+        } else {
+            None
+        }
+    }
+
+    /// check the magic number and the recorded block size
     pub fn is_valid(&self) -> bool {
         self.magic == EFS_MAGIC
+            && BLOCK_SIZES.contains(&(self.block_size as usize))
+    }
+
+    /// geometry of the image
+    pub fn geometry(&self) -> Geometry {
+        Geometry::new(self.block_size as usize)
     }
 }
 
@@ -64,128 +259,246 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("block_size", &self.block_size)
+            .field("encrypted", &(self.encrypted != 0))
+            .field("version", &self.version)
+            .field("feature_compat", &self.feature_compat)
+            .field("feature_ro_compat", &self.feature_ro_compat)
+            .field("feature_incompat", &self.feature_incompat)
+            .field("resize_state", &self.resize_state)
             .finish()
     }
 }
 
+/// the super block always fits in the smallest block
+const _: () = assert!(core::mem::size_of::<SuperBlock>() <= MIN_BLOCK_SZ);
+
 /// type of disk_inode => {File/Directory}
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum DiskInodeType {
+    /// regular file
     File,
+    /// directory
     Directory,
 }
 
-type IndirectBlock = [u32; BLOCK_SZ / 4];
-type DataBlock = [u8; BLOCK_SZ];
-
 /// struct disk_inode
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct DiskInode {
+    /// size of the file in bytes
     pub size: u32,
+    /// direct index
     pub direct: [u32; INODE_DIRECT_COUNT],
+    /// block id of the indirect1 index block
     pub indirect1: u32,
+    /// block id of the indirect2 index block
     pub indirect2: u32,
     type_: DiskInodeType,
+    flags: u8,
+}
+
+/// a disk inode takes the same room whatever flags it gets
+const _: () = assert!(DISK_INODE_SZ == 128);
+
+impl Debug for DiskInode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_struct("DiskInode")
+            .field("type", &self.type_)
+            .field("size", &self.size)
+            .field("compressed", &self.is_compressed())
+            .field("indirect1", &self.indirect1)
+            .field("indirect2", &self.indirect2)
+            .finish()
+    }
 }
 
 impl DiskInode {
+    /// initialize a disk inode as an empty file or directory
     pub fn initialize(&mut self, type_: DiskInodeType) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
         self.type_ = type_;
+        self.flags = 0;
     }
 
+    /// whether this inode is a directory
     pub fn is_dir(&self) -> bool {
         self.type_ == DiskInodeType::Directory
     }
 
+    /// whether this inode is a regular file
     pub fn is_file(&self) -> bool {
         self.type_ == DiskInodeType::File
     }
 
-    pub fn data_blocks(&self) -> u32 {
-        Self::_data_blocks(self.size)
+    /// whether the data is stored compressed
+    pub fn is_compressed(&self) -> bool {
+        self.flags & INODE_FLAG_COMPRESSED != 0
     }
 
-    fn _data_blocks(size: u32) -> u32 {
-        (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32 
+    /// mark the data as stored compressed or not
+    pub fn set_compressed(&mut self, compressed: bool) {
+        if compressed {
+            self.flags |= INODE_FLAG_COMPRESSED;
+        } else {
+            self.flags &= !INODE_FLAG_COMPRESSED;
+        }
+    }
+
+    /// number of data blocks holding the file data
+    pub fn data_blocks(&self, geo: &Geometry) -> u32 {
+        Self::_data_blocks(self.size, geo)
+    }
+
+    fn _data_blocks(size: u32, geo: &Geometry) -> u32 {
+        size.div_ceil(geo.block_size as u32)
     }
 
     /// return total number of blocks needed include indirect1/2
-    /** 
+    /**
         The data block area contains not only the file data,
         but also the index information of the file data block in some cases.
     */
-    pub fn total_blocks(size: u32) -> u32 {
-        let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks as usize;
+    pub fn total_blocks(size: u32, geo: &Geometry) -> u32 {
+        let data_blocks = Self::_data_blocks(size, geo) as usize;
+        let mut total = data_blocks;
         // indirect1
         if data_blocks > INODE_DIRECT_COUNT {
             total += 1;
         }
         // indirect2 => 1 * indirect2 + n * indirect1
-        if data_blocks > INDIRECT1_BOUND {
+        if data_blocks > geo.indirect1_bound {
             total += 1;
-            total += (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1)
-                        / INODE_INDIRECT1_COUNT;
+            total += (data_blocks - geo.indirect1_bound).div_ceil(geo.indirect1_count);
         }
         total as u32
     }
 
-    /// get the number of data blocks that have to be allocated 
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size as u32);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
+    /// get the number of data blocks that have to be allocated
+    pub fn blocks_num_needed(&self, new_size: u32, geo: &Geometry) -> u32 {
+        assert!(new_size >= self.size);
+        Self::total_blocks(new_size, geo) - Self::total_blocks(self.size, geo)
     }
 
     /// get global block_id given inner_id
     /// inner_id => inner id of disk_inode pointed to file data_block area. [0.._data_blocks(size)]
-    pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+    pub fn get_block_id(
+        &self,
+        inner_id: u32,
+        geo: &Geometry,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> u32 {
+        self.get_block_entry(inner_id, geo, block_device) & !BLOCK_UNWRITTEN
+    }
+
+    /// whether the data block of inner_id is allocated but unwritten
+    pub fn is_unwritten(
+        &self,
+        inner_id: u32,
+        geo: &Geometry,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> bool {
+        self.get_block_entry(inner_id, geo, block_device) & BLOCK_UNWRITTEN != 0
+    }
+
+    /// get the index entry of inner_id, block id and [`BLOCK_UNWRITTEN`]
+    pub(crate) fn get_block_entry(
+        &self,
+        inner_id: u32,
+        geo: &Geometry,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> u32 {
         let inner_id = inner_id as usize;
         if inner_id < INODE_DIRECT_COUNT {
             self.direct[inner_id]
-        } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+        } else if inner_id < geo.indirect1_bound {
+            get_block_cache(self.indirect1 as usize, geo.block_size, Arc::clone(block_device))
                 .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
+                .read_slice(|indirect_block: &[u32]| {
                     indirect_block[inner_id - INODE_DIRECT_COUNT]
                 })
         } else {
-            let last = inner_id - INDIRECT1_BOUND;
+            let last = inner_id - geo.indirect1_bound;
             // find indirect1
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+            let indirect1 = get_block_cache(self.indirect2 as usize, geo.block_size, Arc::clone(block_device))
                 .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[last / INODE_INDIRECT1_COUNT]
+                .read_slice(|indirect_block: &[u32]| {
+                    indirect_block[last / geo.indirect1_count]
                 });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
+            get_block_cache(indirect1 as usize, geo.block_size, Arc::clone(block_device))
                 .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[last % INODE_INDIRECT1_COUNT]
+                .read_slice(|indirect_block: &[u32]| {
+                    indirect_block[last % geo.indirect1_count]
                 })
         }
     }
 
+    /// set the index entry of inner_id, which must already be mapped
+    pub(crate) fn set_block_entry(
+        &mut self,
+        inner_id: u32,
+        entry: u32,
+        geo: &Geometry,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        let inner_id = inner_id as usize;
+        if inner_id < INODE_DIRECT_COUNT {
+            self.direct[inner_id] = entry;
+        } else if inner_id < geo.indirect1_bound {
+            get_block_cache(self.indirect1 as usize, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .modify_slice(|indirect_block: &mut [u32]| {
+                    indirect_block[inner_id - INODE_DIRECT_COUNT] = entry;
+                });
+        } else {
+            let last = inner_id - geo.indirect1_bound;
+            let indirect1 = get_block_cache(self.indirect2 as usize, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .read_slice(|indirect_block: &[u32]| {
+                    indirect_block[last / geo.indirect1_count]
+                });
+            get_block_cache(indirect1 as usize, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .modify_slice(|indirect_block: &mut [u32]| {
+                    indirect_block[last % geo.indirect1_count] = entry;
+                });
+        }
+    }
+
+    /// mark the data blocks from inner_id `from` to the end as unwritten
+    /**
+        Used after increase_size to preallocate blocks: they keep whatever
+        they hold on disk but read as zeros until they are first written.
+    */
+    pub fn mark_unwritten(&mut self, from: u32, geo: &Geometry, block_device: &Arc<dyn BlockDevice>) {
+        for inner_id in from..self.data_blocks(geo) {
+            let entry = self.get_block_entry(inner_id, geo, block_device);
+            self.set_block_entry(inner_id, entry | BLOCK_UNWRITTEN, geo, block_device);
+        }
+    }
+
     /// increase the size of current disk_inode
     /**
         new_size: when writing data to a file, new_size =  old_size + write_data_len
-        new_blocks: increase_size is only responsible for maintaining the relationship 
-                    between block numbers and indexes in the disk_inode, so the available 
-                    blocks need to be allocated in advance and passed as parameters before 
+        new_blocks: increase_size is only responsible for maintaining the relationship
+                    between block numbers and indexes in the disk_inode, so the available
+                    blocks need to be allocated in advance and passed as parameters before
                     calling this function
-    */ 
+    */
     pub fn increase_size(
         &mut self,
         new_size: u32,
         new_blocks: Vec<u32>,
+        geo: &Geometry,
         block_device: &Arc<dyn BlockDevice>,
     ) {
        // these blocks is used to store file data
-       let mut current_blocks = self.data_blocks();
+       let mut current_blocks = self.data_blocks(geo);
        self.size = new_size;
-       let mut total_blocks = self.data_blocks();
+       let mut total_blocks = self.data_blocks(geo);
        let mut new_blocks = new_blocks.into_iter();
        // fill direct
        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
@@ -193,7 +506,7 @@ impl DiskInode {
            current_blocks += 1;
        }
        // alloc indirect1
-       if total_blocks > INODE_DIRECT_COUNT {
+       if total_blocks > INODE_DIRECT_COUNT as u32 {
            if current_blocks == INODE_DIRECT_COUNT as u32 {
                self.indirect1 = new_blocks.next().unwrap();
            }
@@ -203,45 +516,44 @@ impl DiskInode {
            return;
        }
        // fill indirect1
-       get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+       get_block_cache(self.indirect1 as usize, geo.block_size, Arc::clone(block_device))
            .lock()
-           .modify(0, |indirect1: &mut IndirectBlock| {
-               while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
+           .modify_slice(|indirect1: &mut [u32]| {
+               while current_blocks < total_blocks.min(geo.indirect1_count as u32) {
                    indirect1[current_blocks as usize] = new_blocks.next().unwrap();
                    current_blocks += 1;
                }
            });
        // alloc indirect2
-       if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-           if current_blocks == INODE_INDIRECT1_COUNT as u32 {
+       if total_blocks > geo.indirect1_count as u32 {
+           if current_blocks == geo.indirect1_count as u32 {
                self.indirect2 = new_blocks.next().unwrap();
-               
            }
-           current_blocks -= INODE_INDIRECT1_COUNT as u32;
-           total_blocks -= INODE_INDIRECT1_COUNT as u32;
+           current_blocks -= geo.indirect1_count as u32;
+           total_blocks -= geo.indirect1_count as u32;
        } else {
            return;
        }
        // fill indirect2
-       let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-       let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-       let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-       let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-       get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+       let mut a0 = current_blocks as usize / geo.indirect1_count;
+       let mut b0 = current_blocks as usize % geo.indirect1_count;
+       let a1 = total_blocks as usize / geo.indirect1_count;
+       let b1 = total_blocks as usize % geo.indirect1_count;
+       get_block_cache(self.indirect2 as usize, geo.block_size, Arc::clone(block_device))
            .lock()
-           .modify(0, |indirect2: &mut IndirectBlock| {
+           .modify_slice(|indirect2: &mut [u32]| {
                while (a0 < a1) || (a0 == a1 && b0 < b1) {
                    if b0 == 0 {
                        indirect2[a0] = new_blocks.next().unwrap();
                    }
-                   get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
+                   get_block_cache(indirect2[a0] as usize, geo.block_size, Arc::clone(block_device))
                        .lock()
-                       .modify(0, |indirect1: &mut IndirectBlock| {
-                          indirect1[b0] = new_blocks.next().unwrap(); 
+                       .modify_slice(|indirect1: &mut [u32]| {
+                          indirect1[b0] = new_blocks.next().unwrap();
                        });
                    // move to next
                    b0 += 1;
-                   if b0 == INODE_INDIRECT1_COUNT {
+                   if b0 == geo.indirect1_count {
                        b0 = 0;
                        a0 += 1;
                    }
@@ -251,14 +563,14 @@ impl DiskInode {
 
     /// clear size to zero and return blocks that should be deallocated
     /// we will clear the block contents to zero later
-    pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+    pub fn clear_size(&mut self, geo: &Geometry, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
         let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks as usize;
+        let mut data_blocks = self.data_blocks(geo) as usize;
         self.size = 0;
         let mut current_blocks: usize = 0;
         // direct
         while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
+            v.push(self.direct[current_blocks] & !BLOCK_UNWRITTEN);
             self.direct[current_blocks] = 0;
             current_blocks += 1;
         }
@@ -271,47 +583,47 @@ impl DiskInode {
             return v;
         }
         // indirect1 => data_blocks
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
+        get_block_cache(self.indirect1 as usize, geo.block_size, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-               while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                   v.push(indirect1[current_blocks]);
+            .read_slice(|indirect1: &[u32]| {
+               while current_blocks < data_blocks.min(geo.indirect1_count) {
+                   v.push(indirect1[current_blocks] & !BLOCK_UNWRITTEN);
                    current_blocks += 1;
-               } 
+               }
             });
         self.indirect1 = 0;
         // indirect2
-        if data_blocks > INODE_INDIRECT1_COUNT {
+        if data_blocks > geo.indirect1_count {
             v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
+            data_blocks -= geo.indirect1_count;
         } else {
             return v;
         }
         // indirect2 => data_blocks
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
+        assert!(data_blocks <= geo.indirect2_count);
+        let a1 = data_blocks / geo.indirect1_count;
+        let b1 = data_blocks % geo.indirect1_count;
+        get_block_cache(self.indirect2 as usize, geo.block_size, Arc::clone(block_device))
             .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-               for entry in indirect2.iter_mut().take(a1) {
+            .read_slice(|indirect2: &[u32]| {
+               for entry in indirect2.iter().take(a1) {
                    v.push(*entry);
-                   get_block_cache(*entry as usize, Arc::clone(block_device))
+                   get_block_cache(*entry as usize, geo.block_size, Arc::clone(block_device))
                        .lock()
-                       .modify(0, |indirect1: &mut IndirectBlock| {
+                       .read_slice(|indirect1: &[u32]| {
                           for entry in indirect1.iter() {
-                              v.push(*entry);
-                          } 
+                              v.push(*entry & !BLOCK_UNWRITTEN);
+                          }
                        });
                }
                if b1 > 0 {
                    v.push(indirect2[a1]);
-                   get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
+                   get_block_cache(indirect2[a1] as usize, geo.block_size, Arc::clone(block_device))
                        .lock()
-                       .modify(0, |indirect1: &mut IndirectBlock| {
+                       .read_slice(|indirect1: &[u32]| {
                           for entry in indirect1.iter().take(b1) {
-                              v.push(*entry);
-                          } 
+                              v.push(*entry & !BLOCK_UNWRITTEN);
+                          }
                        });
                }
             });
@@ -319,37 +631,116 @@ impl DiskInode {
         v
     }
 
+    /// decrease size to new_size and return blocks that should be deallocated
+    /**
+        Only the data blocks past the new end and the index blocks that no
+        longer point to any of the remaining data blocks are returned; the
+        tail of the new last block is zeroed so that a later increase_size
+        reads back zeros there.
+    */
+    pub fn decrease_size(
+        &mut self,
+        new_size: u32,
+        geo: &Geometry,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size);
+        let old_blocks = self.data_blocks(geo) as usize;
+        let new_blocks = Self::_data_blocks(new_size, geo) as usize;
+        // zero the tail of the last partial block
+        let tail = new_size as usize % geo.block_size;
+        if tail != 0 {
+            let block_id = self.get_block_id(new_blocks as u32 - 1, geo, block_device);
+            get_block_cache(block_id as usize, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .modify_slice(|data_block: &mut [u8]| {
+                    data_block[tail..].iter_mut().for_each(|p| *p = 0);
+                });
+        }
+        // data blocks
+        let mut v: Vec<u32> = (new_blocks..old_blocks)
+            .map(|inner_id| self.get_block_id(inner_id as u32, geo, block_device))
+            .collect();
+        for inner_id in new_blocks..old_blocks.min(INODE_DIRECT_COUNT) {
+            self.direct[inner_id] = 0;
+        }
+        // indirect1
+        if old_blocks > INODE_DIRECT_COUNT && new_blocks <= INODE_DIRECT_COUNT {
+            v.push(self.indirect1);
+            self.indirect1 = 0;
+        }
+        // indirect2 => indirect1 blocks
+        if old_blocks > geo.indirect1_bound {
+            let old_count = (old_blocks - geo.indirect1_bound).div_ceil(geo.indirect1_count);
+            let new_count = new_blocks
+                .saturating_sub(geo.indirect1_bound)
+                .div_ceil(geo.indirect1_count);
+            get_block_cache(self.indirect2 as usize, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .read_slice(|indirect2: &[u32]| {
+                    v.extend_from_slice(&indirect2[new_count..old_count]);
+                });
+            if new_count == 0 {
+                v.push(self.indirect2);
+                self.indirect2 = 0;
+            }
+        }
+        self.size = new_size;
+        v
+    }
+
     /// read data from current disk_inode
     pub fn read_at(
         &self,
         offset: usize,
         buf: &mut [u8],
+        geo: &Geometry,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> usize {
+        self.read_at_with(offset, buf, geo, block_device, |_, _, _| {})
+    }
+
+    /// read data from current disk_inode and pass it through `f`
+    /**
+        `f(inner_id, block_offset, bytes)` gets the bytes read from each data
+        block along with where they sit in the file, e.g. to decrypt them.
+    */
+    pub fn read_at_with(
+        &self,
+        offset: usize,
+        buf: &mut [u8],
+        geo: &Geometry,
         block_device: &Arc<dyn BlockDevice>,
+        mut f: impl FnMut(u32, usize, &mut [u8]),
     ) -> usize {
-        let start = offset;
-        let end = (offset + buf.len()).min(self.size);
+        let block_size = geo.block_size;
+        let mut start = offset;
+        let end = (offset + buf.len()).min(self.size as usize);
         if start >= end {
             return 0;
         }
-        let mut start_block = start / BLOCK_SZ;
+        let mut start_block = start / block_size;
         let mut read_size: usize = 0;
         // reading...
         loop {
             // determines whether each read reaches the boundary of a block
-            let mut end_current_block = (start / BLOCK_SZ + 1) * BLOCK_SZ;
+            let mut end_current_block = (start / block_size + 1) * block_size;
             end_current_block = end_current_block.min(end);
             // read and update read size
             let block_inner_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_inner_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_inner_read_size];
-                dst.copy_from_slice(src);
-            });
+            let entry = self.get_block_entry(start_block as u32, geo, block_device);
+            if entry & BLOCK_UNWRITTEN != 0 {
+                dst.fill(0);
+            } else {
+                get_block_cache(entry as usize, block_size, Arc::clone(block_device))
+                    .lock()
+                    .read_slice(|data_block: &[u8]| {
+                        let src = &data_block[start % block_size..start % block_size + block_inner_read_size];
+                        dst.copy_from_slice(src);
+                    });
+                f(start_block as u32, start % block_size, dst);
+            }
             read_size += block_inner_read_size;
             // move to next block
             if end_current_block == end {
@@ -367,29 +758,61 @@ impl DiskInode {
         &mut self,
         offset: usize,
         buf: &[u8],
+        geo: &Geometry,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> usize {
+        self.write_at_with(offset, buf, geo, block_device, |_, _, _| {})
+    }
+
+    /// write data into current disk_inode after passing it through `f`
+    /**
+        `f(inner_id, block_offset, bytes)` gets the bytes in the block cache
+        right after they are copied there, e.g. to encrypt them.
+    */
+    pub fn write_at_with(
+        &mut self,
+        offset: usize,
+        buf: &[u8],
+        geo: &Geometry,
         block_device: &Arc<dyn BlockDevice>,
+        mut f: impl FnMut(u32, usize, &mut [u8]),
     ) -> usize {
+        let block_size = geo.block_size;
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size);
+        let end = (offset + buf.len()).min(self.size as usize);
         assert!(start <= end);
-        let start_block = start / BLOCK_SZ;
-        let write_size: usize = 0;
+        if start == end {
+            return 0;
+        }
+        let mut start_block = start / block_size;
+        let mut write_size: usize = 0;
         // writting
         loop {
-            let mut end_current_block = (start / BLOCK_SZ + 1) * BLOCK_SZ;
+            let mut end_current_block = (start / block_size + 1) * block_size;
             end_current_block = end_current_block.min(end);
             let block_inner_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
-                let src = &buf[write_size..write_size + block_inner_write_size];
-                let index_s = start % BLOCK_SZ; 
-                let dst = &mut data_block[index_s..index_s + block_inner_write_size];
-                dst.copy_from_slice(src);
-            });
+            let entry = self.get_block_entry(start_block as u32, geo, block_device);
+            let block_id = entry & !BLOCK_UNWRITTEN;
+            let unwritten = entry & BLOCK_UNWRITTEN != 0;
+            get_block_cache(block_id as usize, block_size, Arc::clone(block_device))
+                .lock()
+                .modify_slice(|data_block: &mut [u8]| {
+                    let src = &buf[write_size..write_size + block_inner_write_size];
+                    let index_s = start % block_size;
+                    if unwritten {
+                        // first write to the block, the rest of it reads as zeros
+                        data_block.fill(0);
+                        data_block[index_s..index_s + block_inner_write_size].copy_from_slice(src);
+                        f(start_block as u32, 0, data_block);
+                    } else {
+                        let dst = &mut data_block[index_s..index_s + block_inner_write_size];
+                        dst.copy_from_slice(src);
+                        f(start_block as u32, index_s, dst);
+                    }
+                });
+            if unwritten {
+                self.set_block_entry(start_block as u32, block_id, geo, block_device);
+            }
             write_size += block_inner_write_size;
             if end_current_block == end {
                 break;
@@ -397,7 +820,7 @@ impl DiskInode {
             start_block += 1;
             start = end_current_block;
         }
-        write_size;
+        write_size
     }
 }
 
@@ -408,7 +831,7 @@ pub struct DirEntry {
     inode_number: u32,
 }
 /// Size of a directory entry
-pub const DIRECT_SZ: usize = 32;
+pub const DIRENT_SZ: usize = 32;
 
 impl DirEntry {
     /// create a empty directory entry
@@ -419,7 +842,12 @@ impl DirEntry {
         }
     }
     /// create a new directory entry from name and inode_number
+    /**
+        The name must be at most `NAME_LENGTH_LIMIT` bytes, leaving room
+        for the NUL that ends it.
+    */
     pub fn new(name: &str, inode_number: u32) -> Self {
+        assert!(name.len() <= NAME_LENGTH_LIMIT, "name {} is too long", name);
         let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
         bytes[..name.len()].copy_from_slice(name.as_bytes());
         Self {
@@ -427,31 +855,35 @@ impl DirEntry {
             inode_number,
         }
     }
-    
+
+    /// name of the entry
     pub fn name(&self) -> &str {
-        // get the real length of name_str
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap();
-        core::str::from_utf8(&self.name[..len]).unwrap();
+        // get the real length of name_str, a damaged entry may have no NUL
+        let len = self.name.iter().position(|byte| *byte == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap()
     }
 
+    /// inode number of the entry
     pub fn inode_number(&self) -> u32 {
         self.inode_number
     }
 
+    /// view the entry as raw bytes
     pub fn as_bytes(&self) -> &[u8] {
         unsafe {
             core::slice::from_raw_parts(
                 self as *const _ as usize as *const u8,
-                DIRECT_SZ
+                DIRENT_SZ
             )
         }
     }
 
+    /// view the entry as mutable raw bytes
     pub fn as_bytes_mut(&mut self) -> &mut [u8] {
         unsafe {
-            core::slice::from_raw_parts(
+            core::slice::from_raw_parts_mut(
                 self as *mut _ as usize as *mut u8,
-                DIRECT_SZ
+                DIRENT_SZ
             )
         }
     }
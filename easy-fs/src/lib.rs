@@ -1,20 +1,40 @@
 //!An easy file system isolated from the kernel
 #![no_std]
-#![deny(missing_doc)]
+#![deny(missing_docs)]
 extern crate alloc;
 mod block_dev;
 mod block_cache;
 mod layout;
 mod bitmap;
+mod ram_disk;
+pub mod compress;
+pub mod crypt;
+pub mod ext2;
+pub mod fat32;
+mod defrag;
+mod efs;
+mod fs;
+mod inode_cache;
+mod resize;
+mod vfs;
 
-use block_dev::BlockDevice;
-use block_cache::{
+pub use block_dev::BlockDevice;
+pub use block_cache::{
     get_block_cache,
     block_cache_sync_all,
 };
-use layout::*;
-use bitmap::Bitmap;
-
-pub const BLOCK_SZ: usize = 512;
-
+pub use layout::*;
+pub use bitmap::Bitmap;
+pub use ram_disk::RamDisk;
+pub use defrag::Fragmentation;
+pub use efs::EasyFileSystem;
+pub use fs::{mount_root, probe, FsInode, FsType};
+pub use inode_cache::DEFAULT_PINNED_DIRS;
+pub use vfs::Inode;
 
+/// The smallest supported block size, also the size of the area holding `SuperBlock`
+pub const MIN_BLOCK_SZ: usize = 512;
+/// The largest supported block size, same as the kernel's `PAGE_SIZE`
+pub const MAX_BLOCK_SZ: usize = 4096;
+/// Block size used when the image creator does not pick one
+pub const DEFAULT_BLOCK_SZ: usize = MIN_BLOCK_SZ;
@@ -0,0 +1,54 @@
+//!RamDisk => a BlockDevice living in memory
+/*!
+  Used by the host test suite, and by the kernel to mount an image
+  that was loaded into memory (initrd) instead of a real disk.
+*/
+use super::BlockDevice;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// A block device backed by a `Vec<u8>`
+pub struct RamDisk {
+    data: Mutex<Vec<u8>>,
+}
+
+impl RamDisk {
+    /// Create a zeroed disk of `size` bytes
+    pub fn new(size: usize) -> Self {
+        Self::from_bytes(vec![0u8; size])
+    }
+
+    /// Create a disk holding an existing image
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        Self {
+            data: Mutex::new(data),
+        }
+    }
+
+    /// Size of the disk in bytes
+    pub fn size(&self) -> usize {
+        self.data.lock().len()
+    }
+
+    /// Copy out the whole content of the disk
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.data.lock().clone()
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let data = self.data.lock();
+        let start = block_id * buf.len();
+        assert!(start + buf.len() <= data.len(), "block {} out of RamDisk", block_id);
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut data = self.data.lock();
+        let start = block_id * buf.len();
+        assert!(start + buf.len() <= data.len(), "block {} out of RamDisk", block_id);
+        data[start..start + buf.len()].copy_from_slice(buf);
+    }
+}
@@ -0,0 +1,498 @@
+//! Growing a filesystem in place
+/*!
+  A grow rewrites the areas in front of the data area: the inode bitmap
+  and the inode area may get longer, and so may the data bitmap. Data
+  blocks in the way are moved to free blocks and every index pointing at
+  them is changed.
+
+  To survive a crash at any point, each block that changes is first staged
+  in a block that is free in both the old and the new layout. A chain of
+  descriptor blocks lists where each staged block belongs, and the super
+  block follows the grow with `resize_state`, under
+  [`FEATURE_INCOMPAT_RESIZE`] so that older code keeps off the image:
+  - `STAGING`: blocks are being staged, the old layout still holds. An
+    interrupted grow is undone by zeroing what was staged.
+  - `COPYING`: everything is staged. The staged blocks are copied where
+    they belong, the new super block last, which moves to `CLEANING`.
+  - `CLEANING`: the new layout holds. The staged and descriptor blocks,
+    free from now on, are zeroed as free blocks must be.
+
+  Opening the image finishes whatever step was interrupted, and every step
+  can be run again after a crash in its middle.
+*/
+use super::{
+    block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, EasyFileSystem,
+    Geometry, SuperBlock, BLOCK_UNWRITTEN, FEATURE_INCOMPAT_RESIZE, FEATURE_INCOMPAT_SUPPORTED,
+    FEATURE_RO_COMPAT_SUPPORTED,
+};
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// magic number of a descriptor block
+const DESCRIPTOR_MAGIC: u32 = 0x6772_6f77;
+/// `resize_state`: blocks are being staged
+const STAGING: u32 = 1;
+/// `resize_state`: the staged blocks are to be copied where they belong
+const COPYING: u32 = 2;
+/// `resize_state`: the staged blocks are to be zeroed
+const CLEANING: u32 = 3;
+
+/// where the areas of a layout start
+struct Layout {
+    inode_bitmap_blocks: u32,
+    inode_area_start: u32,
+    data_bitmap_start: u32,
+    data_area_start: u32,
+}
+
+impl Layout {
+    fn of(super_block: &SuperBlock) -> Self {
+        let inode_area_start = 1 + super_block.inode_bitmap_blocks;
+        let data_bitmap_start = inode_area_start + super_block.inode_area_blocks;
+        Self {
+            inode_bitmap_blocks: super_block.inode_bitmap_blocks,
+            inode_area_start,
+            data_bitmap_start,
+            data_area_start: data_bitmap_start + super_block.data_bitmap_blocks,
+        }
+    }
+}
+
+/// what a staged block is made from
+enum Source {
+    /// zeros, which are not staged
+    Zero,
+    /// a copy of a data block
+    Copy(u32),
+    /// a copy of an index block, with its entries moved
+    Index(u32),
+    /// a copy of an inode block, with the pointers of its inodes moved
+    Inodes(u32),
+    /// the block of the new data bitmap with this number
+    DataBitmap(u32),
+    /// the new super block
+    SuperBlock,
+}
+
+/// A planned grow: which block moves where, and what each target becomes
+struct Plan {
+    geometry: Geometry,
+    block_device: Arc<dyn BlockDevice>,
+    /// data blocks that are in the way, and where they go
+    moves: BTreeMap<u32, u32>,
+    /// data blocks in use in the old layout
+    used: BTreeSet<u32>,
+    /// every target with what it becomes and the block it is staged in
+    targets: Vec<(u32, Source, u32)>,
+    /// descriptor blocks listing the targets
+    descriptors: Vec<u32>,
+    new_super_block: SuperBlock,
+    new_layout: Layout,
+}
+
+fn read_block(block_id: u32, geo: &Geometry, block_device: &Arc<dyn BlockDevice>) -> Vec<u8> {
+    get_block_cache(block_id as usize, geo.block_size, Arc::clone(block_device))
+        .lock()
+        .read_slice(|data: &[u8]| data.to_vec())
+}
+
+fn write_block(block_id: u32, data: &[u8], geo: &Geometry, block_device: &Arc<dyn BlockDevice>) {
+    get_block_cache(block_id as usize, geo.block_size, Arc::clone(block_device))
+        .lock()
+        .modify_slice(|block: &mut [u8]| block.copy_from_slice(data));
+}
+
+fn zero_block(block_id: u32, geo: &Geometry, block_device: &Arc<dyn BlockDevice>) {
+    write_block(block_id, &vec![0u8; geo.block_size], geo, block_device);
+}
+
+fn modify_super_block(geo: &Geometry, block_device: &Arc<dyn BlockDevice>, f: impl FnOnce(&mut SuperBlock)) {
+    get_block_cache(0, geo.block_size, Arc::clone(block_device))
+        .lock()
+        .modify(0, f);
+    block_cache_sync_all();
+}
+
+/// the index blocks of an inode: indirect1, indirect2 and the blocks below indirect2
+fn index_blocks(disk_inode: &DiskInode, geo: &Geometry, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+    let data_blocks = disk_inode.data_blocks(geo) as usize;
+    let mut blocks = Vec::new();
+    if data_blocks > disk_inode.direct.len() {
+        blocks.push(disk_inode.indirect1);
+    }
+    if data_blocks > geo.indirect1_bound {
+        blocks.push(disk_inode.indirect2);
+        let count = (data_blocks - geo.indirect1_bound).div_ceil(geo.indirect1_count);
+        get_block_cache(disk_inode.indirect2 as usize, geo.block_size, Arc::clone(block_device))
+            .lock()
+            .read_slice(|indirect2: &[u32]| blocks.extend_from_slice(&indirect2[..count]));
+    }
+    blocks
+}
+
+impl Plan {
+    /// where an index entry points once the blocks in the way are moved
+    fn moved(&self, entry: u32) -> u32 {
+        let block_id = entry & !BLOCK_UNWRITTEN;
+        match self.moves.get(&block_id) {
+            Some(to) => to | (entry & BLOCK_UNWRITTEN),
+            None => entry,
+        }
+    }
+
+    /// whether moving the blocks in the way changes any entry of an index block
+    fn index_changes(&self, block_id: u32) -> bool {
+        get_block_cache(block_id as usize, self.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .read_slice(|entries: &[u32]| entries.iter().any(|entry| self.moved(*entry) != *entry))
+    }
+
+    fn move_inode(&self, disk_inode: &mut DiskInode) {
+        for entry in disk_inode.direct.iter_mut() {
+            *entry = self.moved(*entry);
+        }
+        disk_inode.indirect1 = self.moved(disk_inode.indirect1);
+        disk_inode.indirect2 = self.moved(disk_inode.indirect2);
+    }
+
+    /// whether moving the blocks in the way changes any inode of an inode block
+    fn inodes_change(&self, block_id: u32) -> bool {
+        let inode_size = core::mem::size_of::<DiskInode>();
+        let block_cache = get_block_cache(block_id as usize, self.geometry.block_size, Arc::clone(&self.block_device));
+        let block_cache = block_cache.lock();
+        (0..self.geometry.inodes_per_block).any(|i| {
+            block_cache.read(i * inode_size, |disk_inode: &DiskInode| {
+                let mut moved = *disk_inode;
+                self.move_inode(&mut moved);
+                moved.direct != disk_inode.direct
+                    || moved.indirect1 != disk_inode.indirect1
+                    || moved.indirect2 != disk_inode.indirect2
+            })
+        })
+    }
+
+    /// write what `source` becomes into the block `staged`
+    fn stage(&self, source: &Source, staged: u32) {
+        let geo = &self.geometry;
+        let block_device = &self.block_device;
+        let block_cache = get_block_cache(staged as usize, geo.block_size, Arc::clone(block_device));
+        let mut block_cache = block_cache.lock();
+        match source {
+            Source::Zero => unreachable!(),
+            Source::Copy(from) => {
+                let data = read_block(*from, geo, block_device);
+                block_cache.modify_slice(|block: &mut [u8]| block.copy_from_slice(&data));
+            }
+            Source::Index(from) => {
+                let data = read_block(*from, geo, block_device);
+                block_cache.modify_slice(|block: &mut [u8]| block.copy_from_slice(&data));
+                block_cache.modify_slice(|entries: &mut [u32]| {
+                    for entry in entries.iter_mut() {
+                        *entry = self.moved(*entry);
+                    }
+                });
+            }
+            Source::Inodes(from) => {
+                let data = read_block(*from, geo, block_device);
+                block_cache.modify_slice(|block: &mut [u8]| block.copy_from_slice(&data));
+                let inode_size = core::mem::size_of::<DiskInode>();
+                for i in 0..geo.inodes_per_block {
+                    block_cache.modify(i * inode_size, |disk_inode: &mut DiskInode| {
+                        self.move_inode(disk_inode)
+                    });
+                }
+            }
+            Source::DataBitmap(number) => {
+                let first_bit = *number as usize * geo.block_bits;
+                let data_area_start = self.new_layout.data_area_start;
+                block_cache.modify_slice(|bitmap_block: &mut [u64]| {
+                    bitmap_block.fill(0);
+                    for block_id in self.used.iter().map(|block_id| self.moved(*block_id)) {
+                        let bit = (block_id - data_area_start) as usize;
+                        if (first_bit..first_bit + geo.block_bits).contains(&bit) {
+                            let bit = bit - first_bit;
+                            bitmap_block[bit / 64] |= 1u64 << (bit % 64);
+                        }
+                    }
+                });
+            }
+            Source::SuperBlock => {
+                block_cache.modify_slice(|block: &mut [u8]| block.fill(0));
+                block_cache.modify(0, |super_block: &mut SuperBlock| *super_block = self.new_super_block);
+            }
+        }
+    }
+}
+
+impl EasyFileSystem {
+    /// Grow the filesystem on a device to `total_blocks`, with room for more inodes
+    /**
+        The device must already hold `total_blocks` blocks, and the
+        filesystem must not be mounted. Nothing shrinks: `total_blocks`
+        and `inode_bitmap_blocks` are at least what they were. Returns
+        false when there was nothing to grow.
+    */
+    pub fn grow(block_device: Arc<dyn BlockDevice>, total_blocks: u32, inode_bitmap_blocks: u32) -> bool {
+        Self::finish_resize(&block_device);
+        let super_block = Self::read_super_block(&block_device);
+        assert!(!super_block.is_legacy(), "Upgrade EFS before growing it!");
+        assert!(super_block.feature_incompat & !FEATURE_INCOMPAT_SUPPORTED == 0
+            && super_block.feature_ro_compat & !FEATURE_RO_COMPAT_SUPPORTED == 0,
+            "EFS uses unsupported features!");
+        assert!(total_blocks >= super_block.total_blocks
+            && inode_bitmap_blocks >= super_block.inode_bitmap_blocks,
+            "EFS can only grow!");
+        if total_blocks == super_block.total_blocks
+            && inode_bitmap_blocks == super_block.inode_bitmap_blocks
+        {
+            return false;
+        }
+        let geo = super_block.geometry();
+        // blocks past the old image are free once it grows, and free blocks are zeros
+        for block_id in super_block.total_blocks..total_blocks {
+            zero_block(block_id, &geo, &block_device);
+        }
+        block_cache_sync_all();
+        let plan = Self::plan_grow(Arc::clone(&block_device), &super_block, total_blocks, inode_bitmap_blocks);
+        let journal = plan.descriptors[0];
+        modify_super_block(&geo, &block_device, |super_block| {
+            super_block.feature_incompat |= FEATURE_INCOMPAT_RESIZE;
+            super_block.resize_state = STAGING;
+            super_block.resize_journal = journal;
+        });
+        // descriptors from the last, so that any of them that can be
+        // reached from the super block is complete
+        let capacity = (geo.block_size / 4 - 3) / 2;
+        let chunks: Vec<_> = plan.targets.chunks(capacity).collect();
+        for (i, chunk) in chunks.iter().enumerate().rev() {
+            let next = plan.descriptors.get(i + 1).copied().unwrap_or(0);
+            get_block_cache(plan.descriptors[i] as usize, geo.block_size, Arc::clone(&block_device))
+                .lock()
+                .modify_slice(|descriptor: &mut [u32]| {
+                    descriptor.fill(0);
+                    descriptor[0] = DESCRIPTOR_MAGIC;
+                    descriptor[1] = chunk.len() as u32;
+                    descriptor[2] = next;
+                    for (j, (target, _, staged)) in chunk.iter().enumerate() {
+                        descriptor[3 + 2 * j] = *target;
+                        descriptor[4 + 2 * j] = *staged;
+                    }
+                });
+            block_cache_sync_all();
+        }
+        for (_, source, staged) in plan.targets.iter().filter(|(_, _, staged)| *staged != 0) {
+            plan.stage(source, *staged);
+        }
+        block_cache_sync_all();
+        // the grow is committed from here on
+        modify_super_block(&geo, &block_device, |super_block| super_block.resize_state = COPYING);
+        Self::finish_resize(&block_device);
+        true
+    }
+
+    /// Find what has to change in the old layout to grow it
+    fn plan_grow(
+        block_device: Arc<dyn BlockDevice>,
+        super_block: &SuperBlock,
+        total_blocks: u32,
+        inode_bitmap_blocks: u32,
+    ) -> Plan {
+        let geo = super_block.geometry();
+        let old_layout = Layout::of(super_block);
+        let (inode_area_blocks, data_bitmap_blocks, data_area_blocks) =
+            geo.areas(total_blocks, inode_bitmap_blocks);
+        let mut new_super_block = *super_block;
+        new_super_block.total_blocks = total_blocks;
+        new_super_block.inode_bitmap_blocks = inode_bitmap_blocks;
+        new_super_block.inode_area_blocks = inode_area_blocks;
+        new_super_block.data_bitmap_blocks = data_bitmap_blocks;
+        new_super_block.data_area_blocks = data_area_blocks;
+        new_super_block.resize_state = CLEANING;
+        new_super_block.feature_incompat |= FEATURE_INCOMPAT_RESIZE;
+        let new_layout = Layout::of(&new_super_block);
+        assert!(new_layout.data_area_start >= old_layout.data_area_start, "EFS can only grow!");
+
+        // blocks in use, and which of them are index blocks
+        let data_bitmap = Bitmap::new(
+            old_layout.data_bitmap_start as usize,
+            super_block.data_bitmap_blocks as usize,
+            geo.block_size,
+        );
+        let used: BTreeSet<u32> = (0..super_block.data_area_blocks)
+            .filter(|bit| data_bitmap.is_allocated(&block_device, *bit as usize))
+            .map(|bit| old_layout.data_area_start + bit)
+            .collect();
+        let inode_bitmap = Bitmap::new(1, old_layout.inode_bitmap_blocks as usize, geo.block_size);
+        let inode_size = core::mem::size_of::<DiskInode>();
+        let mut index = BTreeSet::new();
+        for inode_id in 0..inode_bitmap.maximum() {
+            if !inode_bitmap.is_allocated(&block_device, inode_id) {
+                continue;
+            }
+            let block_id = old_layout.inode_area_start as usize + inode_id / geo.inodes_per_block;
+            let disk_inode = get_block_cache(block_id, geo.block_size, Arc::clone(&block_device))
+                .lock()
+                .read((inode_id % geo.inodes_per_block) * inode_size, |disk_inode: &DiskInode| *disk_inode);
+            index.extend(index_blocks(&disk_inode, &geo, &block_device));
+        }
+
+        // blocks in the way go to the first free blocks of the new data area,
+        // the blocks after them are for staging
+        let in_use = used.clone();
+        let mut free = (new_layout.data_area_start..total_blocks).filter(|block_id| !in_use.contains(block_id));
+        let mut take = || free.next().expect("Not enough free blocks to grow EFS!");
+        let moves: BTreeMap<u32, u32> = used
+            .range(..new_layout.data_area_start)
+            .map(|block_id| (*block_id, take()))
+            .collect();
+        let mut plan = Plan {
+            geometry: geo,
+            block_device: Arc::clone(&block_device),
+            moves,
+            used,
+            targets: Vec::new(),
+            descriptors: Vec::new(),
+            new_super_block,
+            new_layout,
+        };
+
+        let mut targets = Vec::new();
+        // the inode bitmap keeps its blocks and gets zeroed ones
+        for block_id in 1 + old_layout.inode_bitmap_blocks..1 + inode_bitmap_blocks {
+            targets.push((block_id, Source::Zero));
+        }
+        // the inode area moves along
+        for i in 0..inode_area_blocks {
+            let target = plan.new_layout.inode_area_start + i;
+            if i >= super_block.inode_area_blocks {
+                targets.push((target, Source::Zero));
+                continue;
+            }
+            let from = old_layout.inode_area_start + i;
+            if from == target && !plan.inodes_change(from) {
+                continue;
+            }
+            if read_block(from, &geo, &block_device).iter().all(|byte| *byte == 0) {
+                targets.push((target, Source::Zero));
+            } else {
+                targets.push((target, Source::Inodes(from)));
+            }
+        }
+        // the data bitmap is made again
+        for i in 0..data_bitmap_blocks {
+            targets.push((plan.new_layout.data_bitmap_start + i, Source::DataBitmap(i)));
+        }
+        // blocks in the way move, index blocks elsewhere change if they point at them
+        for (from, to) in plan.moves.iter() {
+            let source = if index.contains(from) { Source::Index(*from) } else { Source::Copy(*from) };
+            targets.push((*to, source));
+        }
+        for block_id in index.iter() {
+            if !plan.moves.contains_key(block_id) && plan.index_changes(*block_id) {
+                targets.push((*block_id, Source::Index(*block_id)));
+            }
+        }
+        // and the super block, which has to be copied last
+        targets.push((0, Source::SuperBlock));
+
+        // zeros need no staging, their staged block is 0
+        let capacity = (geo.block_size / 4 - 3) / 2;
+        plan.descriptors = (0..targets.len().div_ceil(capacity)).map(|_| take()).collect();
+        plan.new_super_block.resize_journal = plan.descriptors[0];
+        plan.targets = targets
+            .into_iter()
+            .map(|(target, source)| match source {
+                Source::Zero => (target, source, 0),
+                source => (target, source, take()),
+            })
+            .collect();
+        plan
+    }
+
+    /// Finish a grow that was interrupted, return whether there was one
+    pub(crate) fn finish_resize(block_device: &Arc<dyn BlockDevice>) -> bool {
+        let super_block = Self::raw_super_block(block_device);
+        if !super_block.is_valid() || super_block.feature_incompat & FEATURE_INCOMPAT_RESIZE == 0 {
+            return false;
+        }
+        let geo = super_block.geometry();
+        loop {
+            let super_block = get_block_cache(0, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .read(0, |super_block: &SuperBlock| *super_block);
+            let pairs = Self::read_journal(super_block.resize_journal, &geo, block_device);
+            match super_block.resize_state {
+                0 => return true,
+                COPYING => {
+                    // every block where it belongs, then the super block
+                    for (target, staged) in pairs.iter().filter(|(target, _)| *target != 0) {
+                        if *staged == 0 {
+                            zero_block(*target, &geo, block_device);
+                        } else {
+                            write_block(*target, &read_block(*staged, &geo, block_device), &geo, block_device);
+                        }
+                    }
+                    block_cache_sync_all();
+                    let (_, staged) = pairs
+                        .iter()
+                        .find(|(target, _)| *target == 0)
+                        .expect("Broken EFS grow journal!");
+                    write_block(0, &read_block(*staged, &geo, block_device), &geo, block_device);
+                    block_cache_sync_all();
+                }
+                STAGING | CLEANING => {
+                    for (_, staged) in pairs.iter().filter(|(_, staged)| *staged != 0) {
+                        zero_block(*staged, &geo, block_device);
+                    }
+                    block_cache_sync_all();
+                    let mut descriptors = Self::journal_descriptors(super_block.resize_journal, &geo, block_device);
+                    // from the last, so that the chain from the super block stays whole
+                    while let Some(descriptor) = descriptors.pop() {
+                        zero_block(descriptor, &geo, block_device);
+                        block_cache_sync_all();
+                    }
+                    modify_super_block(&geo, block_device, |super_block| {
+                        super_block.feature_incompat &= !FEATURE_INCOMPAT_RESIZE;
+                        super_block.resize_state = 0;
+                        super_block.resize_journal = 0;
+                    });
+                    return true;
+                }
+                state => panic!("EFS is in an unknown grow step {}!", state),
+            }
+        }
+    }
+
+    /// the descriptor blocks of a journal that are there
+    fn journal_descriptors(first: u32, geo: &Geometry, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+        let mut descriptors = Vec::new();
+        let mut next = first;
+        while next != 0 {
+            let (magic, following) = get_block_cache(next as usize, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .read_slice(|descriptor: &[u32]| (descriptor[0], descriptor[2]));
+            if magic != DESCRIPTOR_MAGIC {
+                break;
+            }
+            descriptors.push(next);
+            next = following;
+        }
+        descriptors
+    }
+
+    /// every (target, staged) pair of a journal
+    fn read_journal(first: u32, geo: &Geometry, block_device: &Arc<dyn BlockDevice>) -> Vec<(u32, u32)> {
+        let mut pairs = Vec::new();
+        for descriptor in Self::journal_descriptors(first, geo, block_device) {
+            get_block_cache(descriptor as usize, geo.block_size, Arc::clone(block_device))
+                .lock()
+                .read_slice(|descriptor: &[u32]| {
+                    let count = descriptor[1] as usize;
+                    pairs.extend((0..count).map(|j| (descriptor[3 + 2 * j], descriptor[4 + 2 * j])));
+                });
+        }
+        pairs
+    }
+}
@@ -1,10 +1,670 @@
 //!Virtaul File System
 /*!
-vfs, as the top layer of the file system model, can mask 
-the differences of the underlying disk layout and provide 
-an abstract interface for external users to read and write 
-files directly. Any file form that users see or use is 
+vfs, as the top layer of the file system model, can mask
+the differences of the underlying disk layout and provide
+an abstract interface for external users to read and write
+files directly. Any file form that users see or use is
 abstracted as [Inode].
+
+There is one [Inode] per inode number, shared by all its handles
+through the inode cache. It keeps a copy of its disk inode behind a
+reader/writer lock: reads of the same or of different files run at
+the same time, a write only excludes the accesses to its own inode.
+A directory stores its disk inode back on every change, as pinned
+directories may stay cached for long; a file only marks it dirty,
+and it is written back when the last handle drops or on `sync`.
+*/
+use super::{
+    block_cache_sync_all, compress, crypt::Cipher, get_block_cache,
+    BlockDevice, DirEntry, DiskInode, DiskInodeType, EasyFileSystem, FsInode, DIRENT_SZ,
+    FEATURE_INCOMPAT_COMPRESSION, FEATURE_INCOMPAT_UNWRITTEN, NAME_LENGTH_LIMIT,
+};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Deref;
+use spin::RwLock;
+
+/// The in-memory copy of a disk inode
+struct InodeInner {
+    disk_inode: DiskInode,
+    /// changed since it was last stored back
+    dirty: bool,
+    /// unlinked, its inode number may already belong to another inode
+    removed: bool,
+}
+
+/// An inode in memory, without the filesystem it belongs to
+/**
+    The inode cache pins directories by their core: a pinned directory
+    does not hold its filesystem alive, which is freed with its last
+    handle. A handle looked up again wraps the same core.
 */
+pub struct InodeCore {
+    inode_id: u32,
+    block_id: usize,
+    block_offset: usize,
+    inner: RwLock<InodeInner>,
+    block_device: Arc<dyn BlockDevice>,
+}
+
+impl InodeCore {
+    /// The inode number of the core
+    pub(crate) fn id(&self) -> u32 {
+        self.inode_id
+    }
+}
+
+/// Virtual filesystem layer over easy-fs
+pub struct Inode {
+    core: Arc<InodeCore>,
+    fs: Arc<EasyFileSystem>,
+}
+
+impl Deref for Inode {
+    type Target = InodeCore;
+
+    fn deref(&self) -> &InodeCore {
+        &self.core
+    }
+}
+
+impl Inode {
+    /// Load inode `inode_id` from disk, see `EasyFileSystem::get_inode` for shared handles
+    pub(crate) fn load(inode_id: u32, fs: Arc<EasyFileSystem>) -> Self {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let core = InodeCore {
+            inode_id,
+            block_id: block_id as usize,
+            block_offset,
+            inner: RwLock::new(InodeInner {
+                disk_inode: fs.read_disk_inode(inode_id),
+                dirty: false,
+                removed: false,
+            }),
+            block_device: Arc::clone(&fs.block_device),
+        };
+        Self::wrap(Arc::new(core), fs)
+    }
+
+    /// A handle on a core kept by the inode cache
+    pub(crate) fn wrap(core: Arc<InodeCore>, fs: Arc<EasyFileSystem>) -> Self {
+        Self { core, fs }
+    }
+
+    pub(crate) fn core(&self) -> &Arc<InodeCore> {
+        &self.core
+    }
+
+    /// The inode number of current inode
+    pub fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+
+    /// Get the handle of `inode_id` on the same filesystem
+    fn child(&self, inode_id: u32) -> Arc<Inode> {
+        EasyFileSystem::get_inode(&self.fs, inode_id)
+    }
+
+    /// Store back the disk inode of a directory, mark the one of a file dirty
+    fn store_disk_inode(&self, inner: &mut InodeInner) {
+        if inner.disk_inode.is_dir() {
+            self.write_back(inner);
+        } else {
+            inner.dirty = true;
+        }
+    }
+
+    /// Store back the disk inode through the block cache
+    fn write_back(&self, inner: &mut InodeInner) {
+        inner.dirty = false;
+        get_block_cache(self.block_id, self.fs.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .modify(self.block_offset, |stored: &mut DiskInode| *stored = inner.disk_inode);
+    }
+
+    /// Write back the disk inode of current inode if it is dirty
+    pub fn sync(&self) {
+        let mut inner = self.inner.write();
+        if inner.dirty && !inner.removed {
+            self.write_back(&mut inner);
+            block_cache_sync_all();
+        }
+    }
+
+    /// Panic if the filesystem cannot be written
+    /**
+        Only for the methods the tools call. Those of [`FsInode`] give
+        back nothing instead, as a kernel reaches them from user calls.
+    */
+    fn check_writable(&self) {
+        assert!(!self.fs.is_read_only(), "EFS is mounted read-only!");
+    }
+
+    /// The cipher with the inode id if file data of current inode is encrypted
+    fn cipher(&self, disk_inode: &DiskInode) -> Option<(&Cipher, u32)> {
+        if !disk_inode.is_file() {
+            return None;
+        }
+        self.fs.cipher().map(|cipher| (cipher, self.inode_id))
+    }
+
+    /// Read the stored bytes of a disk inode, decrypting file data
+    fn read_stored(&self, disk_inode: &DiskInode, offset: usize, buf: &mut [u8]) -> usize {
+        let geometry = &self.fs.geometry;
+        match self.cipher(disk_inode) {
+            Some((cipher, inode_id)) => disk_inode.read_at_with(
+                offset,
+                buf,
+                geometry,
+                &self.block_device,
+                |inner_id, block_offset, bytes| cipher.apply(inode_id, inner_id, block_offset, bytes),
+            ),
+            None => disk_inode.read_at(offset, buf, geometry, &self.block_device),
+        }
+    }
+
+    /// Write the stored bytes of a disk inode, encrypting file data
+    /**
+        The bytes between the old end of an encrypted file and `offset`
+        are written as encrypted zeros, so that they read back as zeros.
+    */
+    fn write_stored(
+        &self,
+        disk_inode: &mut DiskInode,
+        offset: usize,
+        buf: &[u8],
+        old_size: usize,
+    ) -> usize {
+        let geometry = self.fs.geometry;
+        match self.cipher(disk_inode) {
+            Some((cipher, inode_id)) => {
+                let mut encrypt = |inner_id: u32, block_offset: usize, bytes: &mut [u8]| {
+                    cipher.apply(inode_id, inner_id, block_offset, bytes)
+                };
+                let zeros = alloc::vec![0u8; geometry.block_size];
+                let mut pos = old_size;
+                while pos < offset {
+                    let len = (offset - pos).min(zeros.len());
+                    disk_inode.write_at_with(pos, &zeros[..len], &geometry, &self.block_device, &mut encrypt);
+                    pos += len;
+                }
+                disk_inode.write_at_with(offset, buf, &geometry, &self.block_device, &mut encrypt)
+            }
+            None => disk_inode.write_at(offset, buf, &geometry, &self.block_device),
+        }
+    }
+
+    /// Find the dirent index and inode under a disk inode by name
+    fn find_dirent(&self, name: &str, disk_inode: &DiskInode) -> Option<(usize, u32)> {
+        // assert it is a directory
+        assert!(disk_inode.is_dir());
+        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let mut dirent = DirEntry::empty();
+        for i in 0..file_count {
+            assert_eq!(
+                disk_inode.read_at(
+                    DIRENT_SZ * i,
+                    dirent.as_bytes_mut(),
+                    &self.fs.geometry,
+                    &self.block_device,
+                ),
+                DIRENT_SZ,
+            );
+            if dirent.name() == name {
+                return Some((i, dirent.inode_number()));
+            }
+        }
+        None
+    }
+
+    /// Find inode under a disk inode by name
+    fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+        self.find_dirent(name, disk_inode).map(|(_, inode_id)| inode_id)
+    }
+
+    /// Find inode under current inode by name
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        let inner = self.inner.read();
+        self.find_inode_id(name, &inner.disk_inode)
+            .map(|inode_id| self.child(inode_id))
+    }
+
+    /// Increase the size of a disk inode, return its size afterwards
+    /**
+        When the data area runs out of blocks, the inode only grows by the
+        whole blocks that could be allocated, or not at all.
+    */
+    fn increase_size(&self, new_size: u32, disk_inode: &mut DiskInode) -> u32 {
+        if new_size < disk_inode.size {
+            return disk_inode.size;
+        }
+        let geometry = self.fs.geometry;
+        let blocks_needed = disk_inode.blocks_num_needed(new_size, &geometry);
+        let mut v: Vec<u32> = Vec::new();
+        while (v.len() as u32) < blocks_needed {
+            match self.fs.alloc_data() {
+                Some(block_id) => v.push(block_id),
+                None => break,
+            }
+        }
+        let mut new_size = new_size;
+        let block_size = geometry.block_size as u32;
+        while disk_inode.blocks_num_needed(new_size, &geometry) > v.len() as u32 {
+            new_size = ((new_size - 1) / block_size * block_size).max(disk_inode.size);
+        }
+        // index blocks may have been counted for blocks left out
+        let used = disk_inode.blocks_num_needed(new_size, &geometry) as usize;
+        for block_id in v.drain(used..) {
+            self.fs.dealloc_data(block_id);
+        }
+        disk_inode.increase_size(new_size, v, &geometry, &self.block_device);
+        new_size
+    }
+
+    /// Create a file under current inode by name
+    pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File)
+    }
+
+    /// Create a directory under current inode by name
+    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory)
+    }
+
+    /// Create inode under current inode by name
+    /**
+        None if the name is taken, or longer than `NAME_LENGTH_LIMIT` bytes
+        so that its entry could not end with a NUL, if the filesystem is
+        read-only, or if no inode or block is left for it.
+    */
+    fn create_inode(&self, name: &str, type_: DiskInodeType) -> Option<Arc<Inode>> {
+        if name.len() > NAME_LENGTH_LIMIT || self.fs.is_read_only() {
+            return None;
+        }
+        let mut inner = self.inner.write();
+        let root_inode = &mut inner.disk_inode;
+        // assert it is a directory
+        assert!(root_inode.is_dir());
+        // has the file been created?
+        if self.find_inode_id(name, root_inode).is_some() {
+            return None;
+        }
+        // create a new file
+        // alloc a inode with an indirect block
+        let new_inode_id = self.fs.alloc_inode()?;
+        // initialize inode
+        let (new_inode_block_id, new_inode_block_offset) = self.fs.get_disk_inode_pos(new_inode_id);
+        get_block_cache(new_inode_block_id as usize, self.fs.geometry.block_size, Arc::clone(&self.block_device))
+            .lock()
+            .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
+                new_inode.initialize(type_);
+            });
+        // append file in the dirent
+        let file_count = (root_inode.size as usize) / DIRENT_SZ;
+        let new_size = (file_count + 1) * DIRENT_SZ;
+        // increase size, give the inode back if the disk is full
+        if self.increase_size(new_size as u32, root_inode) < new_size as u32 {
+            self.fs.dealloc_inode(new_inode_id);
+            return None;
+        }
+        // write dirent
+        let dirent = DirEntry::new(name, new_inode_id);
+        root_inode.write_at(
+            file_count * DIRENT_SZ,
+            dirent.as_bytes(),
+            &self.fs.geometry,
+            &self.block_device,
+        );
+        self.store_disk_inode(&mut inner);
+        block_cache_sync_all();
+        // return inode
+        Some(self.child(new_inode_id))
+    }
+
+    /// Remove the entry `name` from current inode and free its inode
+    /**
+        Returns false if there is no such entry, if it is a directory
+        that is not empty, or if the filesystem is read-only. Handles
+        still held on the removed inode must not be used anymore: it
+        leaves the inode cache and is never written back again.
+    */
+    pub fn unlink(&self, name: &str) -> bool {
+        if self.fs.is_read_only() {
+            return false;
+        }
+        let mut inner = self.inner.write();
+        let dir = &mut inner.disk_inode;
+        let Some((index, inode_id)) = self.find_dirent(name, dir) else {
+            return false;
+        };
+        let child = self.child(inode_id);
+        {
+            let mut child_inner = child.inner.write();
+            let child_inode = &mut child_inner.disk_inode;
+            if child_inode.is_dir() && child_inode.size > 0 {
+                return false;
+            }
+            child.clear_data(child_inode);
+            child.write_back(&mut child_inner);
+            child_inner.removed = true;
+        }
+        self.fs.inode_cache.forget(&child);
+        self.fs.dealloc_inode(inode_id);
+        // move the last dirent into the hole
+        let geometry = self.fs.geometry;
+        let file_count = (dir.size as usize) / DIRENT_SZ;
+        if index + 1 < file_count {
+            let mut dirent = DirEntry::empty();
+            dir.read_at((file_count - 1) * DIRENT_SZ, dirent.as_bytes_mut(), &geometry, &self.block_device);
+            dir.write_at(index * DIRENT_SZ, dirent.as_bytes(), &geometry, &self.block_device);
+        }
+        let new_size = ((file_count - 1) * DIRENT_SZ) as u32;
+        for data_block in dir.decrease_size(new_size, &geometry, &self.block_device) {
+            self.fs.dealloc_data(data_block);
+        }
+        self.store_disk_inode(&mut inner);
+        block_cache_sync_all();
+        true
+    }
+
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> bool {
+        self.inner.read().disk_inode.is_dir()
+    }
+
+    /// Whether current inode is a regular file
+    pub fn is_file(&self) -> bool {
+        self.inner.read().disk_inode.is_file()
+    }
+
+    /// List inodes under current inode
+    pub fn ls(&self) -> Vec<String> {
+        let inner = self.inner.read();
+        let disk_inode = &inner.disk_inode;
+        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let mut v: Vec<String> = Vec::new();
+        for i in 0..file_count {
+            let mut dirent = DirEntry::empty();
+            assert_eq!(
+                disk_inode.read_at(
+                    i * DIRENT_SZ,
+                    dirent.as_bytes_mut(),
+                    &self.fs.geometry,
+                    &self.block_device,
+                ),
+                DIRENT_SZ,
+            );
+            v.push(String::from(dirent.name()));
+        }
+        v
+    }
+
+    /// Read the data of a disk inode, decompressing it if needed
+    fn read_data(&self, disk_inode: &DiskInode, offset: usize, buf: &mut [u8]) -> usize {
+        if disk_inode.is_compressed() {
+            let read_raw = |pos: usize, raw: &mut [u8]| self.read_stored(disk_inode, pos, raw);
+            compress::read_at(offset, buf, &read_raw)
+        } else {
+            self.read_stored(disk_inode, offset, buf)
+        }
+    }
+
+    /// Size of the data of a disk inode
+    fn data_size(&self, disk_inode: &DiskInode) -> usize {
+        if disk_inode.is_compressed() {
+            let read_raw = |pos: usize, raw: &mut [u8]| self.read_stored(disk_inode, pos, raw);
+            compress::raw_size(&read_raw).unwrap_or(0)
+        } else {
+            disk_inode.size as usize
+        }
+    }
+
+    /// Write the data of a plain disk inode, growing it if needed
+    /**
+        Nothing is written past the largest size a file can have, nor
+        past the blocks left on the disk: a write reaching over either is
+        cut short there.
+    */
+    fn write_data(&self, disk_inode: &mut DiskInode, offset: usize, buf: &[u8]) -> usize {
+        let max_size = self.fs.geometry.max_file_size();
+        if offset > max_size {
+            return 0;
+        }
+        let buf = &buf[..buf.len().min(max_size - offset)];
+        let old_size = disk_inode.size as usize;
+        let size = self.increase_size((offset + buf.len()) as u32, disk_inode) as usize;
+        if size < offset {
+            // the disk filled up before `offset`, the gap still reads as zeros
+            self.write_stored(disk_inode, size, &[], old_size);
+            return 0;
+        }
+        let end = (offset + buf.len()).min(size);
+        self.write_stored(disk_inode, offset, &buf[..end - offset], old_size)
+    }
+
+    /// Free every data block of a disk inode
+    fn clear_data(&self, disk_inode: &mut DiskInode) {
+        let size = disk_inode.size;
+        let geometry = self.fs.geometry;
+        let data_blocks_dealloc = disk_inode.clear_size(&geometry, &self.block_device);
+        assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size, &geometry) as usize);
+        for data_block in data_blocks_dealloc.into_iter() {
+            self.fs.dealloc_data(data_block);
+        }
+        disk_inode.set_compressed(false);
+    }
+
+    /// Store the data of a disk inode uncompressed
+    /**
+        Returns false, changing nothing, if the compressed data is corrupted,
+        larger than a plain file can be, or if there is no memory to hold it.
+    */
+    fn decompress_data(&self, disk_inode: &mut DiskInode) -> bool {
+        let read_raw = |pos: usize, raw: &mut [u8]| self.read_stored(disk_inode, pos, raw);
+        let size = match compress::raw_size(&read_raw) {
+            Some(size) if size <= self.fs.geometry.max_file_size() => size,
+            _ => return false,
+        };
+        let mut data = Vec::new();
+        if data.try_reserve_exact(size).is_err() {
+            return false;
+        }
+        data.resize(size, 0);
+        if self.read_data(disk_inode, 0, &mut data) < size {
+            return false;
+        }
+        self.clear_data(disk_inode);
+        self.write_data(disk_inode, 0, &data);
+        true
+    }
+
+    /// Read data from current inode
+    /**
+        Compressed files are decompressed on the fly,
+        callers always see the original data. A read of a corrupted
+        compressed file stops short at the first bad chunk.
+    */
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.read_data(&self.inner.read().disk_inode, offset, buf)
+    }
+
+    /// Size of the data in current inode, 0 for a corrupted compressed file
+    pub fn size(&self) -> usize {
+        self.data_size(&self.inner.read().disk_inode)
+    }
+
+    /// Whether current inode stores its data compressed
+    pub fn is_compressed(&self) -> bool {
+        self.inner.read().disk_inode.is_compressed()
+    }
+
+    /// Write data to current inode
+    /**
+        A compressed file is turned back into a plain one before the write,
+        a corrupted one is not written. Nothing is written on a read-only
+        filesystem, nor past the largest
+        size a file can have or once the disk is full: the count returned
+        is short then.
+    */
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        if self.fs.is_read_only() {
+            return 0;
+        }
+        let mut inner = self.inner.write();
+        let disk_inode = &mut inner.disk_inode;
+        if disk_inode.is_compressed() && !self.decompress_data(disk_inode) {
+            return 0;
+        }
+        let size = self.write_data(disk_inode, offset, buf);
+        self.store_disk_inode(&mut inner);
+        block_cache_sync_all();
+        size
+    }
+
+    /// Clear the data in current inode, nothing on a read-only filesystem
+    pub fn clear(&self) {
+        if self.fs.is_read_only() {
+            return;
+        }
+        let mut inner = self.inner.write();
+        let disk_inode = &mut inner.disk_inode;
+        self.clear_data(disk_inode);
+        self.store_disk_inode(&mut inner);
+        block_cache_sync_all();
+    }
+
+    /// Set the size of current inode to `new_size`
+    /**
+        Shrinking frees the data and index blocks past the new end,
+        growing makes the file read as zeros up to `new_size`.
+        A compressed file is turned back into a plain one first,
+        a corrupted one is left as it is.
+    */
+    pub fn truncate(&self, new_size: usize) {
+        self.check_writable();
+        let mut inner = self.inner.write();
+        let disk_inode = &mut inner.disk_inode;
+        if disk_inode.is_compressed() && !self.decompress_data(disk_inode) {
+            return;
+        }
+        let old_size = disk_inode.size as usize;
+        if new_size < old_size {
+            let data_blocks_dealloc =
+                disk_inode.decrease_size(new_size as u32, &self.fs.geometry, &self.block_device);
+            for data_block in data_blocks_dealloc.into_iter() {
+                self.fs.dealloc_data(data_block);
+            }
+        } else {
+            self.write_data(disk_inode, new_size, &[]);
+        }
+        self.store_disk_inode(&mut inner);
+        block_cache_sync_all();
+    }
+
+    /// Reserve the data blocks of current inode up to `new_size`
+    /**
+        The file grows like with `truncate`, but the new blocks are only
+        marked unwritten instead of being filled: they read as zeros until
+        they are written. A file already larger than `new_size` is unchanged,
+        as is a corrupted compressed file; a full disk stops the file short
+        of `new_size`.
+    */
+    pub fn fallocate(&self, new_size: usize) {
+        self.check_writable();
+        let mut inner = self.inner.write();
+        let disk_inode = &mut inner.disk_inode;
+        if disk_inode.is_compressed() && !self.decompress_data(disk_inode) {
+            return;
+        }
+        let old_size = disk_inode.size as usize;
+        if new_size > old_size {
+            self.fs.enable_feature_incompat(FEATURE_INCOMPAT_UNWRITTEN);
+            let geometry = self.fs.geometry;
+            let old_blocks = disk_inode.data_blocks(&geometry);
+            let new_size = self.increase_size(new_size as u32, disk_inode) as usize;
+            disk_inode.mark_unwritten(old_blocks, &geometry, &self.block_device);
+            // the tail of the old last block is still a written one
+            let tail_end = old_size.next_multiple_of(geometry.block_size).min(new_size);
+            self.write_stored(disk_inode, tail_end, &[], old_size);
+        }
+        self.store_disk_inode(&mut inner);
+        block_cache_sync_all();
+    }
+
+    /// Replace the data in current inode with `data` stored compressed
+    pub fn write_compressed(&self, data: &[u8]) {
+        self.check_writable();
+        let mut inner = self.inner.write();
+        let disk_inode = &mut inner.disk_inode;
+        self.clear_data(disk_inode);
+        self.fs.enable_feature_incompat(FEATURE_INCOMPAT_COMPRESSION);
+        let stored = compress::pack(data);
+        self.write_data(disk_inode, 0, &stored);
+        disk_inode.set_compressed(true);
+        self.store_disk_inode(&mut inner);
+        block_cache_sync_all();
+    }
+}
+
+impl Drop for Inode {
+    /// The last handle is gone: write back the metadata, then leave the cache
+    fn drop(&mut self) {
+        self.sync();
+        self.fs.inode_cache.release(self.inode_id, self);
+    }
+}
+
+impl FsInode for Inode {
+    fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+
+    fn is_dir(&self) -> bool {
+        Inode::is_dir(self)
+    }
+
+    fn is_file(&self) -> bool {
+        Inode::is_file(self)
+    }
+
+    fn size(&self) -> usize {
+        Inode::size(self)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        Inode::read_at(self, offset, buf)
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        Inode::find(self, name).map(|inode| inode as Arc<dyn FsInode>)
+    }
+
+    fn ls(&self) -> Vec<String> {
+        Inode::ls(self)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.fs.is_read_only()
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        Inode::write_at(self, offset, buf)
+    }
+
+    fn clear(&self) {
+        Inode::clear(self)
+    }
+
+    fn create(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        Inode::create(self, name).map(|inode| inode as Arc<dyn FsInode>)
+    }
+
+    fn create_dir(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        Inode::create_dir(self, name).map(|inode| inode as Arc<dyn FsInode>)
+    }
 
-//TODO
+    fn unlink(&self, name: &str) -> bool {
+        Inode::unlink(self, name)
+    }
+}
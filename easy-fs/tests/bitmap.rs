@@ -0,0 +1,63 @@
+mod common;
+
+use common::{as_device, ram_disk, serial};
+use easy_fs::{block_cache_sync_all, Bitmap, BLOCK_SIZES};
+
+#[test]
+fn alloc_is_sequential_until_full() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let device = as_device(&ram_disk(3, block_size));
+        let bitmap = Bitmap::new(1, 2, block_size);
+        assert_eq!(bitmap.maximum(), 2 * block_size * 8);
+        for bit in 0..bitmap.maximum() {
+            assert_eq!(bitmap.alloc(&device), Some(bit));
+        }
+        assert_eq!(bitmap.alloc(&device), None);
+        block_cache_sync_all();
+    }
+}
+
+#[test]
+fn dealloc_makes_bit_reusable() {
+    let _guard = serial();
+    let block_size = 512;
+    let device = as_device(&ram_disk(3, block_size));
+    let bitmap = Bitmap::new(1, 2, block_size);
+    for _ in 0..5000 {
+        bitmap.alloc(&device).unwrap();
+    }
+    // a bit in the second block and one in the first
+    bitmap.dealloc(&device, 4097);
+    bitmap.dealloc(&device, 63);
+    assert_eq!(bitmap.alloc(&device), Some(63));
+    assert_eq!(bitmap.alloc(&device), Some(4097));
+    assert_eq!(bitmap.alloc(&device), Some(5000));
+}
+
+#[test]
+fn bitmap_is_persisted() {
+    let _guard = serial();
+    let block_size = 1024;
+    let disk = ram_disk(2, block_size);
+    let device = as_device(&disk);
+    let bitmap = Bitmap::new(1, 1, block_size);
+    for _ in 0..70 {
+        bitmap.alloc(&device).unwrap();
+    }
+    block_cache_sync_all();
+    let bytes = disk.to_bytes();
+    let block = &bytes[block_size..2 * block_size];
+    assert!(block[..8].iter().all(|b| *b == 0xff));
+    assert_eq!(block[8], 0b0011_1111);
+    assert!(block[9..].iter().all(|b| *b == 0));
+}
+
+#[test]
+#[should_panic]
+fn dealloc_free_bit_panics() {
+    let _guard = serial();
+    let device = as_device(&ram_disk(2, 512));
+    let bitmap = Bitmap::new(1, 1, 512);
+    bitmap.dealloc(&device, 3);
+}
@@ -0,0 +1,40 @@
+//! Helpers shared by the host test suite
+#![allow(dead_code)]
+use easy_fs::{BlockDevice, RamDisk};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// All tests share the global block cache, which holds at most a few
+/// blocks at a time; tests touching it take this lock to run one by one.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// Serialize a test against the others of the same binary
+pub fn serial() -> MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A zeroed RamDisk of `blocks` blocks of `block_size` bytes
+pub fn ram_disk(blocks: usize, block_size: usize) -> Arc<RamDisk> {
+    Arc::new(RamDisk::new(blocks * block_size))
+}
+
+/// Erase the concrete type of a device
+pub fn as_device(disk: &Arc<RamDisk>) -> Arc<dyn BlockDevice> {
+    Arc::clone(disk) as Arc<dyn BlockDevice>
+}
+
+/// Deterministic test data of `len` bytes
+pub fn pattern(len: usize, seed: u32) -> Vec<u8> {
+    (0..len as u32)
+        .map(|i| (i.wrapping_mul(31).wrapping_add(seed) % 251) as u8)
+        .collect()
+}
+
+/// A fresh scratch dir for one test, under the target dir
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::compress::{self, compress, decompress, pack, CHUNK_SZ};
+use easy_fs::{block_cache_sync_all, get_block_cache, EasyFileSystem, RamDisk};
+use std::sync::Arc;
+
+/// bytes that look like an ELF binary: repeated words with some noise
+fn elf_like(len: usize) -> Vec<u8> {
+    let mut state = 0x1234_5678u32;
+    (0..len)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            if state.is_multiple_of(8) { state as u8 } else { (i % 64) as u8 }
+        })
+        .collect()
+}
+
+/// bytes that do not compress at all
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x9e37_79b9u32;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn round_trip(data: &[u8]) -> Vec<u8> {
+    let compressed = compress(data);
+    let mut out = vec![0u8; data.len()];
+    assert_eq!(decompress(&compressed, &mut out), Some(data.len()));
+    assert_eq!(out, data);
+    compressed
+}
+
+#[test]
+fn codec_round_trip() {
+    for len in 0..40 {
+        round_trip(&pattern(len, 3));
+        round_trip(&vec![7u8; len]);
+    }
+    round_trip(&elf_like(100_000));
+    round_trip(&noise(10_000));
+    // long literal runs and long matches both need extra length bytes
+    let mut data = noise(1000);
+    data.extend(vec![0u8; 5000]);
+    data.extend(noise(300));
+    round_trip(&data);
+}
+
+#[test]
+fn codec_shrinks_repetitive_data() {
+    assert!(round_trip(&vec![0u8; 4096]).len() < 64);
+    assert!(round_trip(&elf_like(4096)).len() < 3000);
+}
+
+#[test]
+fn decompress_rejects_garbage() {
+    let compressed = compress(&elf_like(4096));
+    let mut out = vec![0u8; 4096];
+    // too small a destination
+    assert_eq!(decompress(&compressed, &mut out[..100]), None);
+    // truncated input
+    assert_ne!(decompress(&compressed[..compressed.len() / 2], &mut out), Some(4096));
+    // a match pointing before the start
+    assert_eq!(decompress(&[0x0f, 0x10, 0x00], &mut out), None);
+}
+
+/// Read a compressed file held in `stored` at `offset`, as much as `len` bytes
+fn read_packed(stored: &[u8], offset: usize, len: usize) -> (usize, Vec<u8>) {
+    let read_raw = |pos: usize, buf: &mut [u8]| {
+        let pos = pos.min(stored.len());
+        let len = buf.len().min(stored.len() - pos);
+        buf[..len].copy_from_slice(&stored[pos..pos + len]);
+        len
+    };
+    let mut buf = vec![0u8; len];
+    (compress::read_at(offset, &mut buf, &read_raw), buf)
+}
+
+#[test]
+fn corrupted_headers_read_nothing() {
+    let data = elf_like(3 * CHUNK_SZ);
+    let stored = pack(&data);
+    assert_eq!(read_packed(&stored, 0, data.len()), (data.len(), data.clone()));
+    let set_u32 = |pos: usize, value: u32| {
+        let mut stored = stored.clone();
+        stored[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+        stored
+    };
+    // no chunk size, chunks larger than ever written, a chunk table
+    // running past the end of the file, and a file shorter than a header
+    for bad in [set_u32(4, 0), set_u32(4, u32::MAX), set_u32(0, u32::MAX), stored[..6].to_vec()] {
+        assert_eq!(read_packed(&bad, 0, data.len()).0, 0);
+    }
+}
+
+#[test]
+fn corrupted_chunks_cut_reads_short() {
+    let data = elf_like(3 * CHUNK_SZ);
+    let stored = pack(&data);
+    let ends: Vec<u32> = (0..3).map(|i| u32::from_le_bytes(stored[8 + i * 4..12 + i * 4].try_into().unwrap())).collect();
+    let set_end = |chunk: usize, value: u32| {
+        let mut stored = stored.clone();
+        stored[8 + chunk * 4..12 + chunk * 4].copy_from_slice(&value.to_le_bytes());
+        stored
+    };
+    // the second chunk ends before it starts, or is longer than its data
+    for bad in [set_end(1, ends[0] - 1), set_end(1, ends[0] + CHUNK_SZ as u32 + 1)] {
+        let (read, buf) = read_packed(&bad, 0, data.len());
+        assert_eq!(read, CHUNK_SZ);
+        assert_eq!(buf[..read], data[..read]);
+    }
+    // the last chunk is cut off
+    assert_eq!(read_packed(&stored[..stored.len() - 1], 0, data.len()).0, 2 * CHUNK_SZ);
+    // the second chunk does not decompress to a whole chunk
+    let mut bad = stored.clone();
+    let body = 8 + 3 * 4;
+    bad[body + ends[0] as usize..body + ends[1] as usize].fill(0);
+    assert_eq!(read_packed(&bad, 0, data.len()).0, CHUNK_SZ);
+    assert_eq!(read_packed(&bad, CHUNK_SZ, 10).0, 0);
+}
+
+#[test]
+fn corrupted_compressed_file_is_not_written() {
+    let _guard = serial();
+    let block_size = 512;
+    let disk = ram_disk(8192, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), 8192, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("file").unwrap();
+    file.write_compressed(&elf_like(3 * CHUNK_SZ));
+    file.sync();
+    // one chunk more than stored: the chunk table runs into the data
+    let disk_inode = efs.read_disk_inode(file.inode_id());
+    let block_id = disk_inode.get_block_id(0, &efs.geometry, &efs.block_device);
+    get_block_cache(block_id as usize, block_size, Arc::clone(&efs.block_device))
+        .lock()
+        .modify(0, |raw_size: &mut u32| *raw_size = 4 * CHUNK_SZ as u32);
+    assert_eq!(file.size(), 4 * CHUNK_SZ);
+    let mut buf = vec![0u8; 4 * CHUNK_SZ];
+    assert!(file.read_at(0, &mut buf) < 4 * CHUNK_SZ);
+    assert_eq!(file.write_at(0, b"x"), 0);
+    file.truncate(10);
+    assert!(file.is_compressed());
+    assert_eq!(file.size(), 4 * CHUNK_SZ);
+    // a bad chunk size makes the header unusable
+    get_block_cache(block_id as usize, block_size, Arc::clone(&efs.block_device))
+        .lock()
+        .modify(4, |chunk_size: &mut u32| *chunk_size = 0);
+    assert_eq!(file.size(), 0);
+    assert_eq!(file.write_at(0, b"x"), 0);
+    // clearing still works and gives back a plain file
+    file.clear();
+    assert_eq!(file.write_at(0, b"x"), 1);
+    assert!(!file.is_compressed());
+}
+
+#[test]
+fn pack_stores_incompressible_chunks_raw() {
+    let data = noise(CHUNK_SZ * 2 + 10);
+    let stored = pack(&data);
+    // header, 3 chunk ends, then the raw data
+    assert_eq!(stored.len(), 8 + 3 * 4 + data.len());
+    assert_eq!(&stored[20..], &data[..]);
+}
+
+#[test]
+fn compressed_file_reads_like_a_plain_one() {
+    let _guard = serial();
+    let block_size = 1024;
+    let disk = ram_disk(8192, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), 8192, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let mut data = elf_like(5 * CHUNK_SZ + 123);
+    data.extend(noise(CHUNK_SZ + 7));
+    let plain = root.create("plain").unwrap();
+    plain.write_at(0, &data);
+    let packed = root.create("packed").unwrap();
+    packed.write_compressed(&data);
+    assert!(packed.is_compressed());
+    assert_eq!(packed.size(), data.len());
+    // whole file
+    let mut buf = vec![0u8; data.len() + 100];
+    assert_eq!(packed.read_at(0, &mut buf), data.len());
+    assert_eq!(&buf[..data.len()], &data[..]);
+    // random ranges, inside a chunk and across chunks
+    for (offset, len) in [(0, 1), (10, 100), (CHUNK_SZ - 5, 10), (3 * CHUNK_SZ + 1, 2 * CHUNK_SZ), (data.len() - 3, 10)] {
+        let mut a = vec![0u8; len];
+        let mut b = vec![0u8; len];
+        assert_eq!(packed.read_at(offset, &mut a), plain.read_at(offset, &mut b));
+        assert_eq!(a, b);
+    }
+    assert_eq!(packed.read_at(data.len(), &mut buf), 0);
+}
+
+#[test]
+fn writing_a_compressed_file_makes_it_plain() {
+    let _guard = serial();
+    let block_size = 512;
+    let disk = ram_disk(8192, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), 8192, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let data = elf_like(3 * CHUNK_SZ);
+    let file = root.create("file").unwrap();
+    file.write_compressed(&data);
+    file.write_at(CHUNK_SZ, b"patched");
+    assert!(!file.is_compressed());
+    let mut expected = data.clone();
+    expected[CHUNK_SZ..CHUNK_SZ + 7].copy_from_slice(b"patched");
+    let mut buf = vec![0u8; expected.len()];
+    assert_eq!(file.read_at(0, &mut buf), expected.len());
+    assert_eq!(buf, expected);
+}
+
+#[test]
+fn compressed_file_survives_remount() {
+    let _guard = serial();
+    let block_size = 4096;
+    let disk = ram_disk(4096, block_size);
+    let data = elf_like(10 * CHUNK_SZ + 1);
+    {
+        let efs = EasyFileSystem::create(as_device(&disk), 4096, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        root.create("file").unwrap().write_compressed(&data);
+    }
+    block_cache_sync_all();
+    let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+    let efs = EasyFileSystem::open(as_device(&copy));
+    let file = EasyFileSystem::root_inode(&efs).find("file").unwrap();
+    assert!(file.is_compressed());
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf), data.len());
+    assert_eq!(buf, data);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::{block_cache_sync_all, EasyFileSystem, RamDisk, BLOCK_SIZES};
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+const IMAGE_SIZE: usize = 16 * 1024 * 1024;
+const THREADS: usize = 4;
+
+/// the data thread `t` ends up with in its own file
+fn expected(t: usize) -> Vec<u8> {
+    pattern(16 * 1024 + t * 1111, t as u32)
+}
+
+#[test]
+fn threads_write_their_own_files() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let handles: Vec<_> = (0..THREADS)
+            .map(|t| {
+                let root = Arc::clone(&root);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    // creating in the same directory at the same time
+                    let file = root.create(&format!("file{}", t)).unwrap();
+                    let data = expected(t);
+                    // write in uneven pieces, back to front
+                    let mut end = data.len();
+                    while end > 0 {
+                        let start = end.saturating_sub(777 + t * 13);
+                        assert_eq!(file.write_at(start, &data[start..end]), end - start);
+                        end = start;
+                    }
+                    // a second handle sees the same data
+                    let again = root.find(&format!("file{}", t)).unwrap();
+                    let mut buf = vec![0u8; data.len()];
+                    assert_eq!(again.read_at(0, &mut buf), data.len());
+                    assert_eq!(buf, data);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        block_cache_sync_all();
+        // check everything from a copy of the image
+        let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+        let efs = EasyFileSystem::open(as_device(&copy));
+        let root = EasyFileSystem::root_inode(&efs);
+        assert_eq!(root.ls().len(), THREADS);
+        for t in 0..THREADS {
+            let data = expected(t);
+            let file = root.find(&format!("file{}", t)).unwrap();
+            let mut buf = vec![0u8; data.len() + 1];
+            assert_eq!(file.read_at(0, &mut buf), data.len());
+            assert_eq!(&buf[..data.len()], &data[..]);
+        }
+    }
+}
+
+#[test]
+fn readers_and_writers_share_files() {
+    let _guard = serial();
+    let block_size = 1024;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    // a file that is only read
+    let shared = pattern(100 * 1024, 99);
+    root.create("shared").unwrap().write_at(0, &shared);
+    // a file every writer appends records to
+    root.create("log").unwrap();
+    let record = |t: usize| [t as u8; 64];
+    let rounds = 100;
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let root = Arc::clone(&root);
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let file = root.find("shared").unwrap();
+                let log = root.find("log").unwrap();
+                let mut buf = vec![0u8; 3000];
+                for i in 0..rounds {
+                    if t % 2 == 0 {
+                        let offset = (i * 4099 + t * 37) % (shared.len() - buf.len());
+                        assert_eq!(file.read_at(offset, &mut buf), buf.len());
+                        assert_eq!(&buf[..], &shared[offset..offset + buf.len()]);
+                    } else {
+                        // each record is written under the lock of the log
+                        // at some offset no other record gets
+                        let offset = (i * THREADS + t) * 64;
+                        log.write_at(offset, &record(t));
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let log = root.find("log").unwrap();
+    let writers = (0..THREADS).filter(|t| t % 2 == 1).max().unwrap();
+    assert_eq!(log.size(), ((rounds - 1) * THREADS + writers + 1) * 64);
+    for i in 0..rounds {
+        for t in (0..THREADS).filter(|t| t % 2 == 1) {
+            let mut buf = [0u8; 64];
+            log.read_at((i * THREADS + t) * 64, &mut buf);
+            assert_eq!(buf, record(t));
+        }
+    }
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::crypt::{Cipher, Key};
+use easy_fs::{block_cache_sync_all, compress, EasyFileSystem, RamDisk, BLOCK_SIZES};
+use std::sync::Arc;
+
+const IMAGE_SIZE: usize = 16 * 1024 * 1024;
+const KEY: Key = [7; 32];
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[test]
+fn chacha20_block_test_vector() {
+    // RFC 8439, 2.3.2
+    let key: Key = core::array::from_fn(|i| i as u8);
+    let block = Cipher::new(&key).block(1, [0x0900_0000, 0x4a00_0000, 0]);
+    let expected: [u8; 64] = [
+        0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20, 0x71, 0xc4,
+        0xc7, 0xd1, 0xf4, 0xc7, 0x33, 0xc0, 0x68, 0x03, 0x04, 0x22, 0xaa, 0x9a, 0xc3, 0xd4, 0x6c, 0x4e,
+        0xd2, 0x82, 0x64, 0x46, 0x07, 0x9f, 0xaa, 0x09, 0x14, 0xc2, 0xd7, 0x05, 0xd9, 0x8b, 0x02, 0xa2,
+        0xb5, 0x12, 0x9c, 0xd1, 0xde, 0x16, 0x4e, 0xb9, 0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50, 0x3c, 0x4e,
+    ];
+    assert_eq!(block, expected);
+}
+
+#[test]
+fn apply_at_any_offset_matches_whole_block() {
+    let cipher = Cipher::new(&KEY);
+    let data = pattern(4096, 3);
+    let mut whole = data.clone();
+    cipher.apply(5, 9, 0, &mut whole);
+    assert_ne!(whole, data);
+    // encrypting piece by piece gives the same bytes
+    let mut pieces = data.clone();
+    for (start, end) in [(0, 1), (1, 63), (63, 130), (130, 4096)] {
+        cipher.apply(5, 9, start, &mut pieces[start..end]);
+    }
+    assert_eq!(pieces, whole);
+    // and the cipher is its own inverse
+    cipher.apply(5, 9, 0, &mut whole);
+    assert_eq!(whole, data);
+}
+
+#[test]
+fn file_data_is_encrypted_on_disk() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let secret = b"this sentence must never reach the disk in clear".repeat(100);
+        {
+            let efs = EasyFileSystem::create_encrypted(
+                as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size, &KEY,
+            );
+            let root = EasyFileSystem::root_inode(&efs);
+            let file = root.create("secret_file").unwrap();
+            file.write_at(0, &secret);
+            let mut buf = vec![0u8; secret.len()];
+            assert_eq!(file.read_at(0, &mut buf), secret.len());
+            assert_eq!(buf, secret);
+        }
+        block_cache_sync_all();
+        let image = disk.to_bytes();
+        assert!(!contains(&image, b"this sentence"));
+        // metadata stays in plain text
+        assert!(contains(&image, b"secret_file"));
+    }
+}
+
+#[test]
+fn remount_with_key() {
+    let _guard = serial();
+    let block_size = 1024;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let data = pattern(70 * 1024 + 5, 11);
+    {
+        let efs = EasyFileSystem::create_encrypted(
+            as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size, &KEY,
+        );
+        let root = EasyFileSystem::root_inode(&efs);
+        root.create("a").unwrap().write_at(0, &data);
+        root.create("b").unwrap().write_at(0, &data);
+    }
+    block_cache_sync_all();
+    let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+    let efs = EasyFileSystem::open_with_key(as_device(&copy), &KEY);
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.ls(), ["a", "b"]);
+    for name in ["a", "b"] {
+        let mut buf = vec![0u8; data.len()];
+        assert_eq!(root.find(name).unwrap().read_at(0, &mut buf), data.len());
+        assert_eq!(buf, data);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Wrong key")]
+fn open_with_wrong_key_panics() {
+    let _guard = serial();
+    let disk = ram_disk(IMAGE_SIZE / 512, 512);
+    EasyFileSystem::create_encrypted(as_device(&disk), (IMAGE_SIZE / 512) as u32, 1, 512, &KEY);
+    block_cache_sync_all();
+    let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+    EasyFileSystem::open_with_key(as_device(&copy), &[8; 32]);
+}
+
+#[test]
+#[should_panic(expected = "a key is needed")]
+fn open_encrypted_without_key_panics() {
+    let _guard = serial();
+    let disk = ram_disk(IMAGE_SIZE / 512, 512);
+    EasyFileSystem::create_encrypted(as_device(&disk), (IMAGE_SIZE / 512) as u32, 1, 512, &KEY);
+    block_cache_sync_all();
+    let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+    EasyFileSystem::open(as_device(&copy));
+}
+
+#[test]
+fn writing_past_the_end_reads_zeros() {
+    let _guard = serial();
+    let block_size = 512;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create_encrypted(
+        as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size, &KEY,
+    );
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("sparse").unwrap();
+    file.write_at(0, b"head");
+    file.write_at(3000, b"tail");
+    let mut buf = vec![0u8; 3004];
+    assert_eq!(file.read_at(0, &mut buf), 3004);
+    assert_eq!(&buf[..4], b"head");
+    assert!(buf[4..3000].iter().all(|&b| b == 0));
+    assert_eq!(&buf[3000..], b"tail");
+}
+
+#[test]
+fn compressed_and_encrypted() {
+    let _guard = serial();
+    let block_size = 2048;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let data = b"compressible compressible compressible ".repeat(2000);
+    {
+        let efs = EasyFileSystem::create_encrypted(
+            as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size, &KEY,
+        );
+        let root = EasyFileSystem::root_inode(&efs);
+        root.create("both").unwrap().write_compressed(&data);
+    }
+    block_cache_sync_all();
+    // the compressed form is not visible either
+    let packed = compress::pack(&data);
+    assert!(!contains(&disk.to_bytes(), &packed[..64]));
+    let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+    let efs = EasyFileSystem::open_with_key(as_device(&copy), &KEY);
+    let file = EasyFileSystem::root_inode(&efs).find("both").unwrap();
+    assert!(file.is_compressed());
+    assert_eq!(file.size(), data.len());
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(file.read_at(0, &mut buf), data.len());
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn truncate_of_an_encrypted_file_reads_zeros() {
+    let _guard = serial();
+    let block_size = 512;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create_encrypted(
+        as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size, &KEY,
+    );
+    let file = EasyFileSystem::root_inode(&efs).create("file").unwrap();
+    let data = pattern(20000, 2);
+    file.write_at(0, &data);
+    file.truncate(700);
+    file.truncate(4000);
+    let mut buf = vec![0u8; 4000];
+    assert_eq!(file.read_at(0, &mut buf), 4000);
+    assert_eq!(&buf[..700], &data[..700]);
+    assert!(buf[700..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn fallocate_of_an_encrypted_file_reads_zeros() {
+    let _guard = serial();
+    let block_size = 1024;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create_encrypted(
+        as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size, &KEY,
+    );
+    let file = EasyFileSystem::root_inode(&efs).create("file").unwrap();
+    file.write_at(0, b"abc");
+    file.fallocate(10000);
+    file.write_at(5000, b"xyz");
+    let mut buf = vec![0u8; 10000];
+    assert_eq!(file.read_at(0, &mut buf), 10000);
+    assert_eq!(&buf[..3], b"abc");
+    assert_eq!(&buf[5000..5003], b"xyz");
+    assert_eq!(buf.iter().filter(|&&b| b != 0).count(), 6);
+}
+
+#[test]
+fn a_full_disk_leaves_the_gap_reading_zeros() {
+    let _guard = serial();
+    let block_size = 512;
+    // one inode bitmap block takes 1024 inode blocks, leave 63 data blocks
+    let blocks = 1090;
+    let disk = ram_disk(blocks, block_size);
+    let efs = EasyFileSystem::create_encrypted(as_device(&disk), blocks as u32, 1, block_size, &KEY);
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("file").unwrap();
+    assert_eq!(file.write_at(0, b"head"), 4);
+    // the write lies past what the disk can hold, the file grows up to there
+    assert_eq!(file.write_at(blocks * block_size, b"tail"), 0);
+    let size = file.size();
+    assert!(size > 4 && size < blocks * block_size);
+    let mut buf = vec![0xffu8; size];
+    assert_eq!(file.read_at(0, &mut buf), size);
+    assert_eq!(&buf[..4], b"head");
+    assert!(buf[4..].iter().all(|byte| *byte == 0));
+}
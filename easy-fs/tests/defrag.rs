@@ -0,0 +1,117 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::{block_cache_sync_all, Bitmap, DiskInode, EasyFileSystem, RamDisk, BLOCK_SIZES};
+use std::sync::Arc;
+
+const FILES: usize = 6;
+
+/// the data file `i` ends up with, some going through indirect2 at small block sizes
+fn expected(i: usize, block_size: usize) -> Vec<u8> {
+    pattern(block_size * (20 + 70 * i) + i * 77, i as u32)
+}
+
+/// Grow files a block at a time in turns, so their blocks interleave,
+/// with files created and removed in between
+fn fragmented_image(block_size: usize, total_blocks: usize) -> Arc<RamDisk> {
+    let disk = ram_disk(total_blocks, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), total_blocks as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let dir = root.create_dir("dir").unwrap();
+    let files: Vec<_> = (0..FILES).map(|i| dir.create(&format!("file{}", i)).unwrap()).collect();
+    let data: Vec<_> = (0..FILES).map(|i| expected(i, block_size)).collect();
+    let longest = data.iter().map(|data| data.len()).max().unwrap();
+    for (round, offset) in (0..longest).step_by(block_size).enumerate() {
+        for (file, data) in files.iter().zip(data.iter()) {
+            if offset < data.len() {
+                let end = (offset + block_size).min(data.len());
+                file.write_at(offset, &data[offset..end]);
+            }
+        }
+        if round % 7 == 0 {
+            let name = format!("tmp{}", round);
+            root.create(&name).unwrap().write_at(0, &pattern(block_size * 3, 0));
+            if round % 14 == 0 {
+                root.unlink(&name);
+            }
+        }
+    }
+    root.create("reserved").unwrap().fallocate(block_size * 5);
+    block_cache_sync_all();
+    disk
+}
+
+fn check(efs: &Arc<EasyFileSystem>, block_size: usize) {
+    let root = EasyFileSystem::root_inode(efs);
+    let dir = root.find("dir").unwrap();
+    for i in 0..FILES {
+        let data = expected(i, block_size);
+        let mut buf = vec![0u8; data.len() + 1];
+        assert_eq!(dir.find(&format!("file{}", i)).unwrap().read_at(0, &mut buf), data.len());
+        assert_eq!(&buf[..data.len()], &data[..]);
+    }
+    let mut buf = vec![1u8; block_size * 5];
+    root.find("reserved").unwrap().read_at(0, &mut buf);
+    assert!(buf.iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn defrag_leaves_one_run_per_inode() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = fragmented_image(block_size, 4096);
+        let efs = EasyFileSystem::open(as_device(&disk));
+        let before = efs.fragmentation();
+        assert!(before.fragmented >= FILES, "{}", before);
+        assert!(efs.defrag() > 0);
+        let after = efs.fragmentation();
+        assert_eq!(after.fragmented, 0, "{}", after);
+        assert_eq!(after.extents, after.inodes);
+        assert_eq!(after.data_blocks, before.data_blocks);
+        // everything is packed at the front of the data area
+        assert_eq!(after.free_extents, 1);
+        drop(efs);
+        let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+        let efs = EasyFileSystem::open(as_device(&copy));
+        check(&efs, block_size);
+        assert_eq!(efs.fragmentation(), after);
+        // defragmenting again moves nothing
+        assert_eq!(efs.defrag(), 0);
+    }
+}
+
+#[test]
+fn defrag_needs_only_one_free_block() {
+    let _guard = serial();
+    let block_size = 512;
+    let disk = fragmented_image(block_size, 4096);
+    let efs = EasyFileSystem::open(as_device(&disk));
+    let geo = efs.geometry;
+    let super_block = efs.super_block();
+    let data_bitmap_start = 1 + super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
+    let bitmap = Bitmap::new(data_bitmap_start as usize, super_block.data_bitmap_blocks as usize, block_size);
+    let free_blocks = || {
+        (0..super_block.data_area_blocks as usize)
+            .filter(|bit| !bitmap.is_allocated(&efs.block_device, *bit))
+            .count() as u32
+    };
+    // take every free block but one with a file growing a block at a time
+    let fill = EasyFileSystem::root_inode(&efs).create("fill").unwrap();
+    let mut free = free_blocks();
+    let mut size = 0;
+    loop {
+        let needed = DiskInode::total_blocks((size + block_size) as u32, &geo)
+            - DiskInode::total_blocks(size as u32, &geo);
+        if free - needed < 1 {
+            break;
+        }
+        fill.write_at(size, &pattern(block_size, 5));
+        size += block_size;
+        free -= needed;
+    }
+    assert_eq!(free_blocks(), 1);
+    drop(fill);
+    efs.defrag();
+    assert_eq!(efs.fragmentation().fragmented, 0);
+    check(&efs, block_size);
+}
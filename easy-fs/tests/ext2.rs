@@ -0,0 +1,157 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, scratch, serial};
+use easy_fs::ext2::{Ext2FileSystem, Ext2Inode};
+use easy_fs::{mount_root, probe, EasyFileSystem, FsInode, FsType, RamDisk};
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+/// the files of the host tree `source` is filled with
+fn files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("empty", Vec::new()),
+        ("small", pattern(100, 1)),
+        // past the direct blocks
+        ("dir/medium", pattern(50 * 1024 + 7, 2)),
+        // past the indirect block of 1 KiB blocks
+        ("dir/sub/large", pattern(700 * 1024 + 3, 3)),
+    ]
+}
+
+/// Make an ext2 image of `blocks` blocks of `block_size` bytes holding `source`
+/**
+    Returns `None` when `mke2fs` cannot be run on this host.
+*/
+fn mke2fs(dir: &Path, source: &Path, block_size: usize, blocks: usize) -> Option<Arc<RamDisk>> {
+    let image = dir.join("ext2.img");
+    let status = Command::new("mke2fs")
+        .args(["-q", "-F", "-t", "ext2", "-b", &block_size.to_string(), "-d"])
+        .arg(source)
+        .arg(&image)
+        .arg(blocks.to_string())
+        .status();
+    match status {
+        Ok(status) => assert!(status.success(), "mke2fs failed"),
+        Err(_) => {
+            eprintln!("mke2fs not found, skipped");
+            return None;
+        }
+    }
+    Some(Arc::new(RamDisk::from_bytes(fs::read(&image).unwrap())))
+}
+
+/// Fill `source` with `files`, plus a sparse file
+fn host_tree(source: &Path) {
+    for (path, data) in files() {
+        let path = source.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+    }
+    let sparse = fs::File::create(source.join("sparse")).unwrap();
+    sparse.set_len(5 << 20).unwrap();
+    fs::write(source.join("dir/sub/tail"), b"tail").unwrap();
+}
+
+fn lookup(root: &Arc<Ext2Inode>, path: &str) -> Arc<Ext2Inode> {
+    path.split('/').fold(Arc::clone(root), |dir, name| dir.find(name).unwrap())
+}
+
+#[test]
+fn reads_images_made_by_mke2fs() {
+    let _guard = serial();
+    for block_size in [1024, 4096] {
+        let dir = scratch(&format!("ext2_{}", block_size));
+        let source = dir.join("source");
+        host_tree(&source);
+        let Some(disk) = mke2fs(&dir, &source, block_size, (16 << 20) / block_size) else {
+            return;
+        };
+        let fs = Ext2FileSystem::open(as_device(&disk));
+        assert_eq!(fs.block_size, block_size);
+        let root = Ext2FileSystem::root_inode(&fs);
+        assert!(root.is_dir());
+        let mut names = root.ls();
+        names.sort();
+        assert_eq!(names, ["dir", "empty", "lost+found", "small", "sparse"]);
+        // ".", "..", and the ".." of "dir" and "lost+found"
+        assert_eq!(FsInode::nlink(&*root), 4);
+        assert_eq!(FsInode::nlink(&*root.find("small").unwrap()), 1);
+        assert!(root.find("missing").is_none());
+        for (path, data) in files() {
+            let file = lookup(&root, path);
+            assert!(file.is_file());
+            assert_eq!(file.size(), data.len(), "{}", path);
+            let mut buf = vec![0u8; data.len() + 10];
+            assert_eq!(file.read_at(0, &mut buf), data.len(), "{}", path);
+            assert_eq!(&buf[..data.len()], &data[..], "{}", path);
+        }
+        // reads in the middle, across block boundaries
+        let large = lookup(&root, "dir/sub/large");
+        let mut buf = vec![0u8; 3000];
+        assert_eq!(large.read_at(300 * 1024 - 1000, &mut buf), 3000);
+        assert_eq!(buf, files()[3].1[300 * 1024 - 1000..300 * 1024 + 2000]);
+        // holes read as zeros
+        let sparse = root.find("sparse").unwrap();
+        assert_eq!(sparse.size(), 5 << 20);
+        let mut buf = vec![1u8; 64 * 1024];
+        assert_eq!(sparse.read_at(4 << 20, &mut buf), buf.len());
+        assert!(buf.iter().all(|byte| *byte == 0));
+    }
+}
+
+#[test]
+fn mount_root_picks_the_driver_by_magic() {
+    let _guard = serial();
+    let dir = scratch("ext2_probe");
+    let source = dir.join("source");
+    host_tree(&source);
+    let Some(ext2_disk) = mke2fs(&dir, &source, 1024, 8192) else {
+        return;
+    };
+    assert_eq!(probe(&as_device(&ext2_disk)), Some(FsType::Ext2));
+    let root = mount_root(as_device(&ext2_disk)).unwrap();
+    assert!(root.is_read_only());
+    let small = root.find("small").unwrap();
+    let mut buf = [0u8; 100];
+    assert_eq!(small.read_at(0, &mut buf), 100);
+    assert_eq!(buf[..], pattern(100, 1)[..]);
+    // nothing can be written on ext2
+    assert_eq!(small.write_at(0, b"new"), 0);
+    assert!(root.create("new").is_none());
+
+    let efs_disk = ram_disk(4096, 512);
+    let efs = EasyFileSystem::create(as_device(&efs_disk), 4096, 1, 512);
+    EasyFileSystem::root_inode(&efs).create("small").unwrap().write_at(0, b"efs");
+    assert_eq!(probe(&as_device(&efs_disk)), Some(FsType::Easy));
+    let root = mount_root(as_device(&efs_disk)).unwrap();
+    assert!(!root.is_read_only());
+    assert_eq!(root.find("small").unwrap().size(), 3);
+    assert!(root.create("new").is_some());
+
+    assert_eq!(probe(&as_device(&ram_disk(64, 512))), None);
+    assert!(mount_root(as_device(&ram_disk(64, 512))).is_none());
+}
+
+#[test]
+fn ext4_images_are_refused() {
+    let _guard = serial();
+    let dir = scratch("ext2_ext4");
+    let image = dir.join("ext4.img");
+    let status = Command::new("mke2fs")
+        .args(["-q", "-F", "-t", "ext4", "-b", "1024"])
+        .arg(&image)
+        .arg("8192")
+        .status();
+    if status.is_err() {
+        eprintln!("mke2fs not found, skipped");
+        return;
+    }
+    let disk = Arc::new(RamDisk::from_bytes(fs::read(&image).unwrap()));
+    // it has the magic of ext2, but extents are an incompat feature
+    assert_eq!(probe(&as_device(&disk)), Some(FsType::Ext2));
+    let opened = panic::catch_unwind(AssertUnwindSafe(|| Ext2FileSystem::open(as_device(&disk))));
+    assert!(opened.is_err());
+}
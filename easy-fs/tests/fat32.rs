@@ -0,0 +1,238 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, scratch, serial};
+use easy_fs::fat32::{Fat32FileSystem, FatInode};
+use easy_fs::{mount_root, probe, FsType, RamDisk};
+use std::fs;
+use std::process::Command;
+use std::sync::Arc;
+
+/// 64 MiB of 512 byte sectors, a cluster per sector
+const SECTORS: usize = 128 * 1024;
+
+/// the files written in each test, by path
+fn files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("README", pattern(100, 1)),
+        ("empty.txt", Vec::new()),
+        ("A long file name, with spaces.data", pattern(50 * 1024 + 7, 2)),
+        ("dir/héllo wörld", pattern(1000, 3)),
+        ("dir/sub/Mixed.Case", pattern(3 * 512, 4)),
+    ]
+}
+
+fn lookup(root: &Arc<FatInode>, path: &str) -> Arc<FatInode> {
+    path.split('/').fold(Arc::clone(root), |dir, name| dir.find(name).unwrap())
+}
+
+/// Create `files()` under `root`, making the directories on the way
+fn fill(root: &Arc<FatInode>) {
+    for (path, data) in files() {
+        let (dir_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let mut dir = Arc::clone(root);
+        for part in dir_path.split('/').filter(|part| !part.is_empty()) {
+            dir = dir.find(part).unwrap_or_else(|| dir.create_dir(part).unwrap());
+        }
+        assert_eq!(dir.create(name).unwrap().write_at(0, &data), data.len());
+    }
+}
+
+/// Check `files()` under `root`
+fn check(root: &Arc<FatInode>) {
+    for (path, data) in files() {
+        let file = lookup(root, path);
+        assert!(file.is_file());
+        assert_eq!(file.size(), data.len(), "{}", path);
+        let mut buf = vec![0u8; data.len() + 10];
+        assert_eq!(file.read_at(0, &mut buf), data.len(), "{}", path);
+        assert_eq!(&buf[..data.len()], &data[..], "{}", path);
+    }
+}
+
+#[test]
+fn files_survive_a_remount() {
+    let _guard = serial();
+    let disk = ram_disk(SECTORS, 512);
+    let fs = Fat32FileSystem::format(as_device(&disk), SECTORS as u32);
+    let root = Fat32FileSystem::root_inode(&fs);
+    assert!(root.ls().is_empty());
+    fill(&root);
+    check(&root);
+    let mut names = root.ls();
+    names.sort();
+    assert_eq!(names, ["A long file name, with spaces.data", "README", "dir", "empty.txt"]);
+    // a copy of the image, nothing can come from the cache of `disk`
+    let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+    let fs = Fat32FileSystem::open(as_device(&copy));
+    let root = Fat32FileSystem::root_inode(&fs);
+    check(&root);
+    // names are looked up ignoring case, and by their 8.3 name
+    assert!(root.find("readme").is_some());
+    assert!(root.find("EMPTY.TXT").is_some());
+    assert!(root.find("ALONGF~1.DAT").is_some());
+    assert!(root.create("Readme").is_none());
+    assert_eq!(lookup(&root, "dir/sub").ls(), ["Mixed.Case"]);
+}
+
+#[test]
+fn writes_grow_and_clear_frees() {
+    let _guard = serial();
+    let disk = ram_disk(SECTORS, 512);
+    let fs = Fat32FileSystem::format(as_device(&disk), SECTORS as u32);
+    let root = Fat32FileSystem::root_inode(&fs);
+    let free = fs.free_clusters();
+    let file = root.create("file").unwrap();
+    file.write_at(0, b"head");
+    // past the end: the gap reads as zeros
+    let data = pattern(20 * 1024, 5);
+    file.write_at(10000, &data);
+    assert_eq!(file.size(), 10000 + data.len());
+    let mut buf = vec![1u8; file.size()];
+    assert_eq!(file.read_at(0, &mut buf), buf.len());
+    assert_eq!(&buf[..4], b"head");
+    assert!(buf[4..10000].iter().all(|byte| *byte == 0));
+    assert_eq!(&buf[10000..], &data[..]);
+    assert_eq!(fs.free_clusters(), free - (buf.len() as u32).div_ceil(512));
+    // a second handle sees the same file
+    let other = root.find("FILE").unwrap();
+    other.write_at(0, b"HEAD");
+    file.read_at(0, &mut buf[..4]);
+    assert_eq!(&buf[..4], b"HEAD");
+    file.clear();
+    assert_eq!(other.size(), 0);
+    assert_eq!(fs.free_clusters(), free);
+}
+
+#[test]
+fn writes_that_cannot_fit_are_cut_short() {
+    let _guard = serial();
+    // 1 MiB, small enough to fill
+    let sectors = 2048;
+    let disk = ram_disk(sectors, 512);
+    let fs = Fat32FileSystem::format(as_device(&disk), sectors as u32);
+    let root = Fat32FileSystem::root_inode(&fs);
+    let file = root.create("file").unwrap();
+    // no entry holds a size of 4 GiB or more
+    assert_eq!(file.write_at(1 << 32, b"x"), 0);
+    assert_eq!(file.write_at(usize::MAX, b"x"), 0);
+    assert_eq!(file.size(), 0);
+    // a full volume stops the file at the end of its last cluster
+    let data = pattern(sectors * 512, 6);
+    let written = file.write_at(0, &data);
+    assert!(written > 0 && written < data.len());
+    assert_eq!(written % fs.cluster_size(), 0);
+    assert_eq!(fs.free_clusters(), 0);
+    assert_eq!(file.size(), written);
+    let mut buf = vec![0u8; written];
+    assert_eq!(file.read_at(0, &mut buf), written);
+    assert_eq!(buf, data[..written]);
+    assert_eq!(file.write_at(written, b"more"), 0);
+    // the root has room for the entry, but not a directory for its cluster
+    let other = root.create("other").unwrap();
+    assert_eq!(other.write_at(0, b"x"), 0);
+    assert!(root.create_dir("dir").is_none());
+    // directories are written through their entries only
+    file.clear();
+    let dir = root.create_dir("dir").unwrap();
+    assert_eq!(dir.write_at(0, b"x"), 0);
+    dir.create("inside").unwrap();
+    dir.clear();
+    assert_eq!(dir.ls(), ["inside"]);
+    assert_eq!(root.ls(), ["file", "other", "dir"]);
+}
+
+#[test]
+fn unlink_removes_entries_and_frees_them() {
+    let _guard = serial();
+    let disk = ram_disk(SECTORS, 512);
+    let fs = Fat32FileSystem::format(as_device(&disk), SECTORS as u32);
+    let root = Fat32FileSystem::root_inode(&fs);
+    let free = fs.free_clusters();
+    let dir = root.create_dir("a directory").unwrap();
+    // three entries per name, many clusters of entries
+    let names: Vec<String> = (0..200).map(|i| format!("long name number {}", i)).collect();
+    for name in names.iter() {
+        dir.create(name).unwrap().write_at(0, name.as_bytes());
+    }
+    assert_eq!(dir.ls(), names);
+    for name in names.iter() {
+        let mut buf = vec![0u8; name.len()];
+        dir.find(name).unwrap().read_at(0, &mut buf);
+        assert_eq!(buf, name.as_bytes());
+    }
+    assert!(!root.unlink("a directory"));
+    for name in names.iter().step_by(2) {
+        assert!(dir.unlink(name));
+        assert!(dir.find(name).is_none());
+    }
+    // freed entries are used again
+    dir.create("again").unwrap();
+    assert_eq!(dir.ls().len(), 101);
+    for name in names.iter().skip(1).step_by(2).chain(["again".to_string()].iter()) {
+        assert!(dir.unlink(name));
+    }
+    assert!(dir.ls().is_empty());
+    assert!(root.unlink("a directory"));
+    assert!(!root.unlink("a directory"));
+    assert_eq!(fs.free_clusters(), free);
+    assert!(root.ls().is_empty());
+}
+
+#[test]
+fn mount_root_picks_fat32() {
+    let _guard = serial();
+    let disk = ram_disk(SECTORS, 512);
+    Fat32FileSystem::format(as_device(&disk), SECTORS as u32);
+    assert_eq!(probe(&as_device(&disk)), Some(FsType::Fat32));
+    let root = mount_root(as_device(&disk)).unwrap();
+    assert!(!root.is_read_only());
+    let file = root.create("file").unwrap();
+    assert_eq!(file.write_at(0, b"fat"), 3);
+    assert_eq!(root.find("file").unwrap().size(), 3);
+}
+
+/// Run a host tool and return what it printed
+fn run(program: &str, args: &[&str]) -> Vec<u8> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("cannot run {}: {}", program, e));
+    assert!(output.status.success(), "{} failed: {}", program, String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+/// Run with `cargo test -- --ignored` where the tools are installed
+#[test]
+#[ignore = "needs mkfs.vfat, fsck.vfat and mtools on the host"]
+fn works_with_mkfs_vfat_and_mtools() {
+    let _guard = serial();
+    let dir = scratch("fat32_tools");
+    let image = dir.join("fat.img");
+    let image_arg = image.to_str().unwrap();
+    run("mkfs.vfat", &["-F", "32", "-C", image_arg, "65536"]);
+    // files put by mtools are read by the driver
+    let source = dir.join("source");
+    fs::create_dir_all(&source).unwrap();
+    let long_data = pattern(30 * 1024 + 1, 6);
+    fs::write(source.join("From mtools with a long name.bin"), &long_data).unwrap();
+    let mtools_file = source.join("From mtools with a long name.bin");
+    run("mcopy", &["-i", image_arg, mtools_file.to_str().unwrap(), "::/"]);
+    run("mmd", &["-i", image_arg, "::/made by mmd"]);
+    let disk = Arc::new(RamDisk::from_bytes(fs::read(&image).unwrap()));
+    let fs = Fat32FileSystem::open(as_device(&disk));
+    let root = Fat32FileSystem::root_inode(&fs);
+    let file = root.find("From mtools with a long name.bin").unwrap();
+    let mut buf = vec![0u8; long_data.len()];
+    assert_eq!(file.read_at(0, &mut buf), long_data.len());
+    assert_eq!(buf, long_data);
+    assert!(root.find("made by mmd").unwrap().is_dir());
+    // files written by the driver are read by mtools
+    fill(&root);
+    fs::write(&image, disk.to_bytes()).unwrap();
+    for (path, data) in files() {
+        let out = run("mtype", &["-i", image_arg, &format!("::/{}", path)]);
+        assert_eq!(out, data, "{}", path);
+    }
+    // and the volume is still consistent
+    run("fsck.vfat", &["-n", image_arg]);
+}
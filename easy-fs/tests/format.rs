@@ -0,0 +1,150 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::{
+    block_cache_sync_all, get_block_cache, EasyFileSystem, Geometry, RamDisk, SuperBlock,
+    BLOCK_SIZES, FEATURE_INCOMPAT_BLOCK_SIZE, FEATURE_INCOMPAT_COMPRESSION, FEATURE_INCOMPAT_UNWRITTEN,
+    FORMAT_VERSION,
+};
+use std::sync::Arc;
+
+const IMAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Format an image with a compressed and a preallocated file
+fn image(block_size: usize) -> Arc<RamDisk> {
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    root.create("plain").unwrap().write_at(0, &pattern(5000, 1));
+    root.create("packed").unwrap().write_compressed(&[7u8; 20000]);
+    root.create("reserved").unwrap().fallocate(30000);
+    block_cache_sync_all();
+    disk
+}
+
+/// Change the super block of an image and return a copy of the result
+fn patch_super_block(disk: &Arc<RamDisk>, block_size: usize, f: impl FnOnce(&mut SuperBlock)) -> Arc<RamDisk> {
+    get_block_cache(0, block_size, as_device(disk)).lock().modify(0, f);
+    block_cache_sync_all();
+    Arc::new(RamDisk::from_bytes(disk.to_bytes()))
+}
+
+#[test]
+fn new_image_records_version_and_features() {
+    let _guard = serial();
+    let disk = ram_disk(IMAGE_SIZE / 512, 512);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / 512) as u32, 1, 512);
+    let super_block = efs.super_block();
+    assert_eq!(super_block.version, FORMAT_VERSION);
+    assert_eq!(super_block.feature_incompat, 0);
+    // features are recorded when they are first used
+    let root = EasyFileSystem::root_inode(&efs);
+    root.create("packed").unwrap().write_compressed(b"abcabcabcabcabcabc");
+    assert_eq!(efs.super_block().feature_incompat, FEATURE_INCOMPAT_COMPRESSION);
+    root.create("reserved").unwrap().fallocate(1000);
+    assert_eq!(
+        efs.super_block().feature_incompat,
+        FEATURE_INCOMPAT_COMPRESSION | FEATURE_INCOMPAT_UNWRITTEN,
+    );
+    // a larger block size is a feature from the start
+    let disk = ram_disk(IMAGE_SIZE / 4096, 4096);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / 4096) as u32, 1, 4096);
+    assert_eq!(efs.super_block().feature_incompat, FEATURE_INCOMPAT_BLOCK_SIZE);
+}
+
+#[test]
+#[should_panic(expected = "unsupported features")]
+fn unknown_incompat_feature_refuses_mount() {
+    let _guard = serial();
+    let disk = image(512);
+    let copy = patch_super_block(&disk, 512, |super_block| super_block.feature_incompat |= 1 << 30);
+    EasyFileSystem::open(as_device(&copy));
+}
+
+#[test]
+fn unknown_compat_feature_is_ignored() {
+    let _guard = serial();
+    let disk = image(512);
+    let copy = patch_super_block(&disk, 512, |super_block| super_block.feature_compat |= 1 << 30);
+    let efs = EasyFileSystem::open(as_device(&copy));
+    assert!(!efs.is_read_only());
+    EasyFileSystem::root_inode(&efs).create("new").unwrap();
+}
+
+#[test]
+fn unknown_ro_compat_feature_mounts_read_only() {
+    let _guard = serial();
+    let disk = image(1024);
+    let copy = patch_super_block(&disk, 1024, |super_block| super_block.feature_ro_compat |= 1 << 30);
+    let efs = EasyFileSystem::open(as_device(&copy));
+    assert!(efs.is_read_only());
+    let root = EasyFileSystem::root_inode(&efs);
+    let mut buf = vec![0u8; 5000];
+    assert_eq!(root.find("plain").unwrap().read_at(0, &mut buf), 5000);
+    assert_eq!(buf, pattern(5000, 1));
+    // writes are refused, nothing changes
+    let plain = root.find("plain").unwrap();
+    assert!(root.create("new").is_none());
+    assert!(root.create_dir("new").is_none());
+    assert!(!root.unlink("plain"));
+    assert_eq!(plain.write_at(0, b"changed"), 0);
+    plain.clear();
+    assert_eq!(plain.size(), 5000);
+    assert_eq!(root.ls(), ["plain", "packed", "reserved"]);
+}
+
+#[test]
+fn legacy_image_is_read_only_until_upgraded() {
+    let _guard = serial();
+    for block_size in [512, 2048] {
+        let disk = image(block_size);
+        // an image made before the feature fields: they all read as zero
+        let legacy = patch_super_block(&disk, block_size, |super_block| {
+            super_block.version = 0;
+            super_block.feature_incompat = 0;
+        });
+        {
+            let efs = EasyFileSystem::open(as_device(&legacy));
+            assert!(efs.is_read_only());
+            let packed = EasyFileSystem::root_inode(&efs).find("packed").unwrap();
+            let mut buf = vec![0u8; 20000];
+            assert_eq!(packed.read_at(0, &mut buf), 20000);
+        }
+        assert!(EasyFileSystem::upgrade(as_device(&legacy)));
+        assert!(!EasyFileSystem::upgrade(as_device(&legacy)));
+        block_cache_sync_all();
+        let upgraded = Arc::new(RamDisk::from_bytes(legacy.to_bytes()));
+        let efs = EasyFileSystem::open(as_device(&upgraded));
+        assert!(!efs.is_read_only());
+        let super_block = efs.super_block();
+        assert_eq!(super_block.version, FORMAT_VERSION);
+        let mut expected = FEATURE_INCOMPAT_COMPRESSION | FEATURE_INCOMPAT_UNWRITTEN;
+        if block_size != 512 {
+            expected |= FEATURE_INCOMPAT_BLOCK_SIZE;
+        }
+        assert_eq!(super_block.feature_incompat, expected);
+        let root = EasyFileSystem::root_inode(&efs);
+        assert_eq!(root.ls(), ["plain", "packed", "reserved"]);
+        root.create("new").unwrap().write_at(0, b"written after the upgrade");
+    }
+}
+
+#[test]
+fn computed_geometry_fits_what_was_asked() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let geo = Geometry::new(block_size);
+        for (inodes, data_blocks) in [(1, 1), (100, 3000), (5000, 4097), (40000, 20000)] {
+            let inode_bitmap_blocks = geo.inode_bitmap_blocks(inodes);
+            let total_blocks = geo.total_blocks(inode_bitmap_blocks, data_blocks);
+            let disk = ram_disk(total_blocks as usize, block_size);
+            let efs = EasyFileSystem::create(as_device(&disk), total_blocks, inode_bitmap_blocks, block_size);
+            let super_block = efs.super_block();
+            assert_eq!(super_block.total_blocks, total_blocks);
+            assert!(super_block.inode_bitmap_blocks as usize * geo.block_bits >= inodes as usize);
+            assert!(super_block.data_area_blocks >= data_blocks);
+            // and not much more than that
+            assert!(super_block.data_area_blocks <= data_blocks + 1);
+        }
+    }
+}
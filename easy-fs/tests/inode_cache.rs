@@ -0,0 +1,125 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::EasyFileSystem;
+use std::sync::Arc;
+
+const BLOCKS: usize = 4096;
+const BLOCK_SIZE: usize = 512;
+
+fn fresh() -> Arc<EasyFileSystem> {
+    let disk = ram_disk(BLOCKS, BLOCK_SIZE);
+    EasyFileSystem::create(as_device(&disk), BLOCKS as u32, 1, BLOCK_SIZE)
+}
+
+#[test]
+fn repeated_opens_share_one_inode() {
+    let _guard = serial();
+    let efs = fresh();
+    let root = EasyFileSystem::root_inode(&efs);
+    assert!(Arc::ptr_eq(&root, &EasyFileSystem::root_inode(&efs)));
+    let created = root.create("file").unwrap();
+    let first = root.find("file").unwrap();
+    let second = root.find("file").unwrap();
+    assert!(Arc::ptr_eq(&created, &first));
+    assert!(Arc::ptr_eq(&first, &second));
+    // a size update through one opener is seen by the other
+    first.write_at(0, &pattern(3000, 1));
+    assert_eq!(second.size(), 3000);
+    second.truncate(100);
+    assert_eq!(first.size(), 100);
+}
+
+#[test]
+fn metadata_is_written_back_on_last_drop() {
+    let _guard = serial();
+    let efs = fresh();
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("file").unwrap();
+    let other = root.find("file").unwrap();
+    let inode_id = file.inode_id();
+    file.write_at(0, &pattern(5000, 2));
+    assert_eq!(efs.read_disk_inode(inode_id).size, 0);
+    drop(file);
+    assert_eq!(efs.read_disk_inode(inode_id).size, 0);
+    drop(other);
+    assert_eq!(efs.read_disk_inode(inode_id).size, 5000);
+    // reloaded from disk, the data is all there
+    let mut buf = vec![0u8; 5000];
+    assert_eq!(root.find("file").unwrap().read_at(0, &mut buf), 5000);
+    assert_eq!(buf, pattern(5000, 2));
+    // sync writes back without dropping
+    let file = root.find("file").unwrap();
+    file.truncate(10);
+    file.sync();
+    assert_eq!(efs.read_disk_inode(inode_id).size, 10);
+}
+
+#[test]
+fn directories_are_pinned_up_to_the_limit() {
+    let _guard = serial();
+    let efs = fresh();
+    efs.set_pinned_dirs(2);
+    let root = EasyFileSystem::root_inode(&efs);
+    for name in ["a", "b", "c"] {
+        root.create_dir(name).unwrap();
+    }
+    root.create("file").unwrap();
+    // root is held, "b" and "c" are the two last looked up directories
+    assert_eq!(efs.cached_inodes(), 3);
+    let c = root.find("c").unwrap();
+    root.find("a").unwrap();
+    root.find("b").unwrap();
+    // "c" fell out of the pinned ones, but still has a handle
+    assert_eq!(efs.cached_inodes(), 4);
+    drop(c);
+    assert_eq!(efs.cached_inodes(), 3);
+    efs.set_pinned_dirs(0);
+    assert_eq!(efs.cached_inodes(), 1);
+    root.find("a").unwrap();
+    assert_eq!(efs.cached_inodes(), 1);
+}
+
+#[test]
+fn unlinked_inode_is_not_written_back() {
+    let _guard = serial();
+    let efs = fresh();
+    let root = EasyFileSystem::root_inode(&efs);
+    let old = root.create("old").unwrap();
+    old.write_at(0, &pattern(2000, 3));
+    assert!(root.unlink("old"));
+    // the inode number is given again, to a new inode
+    let new = root.create("new").unwrap();
+    assert_eq!(new.inode_id(), old.inode_id());
+    assert!(!Arc::ptr_eq(&new, &old));
+    new.write_at(0, b"new");
+    drop(new);
+    drop(old);
+    assert_eq!(efs.read_disk_inode(root.find("new").unwrap().inode_id()).size, 3);
+    assert_eq!(root.ls(), ["new"]);
+}
+
+#[test]
+fn pinned_directories_do_not_keep_the_filesystem() {
+    let _guard = serial();
+    let efs = fresh();
+    let root = EasyFileSystem::root_inode(&efs);
+    let dir = root.create_dir("dir").unwrap();
+    dir.create("file").unwrap().write_at(0, b"data");
+    drop(dir);
+    drop(root);
+    // both directories stay pinned, without a handle
+    assert_eq!(efs.cached_inodes(), 2);
+    assert_eq!(Arc::strong_count(&efs), 1);
+    // and are found again as they were
+    let dir = EasyFileSystem::root_inode(&efs).find("dir").unwrap();
+    assert_eq!(efs.cached_inodes(), 2);
+    let mut buf = [0u8; 4];
+    assert_eq!(dir.find("file").unwrap().read_at(0, &mut buf), 4);
+    assert_eq!(&buf, b"data");
+    drop(dir);
+    assert_eq!(Arc::strong_count(&efs), 1);
+    let weak = Arc::downgrade(&efs);
+    drop(efs);
+    assert!(weak.upgrade().is_none());
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::{
+    block_cache_sync_all, get_block_cache, BlockDevice, DiskInode, DiskInodeType, Geometry,
+    BLOCK_SIZES,
+};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// A DiskInode stored at block 0 of a RamDisk, with a trivial block allocator
+struct Fixture {
+    geo: Geometry,
+    device: Arc<dyn BlockDevice>,
+    next_block: u32,
+    allocated: BTreeSet<u32>,
+}
+
+impl Fixture {
+    fn new(block_size: usize, blocks: usize) -> Self {
+        let geo = Geometry::new(block_size);
+        let device = as_device(&ram_disk(blocks, block_size));
+        let fixture = Self {
+            geo,
+            device,
+            next_block: 1,
+            allocated: BTreeSet::new(),
+        };
+        fixture.modify(|inode| inode.initialize(DiskInodeType::File));
+        fixture
+    }
+
+    fn modify<V>(&self, f: impl FnOnce(&mut DiskInode) -> V) -> V {
+        get_block_cache(0, self.geo.block_size, Arc::clone(&self.device))
+            .lock()
+            .modify(0, f)
+    }
+
+    fn read<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
+        get_block_cache(0, self.geo.block_size, Arc::clone(&self.device))
+            .lock()
+            .read(0, f)
+    }
+
+    fn grow(&mut self, new_size: u32) {
+        let geo = self.geo;
+        let needed = self.read(|inode| inode.blocks_num_needed(new_size, &geo));
+        let blocks: Vec<u32> = (0..needed).map(|i| self.next_block + i).collect();
+        self.next_block += needed;
+        self.allocated.extend(blocks.iter());
+        let device = Arc::clone(&self.device);
+        self.modify(|inode| inode.increase_size(new_size, blocks, &geo, &device));
+    }
+
+    fn shrink(&mut self, new_size: u32) {
+        let geo = self.geo;
+        let device = Arc::clone(&self.device);
+        let freed = self.modify(|inode| inode.decrease_size(new_size, &geo, &device));
+        for block_id in freed {
+            assert!(self.allocated.remove(&block_id), "block {} freed twice", block_id);
+        }
+    }
+
+    fn read_indirect(&self, block_id: u32) -> Vec<u32> {
+        get_block_cache(block_id as usize, self.geo.block_size, Arc::clone(&self.device))
+            .lock()
+            .read_slice(|block: &[u32]| block.to_vec())
+    }
+
+    /// (data blocks in inner id order, index blocks)
+    fn blocks(&self) -> (Vec<u32>, BTreeSet<u32>) {
+        let geo = self.geo;
+        let device = Arc::clone(&self.device);
+        let (data_blocks, indirect1, indirect2) = self.read(|inode| {
+            let data: Vec<u32> = (0..inode.data_blocks(&geo))
+                .map(|i| inode.get_block_id(i, &geo, &device))
+                .collect();
+            (data, inode.indirect1, inode.indirect2)
+        });
+        let mut index = BTreeSet::new();
+        if data_blocks.len() > 28 {
+            index.insert(indirect1);
+        }
+        if data_blocks.len() > geo.indirect1_bound {
+            index.insert(indirect2);
+            let used = (data_blocks.len() - geo.indirect1_bound).div_ceil(geo.indirect1_count);
+            index.extend(self.read_indirect(indirect2).into_iter().take(used));
+        }
+        (data_blocks, index)
+    }
+
+    fn check_mapping(&self) {
+        let (data, index) = self.blocks();
+        let data_set: BTreeSet<u32> = data.iter().copied().collect();
+        assert_eq!(data_set.len(), data.len(), "a data block is mapped twice");
+        assert!(data_set.is_disjoint(&index), "a data block is used as an index");
+        let all: BTreeSet<u32> = data_set.union(&index).copied().collect();
+        assert_eq!(all, self.allocated);
+        let geo = self.geo;
+        let size = self.read(|inode| inode.size);
+        assert_eq!(DiskInode::total_blocks(size, &geo) as usize, self.allocated.len());
+    }
+}
+
+/// Data block counts around the direct/indirect1/indirect2 boundaries
+fn boundaries(geo: &Geometry) -> Vec<usize> {
+    let b1 = geo.indirect1_bound;
+    vec![
+        0, 1, 27, 28, 29, b1 - 1, b1, b1 + 1,
+        b1 + geo.indirect1_count, b1 + geo.indirect1_count + 1,
+        b1 + 2 * geo.indirect1_count + 3,
+    ]
+}
+
+#[test]
+fn geometry_is_derived_from_block_size() {
+    let geo = Geometry::new(512);
+    assert_eq!(geo.indirect1_count, 128);
+    assert_eq!(geo.indirect1_bound, 28 + 128);
+    assert_eq!(geo.indirect2_bound, 28 + 128 + 128 * 128);
+    assert_eq!(geo.block_bits, 4096);
+    assert_eq!(geo.inodes_per_block, 4);
+    let geo = Geometry::new(4096);
+    assert_eq!(geo.indirect1_count, 1024);
+    assert_eq!(geo.block_bits, 32768);
+    assert_eq!(geo.inodes_per_block, 32);
+}
+
+#[test]
+#[should_panic]
+fn unsupported_block_size_panics() {
+    Geometry::new(3000);
+}
+
+#[test]
+fn total_blocks_counts_index_blocks() {
+    let geo = Geometry::new(512);
+    let bs = 512u32;
+    assert_eq!(DiskInode::total_blocks(0, &geo), 0);
+    assert_eq!(DiskInode::total_blocks(1, &geo), 1);
+    assert_eq!(DiskInode::total_blocks(28 * bs, &geo), 28);
+    assert_eq!(DiskInode::total_blocks(28 * bs + 1, &geo), 30);
+    assert_eq!(DiskInode::total_blocks(156 * bs, &geo), 157);
+    assert_eq!(DiskInode::total_blocks(156 * bs + 1, &geo), 160);
+    assert_eq!(DiskInode::total_blocks(284 * bs, &geo), 287);
+    assert_eq!(DiskInode::total_blocks(284 * bs + 1, &geo), 289);
+}
+
+#[test]
+fn grow_block_by_block_across_boundaries() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let geo = Geometry::new(block_size);
+        let last = *boundaries(&geo).last().unwrap();
+        let mut fixture = Fixture::new(block_size, last + 8);
+        for data_blocks in boundaries(&geo) {
+            fixture.grow((data_blocks * block_size) as u32);
+            fixture.check_mapping();
+        }
+    }
+    block_cache_sync_all();
+}
+
+#[test]
+fn grow_in_one_step_to_indirect2() {
+    let _guard = serial();
+    let block_size = 512;
+    let geo = Geometry::new(block_size);
+    let mut fixture = Fixture::new(block_size, 600);
+    fixture.grow(((geo.indirect1_bound + 200) * block_size - 7) as u32);
+    fixture.check_mapping();
+}
+
+#[test]
+fn grow_by_unaligned_sizes() {
+    let _guard = serial();
+    let block_size = 1024;
+    let mut fixture = Fixture::new(block_size, 400);
+    let mut size = 0u32;
+    while size < 300 * block_size as u32 {
+        size += 777;
+        fixture.grow(size);
+        fixture.check_mapping();
+    }
+}
+
+#[test]
+fn write_and_read_back_across_boundaries() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let geo = Geometry::new(block_size);
+        let size = (geo.indirect1_bound + geo.indirect1_count + 3) * block_size + 100;
+        let mut fixture = Fixture::new(block_size, size / block_size + 8);
+        fixture.grow(size as u32);
+        let data = pattern(size, block_size as u32);
+        let device = Arc::clone(&fixture.device);
+        assert_eq!(fixture.modify(|inode| inode.write_at(0, &data, &geo, &device)), size);
+        // read the whole file, then a range crossing into indirect2
+        let mut buf = vec![0u8; size + 10];
+        assert_eq!(fixture.read(|inode| inode.read_at(0, &mut buf, &geo, &device)), size);
+        assert_eq!(&buf[..size], &data[..]);
+        let offset = geo.indirect1_bound * block_size - 3;
+        let mut buf = vec![0u8; block_size * 2];
+        assert_eq!(fixture.read(|inode| inode.read_at(offset, &mut buf, &geo, &device)), buf.len());
+        assert_eq!(&buf[..], &data[offset..offset + buf.len()]);
+        // reading at or after the end returns nothing
+        assert_eq!(fixture.read(|inode| inode.read_at(size, &mut buf, &geo, &device)), 0);
+    }
+}
+
+#[test]
+fn clear_size_returns_every_block() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let geo = Geometry::new(block_size);
+        let last = *boundaries(&geo).last().unwrap();
+        let mut fixture = Fixture::new(block_size, last + 8);
+        fixture.grow((last * block_size - 1) as u32);
+        let device = Arc::clone(&fixture.device);
+        let freed: Vec<u32> = fixture.modify(|inode| inode.clear_size(&geo, &device));
+        let freed_set: BTreeSet<u32> = freed.iter().copied().collect();
+        assert_eq!(freed_set.len(), freed.len());
+        assert_eq!(freed_set, fixture.allocated);
+        fixture.read(|inode| {
+            assert_eq!(inode.size, 0);
+            assert_eq!(inode.indirect1, 0);
+            assert_eq!(inode.indirect2, 0);
+        });
+    }
+}
+
+#[test]
+fn shrink_block_by_block_across_boundaries() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let geo = Geometry::new(block_size);
+        let last = *boundaries(&geo).last().unwrap();
+        let mut fixture = Fixture::new(block_size, 2 * last + 8);
+        fixture.grow((last * block_size) as u32);
+        for data_blocks in boundaries(&geo).into_iter().rev() {
+            fixture.shrink((data_blocks * block_size) as u32);
+            fixture.check_mapping();
+        }
+        assert!(fixture.allocated.is_empty());
+        // the inode can grow again from nothing
+        fixture.grow((last * block_size) as u32);
+        fixture.check_mapping();
+    }
+}
+
+#[test]
+fn shrink_zeroes_the_tail_of_the_last_block() {
+    let _guard = serial();
+    let block_size = 512;
+    let geo = Geometry::new(block_size);
+    let mut fixture = Fixture::new(block_size, 64);
+    let size = 40 * block_size;
+    fixture.grow(size as u32);
+    let data = pattern(size, 9);
+    let device = Arc::clone(&fixture.device);
+    fixture.modify(|inode| inode.write_at(0, &data, &geo, &device));
+    fixture.shrink(1000);
+    fixture.check_mapping();
+    fixture.grow(3000);
+    fixture.check_mapping();
+    let mut buf = vec![0u8; 3000];
+    assert_eq!(fixture.read(|inode| inode.read_at(0, &mut buf, &geo, &device)), 3000);
+    assert_eq!(&buf[..1000], &data[..1000]);
+    assert!(buf[1000..].iter().all(|&b| b == 0));
+}
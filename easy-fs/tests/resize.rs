@@ -0,0 +1,185 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::{
+    block_cache_sync_all, Bitmap, BlockDevice, EasyFileSystem, Inode, RamDisk, FEATURE_INCOMPAT_RESIZE,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A RamDisk that loses every write past a budget, as if the power went off
+struct CrashDisk {
+    disk: RamDisk,
+    budget: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl CrashDisk {
+    fn new(bytes: Vec<u8>, budget: usize) -> Arc<Self> {
+        Arc::new(Self {
+            disk: RamDisk::from_bytes(bytes),
+            budget: AtomicUsize::new(budget),
+            writes: AtomicUsize::new(0),
+        })
+    }
+}
+
+impl BlockDevice for CrashDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.disk.read_block(block_id, buf);
+    }
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        if self.writes.fetch_add(1, Ordering::SeqCst) < self.budget.load(Ordering::SeqCst) {
+            self.disk.write_block(block_id, buf);
+        }
+    }
+}
+
+/// the files of an image, by path
+fn files() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("small", pattern(100, 1)),
+        ("dir/indirect1", pattern(40 * 1024, 2)),
+        ("dir/indirect2", pattern(100 * 1024, 3)),
+        ("dir/sub/empty", Vec::new()),
+        ("last", pattern(3000, 4)),
+    ]
+}
+
+fn lookup(root: &Inode, path: &str) -> Arc<Inode> {
+    let mut names = path.split('/');
+    let mut inode = root.find(names.next().unwrap()).unwrap();
+    for name in names {
+        inode = inode.find(name).unwrap();
+    }
+    inode
+}
+
+/// Format `total_blocks` of a larger disk and fill it with `files`
+fn image(disk_blocks: usize, total_blocks: u32, block_size: usize) -> Arc<RamDisk> {
+    let disk = ram_disk(disk_blocks, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), total_blocks, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let dir = root.create_dir("dir").unwrap();
+    dir.create_dir("sub").unwrap().create("empty").unwrap();
+    for (path, data) in files() {
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => (lookup(&root, parent), name),
+            None => (EasyFileSystem::root_inode(&efs), path),
+        };
+        let file = parent.find(name).unwrap_or_else(|| parent.create(name).unwrap());
+        file.write_at(0, &data);
+    }
+    root.create("reserved").unwrap().fallocate(5000);
+    block_cache_sync_all();
+    disk
+}
+
+/// check every file, and that free data blocks are zeros
+fn check(efs: &Arc<EasyFileSystem>) {
+    let root = EasyFileSystem::root_inode(efs);
+    for (path, data) in files() {
+        let file = lookup(&root, path);
+        let mut buf = vec![0u8; data.len() + 1];
+        assert_eq!(file.read_at(0, &mut buf), data.len(), "{}", path);
+        assert_eq!(&buf[..data.len()], &data[..], "{}", path);
+    }
+    let mut buf = vec![1u8; 5000];
+    assert_eq!(root.find("reserved").unwrap().read_at(0, &mut buf), 5000);
+    assert!(buf.iter().all(|byte| *byte == 0));
+    let super_block = efs.super_block();
+    assert_eq!(super_block.feature_incompat & FEATURE_INCOMPAT_RESIZE, 0);
+    assert_eq!(super_block.resize_state, 0);
+    let data_bitmap_start = 1 + super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
+    let data_area_start = data_bitmap_start + super_block.data_bitmap_blocks;
+    let bitmap = Bitmap::new(data_bitmap_start as usize, super_block.data_bitmap_blocks as usize, efs.geometry.block_size);
+    let mut block = vec![0u8; efs.geometry.block_size];
+    for bit in 0..super_block.data_area_blocks {
+        if !bitmap.is_allocated(&efs.block_device, bit as usize) {
+            efs.block_device.read_block((data_area_start + bit) as usize, &mut block);
+            assert!(block.iter().all(|byte| *byte == 0), "free block {} is not zero", data_area_start + bit);
+        }
+    }
+}
+
+#[test]
+fn grow_keeps_files_and_adds_room() {
+    let _guard = serial();
+    for (block_size, total_blocks) in [(512, 2048), (4096, 2048)] {
+        let disk = image(total_blocks * 4, total_blocks as u32, block_size);
+        // more data blocks, and an inode bitmap twice as long
+        assert!(EasyFileSystem::grow(as_device(&disk), total_blocks as u32 * 4, 2));
+        assert!(!EasyFileSystem::grow(as_device(&disk), total_blocks as u32 * 4, 2));
+        block_cache_sync_all();
+        let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+        let efs = EasyFileSystem::open(as_device(&copy));
+        let super_block = efs.super_block();
+        assert_eq!(super_block.total_blocks, total_blocks as u32 * 4);
+        assert_eq!(super_block.inode_bitmap_blocks, 2);
+        check(&efs);
+        // the new room can be used
+        let big = pattern(total_blocks * block_size, 9);
+        let root = EasyFileSystem::root_inode(&efs);
+        root.create("big").unwrap().write_at(0, &big);
+        let mut buf = vec![0u8; big.len()];
+        root.find("big").unwrap().read_at(0, &mut buf);
+        assert_eq!(buf, big);
+    }
+}
+
+#[test]
+fn grow_data_area_only() {
+    let _guard = serial();
+    let disk = image(4096, 2048, 512);
+    assert!(EasyFileSystem::grow(as_device(&disk), 3000, 1));
+    block_cache_sync_all();
+    let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+    let efs = EasyFileSystem::open(as_device(&copy));
+    assert_eq!(efs.super_block().total_blocks, 3000);
+    check(&efs);
+}
+
+#[test]
+#[should_panic(expected = "only grow")]
+fn shrinking_is_refused() {
+    let _guard = serial();
+    let disk = image(2048, 2048, 512);
+    EasyFileSystem::grow(as_device(&disk), 1024, 1);
+}
+
+#[test]
+fn grow_survives_a_crash_anywhere() {
+    let _guard = serial();
+    let (block_size, old_blocks, new_blocks) = (512, 2048u32, 6144u32);
+    let before = image(new_blocks as usize, old_blocks, block_size).to_bytes();
+    // count the writes of a whole grow
+    let counter = CrashDisk::new(before.clone(), usize::MAX);
+    EasyFileSystem::grow(Arc::clone(&counter) as Arc<dyn BlockDevice>, new_blocks, 2);
+    block_cache_sync_all();
+    let writes = counter.writes.load(Ordering::SeqCst);
+    let step = (writes / 97).max(1);
+    let mut grown = 0;
+    for budget in (0..=writes).step_by(step).chain(writes - 20..writes) {
+        let crashed = CrashDisk::new(before.clone(), budget);
+        EasyFileSystem::grow(Arc::clone(&crashed) as Arc<dyn BlockDevice>, new_blocks, 2);
+        block_cache_sync_all();
+        // whatever made it to the disk mounts, as the old or the new layout
+        let after = Arc::new(RamDisk::from_bytes(crashed.disk.to_bytes()));
+        let efs = EasyFileSystem::open(as_device(&after));
+        let total_blocks = efs.super_block().total_blocks;
+        assert!(total_blocks == old_blocks || total_blocks == new_blocks);
+        if total_blocks == new_blocks {
+            grown += 1;
+        }
+        check(&efs);
+        drop(efs);
+        // and a grow after the crash goes through
+        EasyFileSystem::grow(as_device(&after), new_blocks, 2);
+        block_cache_sync_all();
+        let efs = EasyFileSystem::open(as_device(&after));
+        assert_eq!(efs.super_block().total_blocks, new_blocks);
+        check(&efs);
+    }
+    assert!(grown > 0);
+}
+
@@ -0,0 +1,363 @@
+mod common;
+
+use common::{as_device, pattern, ram_disk, serial};
+use easy_fs::{block_cache_sync_all, EasyFileSystem, RamDisk, BLOCK_SIZES, NAME_LENGTH_LIMIT};
+use std::sync::Arc;
+
+const IMAGE_SIZE: usize = 16 * 1024 * 1024;
+
+#[test]
+fn create_find_and_ls() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        assert!(root.ls().is_empty());
+        assert!(root.find("filea").is_none());
+        root.create("filea").unwrap();
+        root.create("fileb").unwrap();
+        assert_eq!(root.ls(), ["filea", "fileb"]);
+        assert!(root.find("filea").is_some());
+        assert!(root.find("filec").is_none());
+        // a name can only be created once
+        assert!(root.create("filea").is_none());
+        assert_eq!(root.ls().len(), 2);
+    }
+}
+
+#[test]
+fn names_must_leave_room_for_the_nul() {
+    let _guard = serial();
+    let block_size = 512;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let longest = "a".repeat(NAME_LENGTH_LIMIT);
+    // 28 bytes would fill the entry with no NUL, 29 would not fit at all
+    for len in [NAME_LENGTH_LIMIT + 1, NAME_LENGTH_LIMIT + 2] {
+        let name = "b".repeat(len);
+        assert!(root.create(&name).is_none(), "{} bytes", len);
+        assert!(root.create_dir(&name).is_none(), "{} bytes", len);
+    }
+    assert!(root.ls().is_empty());
+    root.create(&longest).unwrap();
+    assert_eq!(root.ls(), [longest.as_str()]);
+    assert!(root.find(&longest).is_some());
+}
+
+#[test]
+fn many_entries_span_several_blocks() {
+    let _guard = serial();
+    let block_size = 512;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    // 16 entries per block, 28 direct blocks: spill into indirect1
+    let names: Vec<String> = (0..500).map(|i| format!("file{}", i)).collect();
+    for name in names.iter() {
+        root.create(name).unwrap();
+    }
+    assert_eq!(root.ls(), names);
+    for name in names.iter() {
+        assert!(root.find(name).is_some());
+    }
+}
+
+#[test]
+fn write_read_and_clear() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        let file = root.create("file").unwrap();
+        let data = pattern(300 * 1024 + 17, 1);
+        assert_eq!(file.write_at(0, &data), data.len());
+        let mut buf = vec![0u8; data.len()];
+        assert_eq!(file.read_at(0, &mut buf), data.len());
+        assert_eq!(buf, data);
+        // overwrite in the middle and append at the end
+        file.write_at(1000, b"hello");
+        file.write_at(data.len(), b"world");
+        let mut buf = [0u8; 5];
+        file.read_at(1000, &mut buf);
+        assert_eq!(&buf, b"hello");
+        file.read_at(data.len(), &mut buf);
+        assert_eq!(&buf, b"world");
+        // clearing frees the data for another file
+        file.clear();
+        assert_eq!(file.read_at(0, &mut buf), 0);
+        let other = root.create("other").unwrap();
+        assert_eq!(other.write_at(0, &data), data.len());
+    }
+}
+
+#[test]
+fn writes_stop_at_the_largest_file() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        let file = root.create("file").unwrap();
+        // past what a u32 size can tell, on every block size
+        assert_eq!(file.write_at(1 << 32, b"x"), 0);
+        assert_eq!(file.write_at(u32::MAX as usize + 1, b"x"), 0);
+        assert_eq!(file.size(), 0);
+    }
+    // 512-byte blocks run out of index first: a write over the end is cut short
+    let block_size = 512;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let file = root.create("file").unwrap();
+    let max_size = efs.geometry.max_file_size();
+    assert!(max_size < IMAGE_SIZE);
+    assert_eq!(file.write_at(max_size + 1, b"x"), 0);
+    assert_eq!(file.size(), 0);
+    assert_eq!(file.write_at(max_size - 3, b"01234567"), 3);
+    assert_eq!(file.size(), max_size);
+    let mut buf = [0u8; 8];
+    assert_eq!(file.read_at(max_size - 3, &mut buf), 3);
+    assert_eq!(&buf[..3], b"012");
+}
+
+#[test]
+fn a_full_disk_cuts_writes_short() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        // one inode bitmap block takes 1024 inode blocks, leave 63 data blocks
+        let blocks = 1090;
+        let disk = ram_disk(blocks, block_size);
+        let efs = EasyFileSystem::create(as_device(&disk), blocks as u32, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        let file = root.create("file").unwrap();
+        let data = pattern(blocks * block_size, 2);
+        let written = file.write_at(0, &data);
+        assert!(written > 0 && written < data.len());
+        assert_eq!(written % block_size, 0);
+        assert_eq!(file.size(), written);
+        let mut buf = vec![0u8; written];
+        assert_eq!(file.read_at(0, &mut buf), written);
+        assert_eq!(buf, data[..written]);
+        assert_eq!(file.write_at(written, b"more"), 0);
+        assert_eq!(file.write_at(written + 10 * block_size, b"more"), 0);
+        assert_eq!(file.size(), written);
+        // entries fill the block of the root, then there is no room for more
+        let entries_per_block = block_size / 32;
+        for i in 1..entries_per_block {
+            root.create(&format!("other{}", i)).unwrap();
+        }
+        assert!(root.create("one_too_many").is_none());
+        assert_eq!(root.ls().len(), entries_per_block);
+        let other = root.find("other1").unwrap();
+        assert_eq!(other.write_at(0, b"x"), 0);
+        // freeing the data makes room again
+        file.clear();
+        assert_eq!(other.write_at(0, &data[..block_size]), block_size);
+        assert!(root.create("one_too_many").is_some());
+    }
+}
+
+#[test]
+fn remount_keeps_files() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let files: Vec<(String, Vec<u8>)> = (0..8)
+            .map(|i| (format!("f{}", i), pattern(i * 9000 + 3, i as u32)))
+            .collect();
+        {
+            let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+            let root = EasyFileSystem::root_inode(&efs);
+            for (name, data) in files.iter() {
+                root.create(name).unwrap().write_at(0, data);
+            }
+        }
+        block_cache_sync_all();
+        // open a copy of the image, nothing can come from the cache of `disk`
+        let copy = Arc::new(RamDisk::from_bytes(disk.to_bytes()));
+        let efs = EasyFileSystem::open(as_device(&copy));
+        assert_eq!(efs.geometry.block_size, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        assert_eq!(root.ls().len(), files.len());
+        for (name, data) in files.iter() {
+            let file = root.find(name).unwrap();
+            let mut buf = vec![0u8; data.len() + 1];
+            assert_eq!(file.read_at(0, &mut buf), data.len());
+            assert_eq!(&buf[..data.len()], &data[..]);
+        }
+        // the remounted filesystem allocates after the existing files
+        let file = root.create("new").unwrap();
+        file.write_at(0, b"fresh");
+        for (name, data) in files.iter() {
+            let mut buf = vec![0u8; data.len()];
+            root.find(name).unwrap().read_at(0, &mut buf);
+            assert_eq!(&buf, data);
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+fn open_unformatted_disk_panics() {
+    let _guard = serial();
+    let disk = ram_disk(16, 512);
+    EasyFileSystem::open(as_device(&disk));
+}
+
+#[test]
+fn truncate_shrinks_and_grows() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        let file = root.create("file").unwrap();
+        let data = pattern(200 * 1024 + 3, 5);
+        file.write_at(0, &data);
+        file.truncate(5000);
+        assert_eq!(file.size(), 5000);
+        file.truncate(9000);
+        assert_eq!(file.size(), 9000);
+        let mut buf = vec![0u8; 9000];
+        assert_eq!(file.read_at(0, &mut buf), 9000);
+        assert_eq!(&buf[..5000], &data[..5000]);
+        assert!(buf[5000..].iter().all(|&b| b == 0));
+        file.truncate(0);
+        assert_eq!(file.read_at(0, &mut buf), 0);
+    }
+}
+
+#[test]
+fn truncate_frees_blocks_for_log_rotation() {
+    let _guard = serial();
+    // a small image that can hold the log only a few times
+    let block_size = 512;
+    let blocks = 4096;
+    let disk = ram_disk(blocks, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), blocks as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let log = root.create("log").unwrap();
+    let line = pattern(300, 7);
+    for round in 0..50 {
+        for i in 0..600 {
+            log.write_at(log.size(), &line[..(i + round) % 300 + 1]);
+        }
+        // keep the first bytes, drop the rest
+        log.truncate(100);
+        assert_eq!(log.size(), 100);
+    }
+    let mut buf = vec![0u8; 100];
+    log.read_at(0, &mut buf);
+    assert_eq!(&buf[..1], &line[..1]);
+}
+
+/// Fill the data blocks of an image with garbage, straight on the device,
+/// except the first `used` ones
+fn scribble_data_area(disk: &Arc<RamDisk>, block_size: usize, used: usize) {
+    let super_block = disk.to_bytes();
+    let word = |i: usize| u32::from_le_bytes(super_block[4 * i..4 * i + 4].try_into().unwrap()) as usize;
+    // total_blocks, inode_bitmap_blocks, inode_area_blocks, data_bitmap_blocks
+    let data_start = 1 + word(2) + word(3) + word(4);
+    let garbage = vec![0xa5u8; block_size];
+    let device = as_device(disk);
+    for block_id in data_start + used..data_start + 1024 {
+        device.write_block(block_id, &garbage);
+    }
+}
+
+#[test]
+fn fallocate_reads_zeros_until_written() {
+    let _guard = serial();
+    for block_size in BLOCK_SIZES {
+        let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+        let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+        let root = EasyFileSystem::root_inode(&efs);
+        let file = root.create("file").unwrap();
+        file.write_at(0, b"header");
+        // the reserved blocks are never zeroed; keep the blocks of the
+        // root directory and of the header
+        block_cache_sync_all();
+        scribble_data_area(&disk, block_size, 2);
+        let size = 300 * 1024 + 11;
+        file.fallocate(size);
+        assert_eq!(file.size(), size);
+        let mut buf = vec![0u8; size];
+        assert_eq!(file.read_at(0, &mut buf), size);
+        assert_eq!(&buf[..6], b"header");
+        assert!(buf[6..].iter().all(|&b| b == 0));
+        // a write in the middle of an unwritten block keeps zeros around it
+        let offset = 200 * 1024 + 100;
+        file.write_at(offset, b"middle");
+        assert_eq!(file.read_at(0, &mut buf), size);
+        assert_eq!(&buf[offset..offset + 6], b"middle");
+        assert!(buf[6..offset].iter().all(|&b| b == 0));
+        assert!(buf[offset + 6..].iter().all(|&b| b == 0));
+        // preallocating less than the size changes nothing
+        file.fallocate(10);
+        assert_eq!(file.size(), size);
+        // freeing preallocated blocks works like freeing written ones
+        file.truncate(50 * 1024);
+        file.clear();
+        let other = root.create("other").unwrap();
+        other.fallocate(size);
+        assert_eq!(other.read_at(size - 6, &mut buf[..6]), 6);
+        assert!(buf[..6].iter().all(|&b| b == 0));
+    }
+}
+
+#[test]
+fn directories_nest() {
+    let _guard = serial();
+    let block_size = 1024;
+    let disk = ram_disk(IMAGE_SIZE / block_size, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), (IMAGE_SIZE / block_size) as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let dir = root.create_dir("dir").unwrap();
+    assert!(dir.is_dir());
+    assert!(root.create_dir("dir").is_none());
+    let sub = dir.create_dir("sub").unwrap();
+    sub.create("file").unwrap().write_at(0, b"deep");
+    assert_eq!(root.ls(), ["dir"]);
+    assert_eq!(dir.ls(), ["sub"]);
+    let file = root.find("dir").unwrap().find("sub").unwrap().find("file").unwrap();
+    assert!(file.is_file());
+    let mut buf = [0u8; 4];
+    file.read_at(0, &mut buf);
+    assert_eq!(&buf, b"deep");
+}
+
+#[test]
+fn unlink_removes_entries_and_frees_them() {
+    let _guard = serial();
+    let block_size = 512;
+    // small enough that the files do not fit twice
+    let blocks = 4096;
+    let disk = ram_disk(blocks, block_size);
+    let efs = EasyFileSystem::create(as_device(&disk), blocks as u32, 1, block_size);
+    let root = EasyFileSystem::root_inode(&efs);
+    let data = pattern(400 * 1024, 3);
+    for round in 0..4 {
+        let names: Vec<String> = (0..40).map(|i| format!("f{}_{}", round, i)).collect();
+        for name in names.iter() {
+            root.create(name).unwrap();
+        }
+        root.find(&names[7]).unwrap().write_at(0, &data);
+        // remove in an order that moves entries around
+        for name in names.iter().step_by(2).chain(names.iter().skip(1).step_by(2)) {
+            assert!(root.unlink(name));
+            assert!(root.find(name).is_none());
+        }
+        assert!(root.ls().is_empty());
+    }
+    assert!(!root.unlink("missing"));
+    // a directory goes away only once empty
+    let dir = root.create_dir("dir").unwrap();
+    dir.create("file").unwrap();
+    assert!(!root.unlink("dir"));
+    assert!(dir.unlink("file"));
+    assert!(root.unlink("dir"));
+    assert!(root.ls().is_empty());
+}
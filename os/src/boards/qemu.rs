@@ -2,6 +2,9 @@
 
 pub const CLOCK_FREQ: usize = 12500000;
 
+/// (base, size) of the MMIO regions the kernel maps: the first virtio device
+pub const MMIO: &[(usize, usize)] = &[(0x1000_1000, 0x1000)];
+
 //ref:: https://github.com/andre-richter/qemu-exit
 use core::arch::asm;
 
@@ -13,6 +13,12 @@ pub const PTE_NUM_PER_FRAME: usize = PAGE_SIZE / PTE_SIZE;
 
 pub const MEMORY_END: usize = 0x81000000;
 
+/// fds a task can have, no fd is given at or past it
+pub const MAX_FD: usize = 256;
+
+/// a program and its stack must end below this, the lower half of Sv39
+pub const USER_SPACE_END: usize = 1 << 38;
+
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 
@@ -27,4 +33,4 @@ pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
 #[cfg(feature = "board_qemu")]
 pub const CLOCK_FREQ: usize = 12500000;
 */
-pub use crate::board::CLOCK_FREQ;
+pub use crate::board::{CLOCK_FREQ, MMIO};
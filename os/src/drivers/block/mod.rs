@@ -0,0 +1,15 @@
+//! Block devices holding the root filesystem
+mod virtio_blk;
+
+pub use virtio_blk::VirtIOBlock;
+
+use alloc::sync::Arc;
+use easy_fs::BlockDevice;
+use lazy_static::*;
+
+type BlockDeviceImpl = virtio_blk::VirtIOBlock;
+
+lazy_static! {
+    /// the disk of the root filesystem
+    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
+}
@@ -0,0 +1,93 @@
+//! virtio-blk over MMIO, the disk of QEMU's virt machine
+use crate::config::MMIO;
+use crate::mm::{
+    frame_alloc, kernel_token, FrameTracker, PageTable, PhysAddr, PhysPageNum, VirtAddr,
+};
+use crate::sync::UPSafeCell;
+use alloc::vec::Vec;
+use easy_fs::BlockDevice;
+use lazy_static::*;
+use virtio_drivers::{VirtIOBlk, VirtIOHeader};
+
+/// sector size of a virtio-blk device
+const SECTOR_SIZE: usize = 512;
+
+/// The first virtio device, which has to be a block device
+pub struct VirtIOBlock(UPSafeCell<VirtIOBlk<'static>>);
+
+lazy_static! {
+    /// frames handed to the driver for its virtqueues
+    static ref QUEUE_FRAMES: UPSafeCell<Vec<FrameTracker>> = unsafe { UPSafeCell::new(Vec::new()) };
+}
+
+impl VirtIOBlock {
+    pub fn new() -> Self {
+        let header = MMIO[0].0 as *mut VirtIOHeader;
+        unsafe {
+            Self(UPSafeCell::new(
+                VirtIOBlk::new(&mut *header).expect("No virtio-blk device!"),
+            ))
+        }
+    }
+}
+
+// the device works in sectors, a block of `buf.len()` bytes is the sectors it spans
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let sectors = buf.len() / SECTOR_SIZE;
+        let mut blk = self.0.exclusive_access();
+        for (i, sector) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
+            blk.read_block(block_id * sectors + i, sector)
+                .expect("Error when reading VirtIOBlk");
+        }
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let sectors = buf.len() / SECTOR_SIZE;
+        let mut blk = self.0.exclusive_access();
+        for (i, sector) in buf.chunks(SECTOR_SIZE).enumerate() {
+            blk.write_block(block_id * sectors + i, sector)
+                .expect("Error when writing VirtIOBlk");
+        }
+    }
+}
+
+/// Physically contiguous pages for the driver, taken from the frame allocator
+#[no_mangle]
+pub extern "C" fn virtio_dma_alloc(pages: usize) -> usize {
+    let mut queue_frames = QUEUE_FRAMES.exclusive_access();
+    let mut ppn_base = PhysPageNum(0);
+    for i in 0..pages {
+        let frame = frame_alloc().unwrap();
+        if i == 0 {
+            ppn_base = frame.ppn;
+        }
+        assert_eq!(frame.ppn.0, ppn_base.0 + i, "DMA pages are not contiguous!");
+        queue_frames.push(frame);
+    }
+    PhysAddr::from(ppn_base).0
+}
+
+/// Give pages of `virtio_dma_alloc` back, their trackers free them
+#[no_mangle]
+pub extern "C" fn virtio_dma_dealloc(pa: usize, pages: usize) -> i32 {
+    let ppn_base = PhysAddr::from(pa).floor().0;
+    QUEUE_FRAMES
+        .exclusive_access()
+        .retain(|frame| !(ppn_base..ppn_base + pages).contains(&frame.ppn.0));
+    0
+}
+
+/// Physical memory is mapped identically in the kernel space
+#[no_mangle]
+pub extern "C" fn virtio_phys_to_virt(paddr: usize) -> usize {
+    paddr
+}
+
+#[no_mangle]
+pub extern "C" fn virtio_virt_to_phys(vaddr: usize) -> usize {
+    PageTable::from_token(kernel_token())
+        .translate_va(VirtAddr::from(vaddr))
+        .unwrap()
+        .0
+}
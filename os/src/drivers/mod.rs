@@ -0,0 +1,4 @@
+//! Device drivers
+pub mod block;
+
+pub use block::BLOCK_DEVICE;
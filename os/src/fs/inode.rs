@@ -0,0 +1,410 @@
+//! Regular files of the root filesystem, opened as [`OSInode`]
+use super::procfs::ProcInode;
+use super::{File, Stat, StatMode, SEEK_CUR, SEEK_END, SEEK_SET};
+use crate::drivers::BLOCK_DEVICE;
+use crate::mm::UserBuffer;
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::*;
+use easy_fs::{mount_root, DirEntry, FsInode, NAME_LENGTH_LIMIT};
+use lazy_static::*;
+
+/// A file opened by a task, with its own offset
+/**
+    Tasks sharing an fd after fork share the offset too.
+*/
+pub struct OSInode {
+    readable: bool,
+    writable: bool,
+    /// a file or a directory, which it stays
+    mode: StatMode,
+    inner: UPSafeCell<OSInodeInner>,
+}
+
+pub struct OSInodeInner {
+    offset: usize,
+    inode: Arc<dyn FsInode>,
+}
+
+impl OSInode {
+    pub fn new(readable: bool, writable: bool, inode: Arc<dyn FsInode>) -> Self {
+        let mode = if inode.is_dir() { StatMode::DIR } else { StatMode::FILE };
+        Self {
+            readable,
+            writable,
+            mode,
+            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
+        }
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.inner.exclusive_access().inode.is_file()
+    }
+
+    /// read everything from the offset on
+    pub fn read_all(&self) -> Vec<u8> {
+        let mut inner = self.inner.exclusive_access();
+        let mut buffer = [0u8; 512];
+        let mut v: Vec<u8> = Vec::new();
+        loop {
+            let len = inner.inode.read_at(inner.offset, &mut buffer);
+            if len == 0 {
+                break;
+            }
+            inner.offset += len;
+            v.extend_from_slice(&buffer[..len]);
+        }
+        v
+    }
+}
+
+lazy_static! {
+    /// root of the filesystem every path starts from
+    /// easy-fs, FAT32 or ext2, whichever the disk holds, with procfs on `/proc`
+    pub static ref ROOT_INODE: Arc<dyn FsInode> = Arc::new(RootDir {
+        disk: mount_root(BLOCK_DEVICE.clone()).expect("No known filesystem on the disk!"),
+        proc: ProcInode::root(),
+    });
+}
+
+/// name of the directory procfs is mounted on
+const PROC_NAME: &str = "proc";
+
+/// The root of the disk, with procfs mounted on `PROC_NAME`
+/**
+    Only the root has a mount point, so nothing else needs to know of
+    mounts: walking a path from here reaches either filesystem. The name
+    hides whatever the disk has under it.
+*/
+struct RootDir {
+    disk: Arc<dyn FsInode>,
+    proc: Arc<dyn FsInode>,
+}
+
+impl FsInode for RootDir {
+    fn inode_id(&self) -> u32 {
+        self.disk.inode_id()
+    }
+
+    fn is_dir(&self) -> bool {
+        true
+    }
+
+    fn is_file(&self) -> bool {
+        false
+    }
+
+    fn size(&self) -> usize {
+        self.disk.size()
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.disk.read_at(offset, buf)
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        if name == PROC_NAME {
+            Some(self.proc.clone())
+        } else {
+            self.disk.find(name)
+        }
+    }
+
+    fn ls(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .disk
+            .ls()
+            .into_iter()
+            .filter(|name| name != PROC_NAME)
+            .collect();
+        names.push(String::from(PROC_NAME));
+        names
+    }
+
+    fn nlink(&self) -> u32 {
+        self.disk.nlink()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        self.disk.write_at(offset, buf)
+    }
+
+    fn clear(&self) {
+        self.disk.clear()
+    }
+
+    fn create(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        if name == PROC_NAME {
+            None
+        } else {
+            self.disk.create(name)
+        }
+    }
+
+    fn create_dir(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        if name == PROC_NAME {
+            None
+        } else {
+            self.disk.create_dir(name)
+        }
+    }
+
+    fn unlink(&self, name: &str) -> bool {
+        name != PROC_NAME && self.disk.unlink(name)
+    }
+}
+
+bitflags! {
+    /// flags of `sys_open`, the same as in the user library
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 9;
+        const TRUNC = 1 << 10;
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+impl OpenFlags {
+    /// (readable, writable) of a file opened with these flags
+    pub fn read_write(&self) -> (bool, bool) {
+        if self.contains(Self::WRONLY) {
+            (false, true)
+        } else if self.contains(Self::RDWR) {
+            (true, true)
+        } else {
+            (true, false)
+        }
+    }
+}
+
+/// Find the inode at `path` from the directory `start`
+fn find_path(start: Arc<dyn FsInode>, path: &str) -> Option<Arc<dyn FsInode>> {
+    path.split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+        .try_fold(start, |dir, name| {
+            if dir.is_dir() {
+                dir.find(name)
+            } else {
+                None
+            }
+        })
+}
+
+/// The current working directory of a task
+/**
+    Relative paths are walked from `inode`. A path going up with `..` is
+    made absolute with `path` and walked from the root instead, as the
+    directories of easy-fs have no `..` entry.
+*/
+#[derive(Clone)]
+pub struct Cwd {
+    pub inode: Arc<dyn FsInode>,
+    /// absolute, with no `.` or `..` in it
+    pub path: String,
+}
+
+impl Cwd {
+    pub fn root() -> Self {
+        Self {
+            inode: ROOT_INODE.clone(),
+            path: String::from("/"),
+        }
+    }
+
+    /// The absolute form of `path`, `..` of the root being the root
+    pub fn absolute(&self, path: &str) -> String {
+        let mut names: Vec<&str> = Vec::new();
+        if !path.starts_with('/') {
+            names.extend(self.path.split('/').filter(|name| !name.is_empty()));
+        }
+        for name in path.split('/') {
+            match name {
+                "" | "." => {}
+                ".." => {
+                    names.pop();
+                }
+                name => names.push(name),
+            }
+        }
+        let mut absolute = String::from("/");
+        absolute.push_str(&names.join("/"));
+        absolute
+    }
+
+    /// Find the inode at `path`, relative to this directory unless it starts with '/'
+    pub fn find(&self, path: &str) -> Option<Arc<dyn FsInode>> {
+        if path.starts_with('/') || path.split('/').any(|name| name == "..") {
+            find_path(ROOT_INODE.clone(), &self.absolute(path))
+        } else {
+            find_path(self.inode.clone(), path)
+        }
+    }
+}
+
+/// Open the file at `path`, relative to `cwd`
+/**
+    With `CREATE` a missing file is made in its directory, and an existing
+    one is emptied like with `TRUNC`; names longer than `NAME_LENGTH_LIMIT`
+    are refused. A directory can only be opened read-only, and so can
+    anything on a read-only filesystem.
+*/
+pub fn open_file(cwd: &Cwd, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+    let (readable, writable) = flags.read_write();
+    let trimmed = path.trim_end_matches('/');
+    let path = if trimmed.is_empty() && path.starts_with('/') { "/" } else { trimmed };
+    let (parent, name) = match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => ("", path),
+    };
+    let dir = cwd.find(parent)?;
+    if !dir.is_dir() {
+        return None;
+    }
+    let found = match name {
+        "" | "." | ".." => cwd.find(path),
+        name => dir.find(name),
+    };
+    // nothing is written, emptied or made on a read-only filesystem
+    let changes = writable || flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC);
+    let inode = match found {
+        Some(inode) => {
+            if (inode.is_dir() && writable) || (changes && inode.is_read_only()) {
+                return None;
+            }
+            if flags.intersects(OpenFlags::CREATE | OpenFlags::TRUNC) && inode.is_file() {
+                inode.clear();
+            }
+            inode
+        }
+        // a name must fit in the record getdents gives back
+        None if flags.contains(OpenFlags::CREATE)
+            && name.len() <= NAME_LENGTH_LIMIT
+            && !dir.is_read_only() =>
+        {
+            dir.create(name)?
+        }
+        None => return None,
+    };
+    Some(Arc::new(OSInode::new(readable, writable, inode)))
+}
+
+impl File for OSInode {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let mut total_read_size = 0usize;
+        for slice in buf.buffers.iter_mut() {
+            let read_size = inner.inode.read_at(inner.offset, slice);
+            if read_size == 0 {
+                break;
+            }
+            inner.offset += read_size;
+            total_read_size += read_size;
+        }
+        total_read_size
+    }
+
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        let mut total_write_size = 0usize;
+        for slice in buf.buffers.iter() {
+            let write_size = inner.inode.write_at(inner.offset, slice);
+            inner.offset += write_size;
+            total_write_size += write_size;
+            if write_size < slice.len() {
+                break;
+            }
+        }
+        total_write_size
+    }
+
+    fn stat(&self) -> Stat {
+        let inner = self.inner.exclusive_access();
+        let inode = &inner.inode;
+        Stat {
+            dev: 0,
+            ino: inode.inode_id() as u64,
+            mode: self.mode,
+            nlink: inode.nlink(),
+            size: inode.size() as u64,
+        }
+    }
+
+    fn mode(&self) -> StatMode {
+        self.mode
+    }
+
+    /// a file may be seeked past its end, writing there fills the gap with zeros
+    /**
+        Any offset is taken: a write past the largest file the filesystem
+        holds writes nothing and returns 0.
+    */
+    fn seek(&self, offset: isize, whence: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => inner.offset as isize,
+            SEEK_END => inner.inode.size() as isize,
+            _ => return -1,
+        };
+        match base.checked_add(offset) {
+            Some(new_offset) if new_offset >= 0 => {
+                inner.offset = new_offset as usize;
+                new_offset
+            }
+            _ => -1,
+        }
+    }
+
+    /// The offset of a directory counts its entries
+    /**
+        The records are easy-fs `DirEntry`s, so entries of other filesystems
+        whose names do not fit in one are left out.
+    */
+    fn getdents(&self, count: usize) -> Option<Vec<DirEntry>> {
+        let mut inner = self.inner.exclusive_access();
+        if !inner.inode.is_dir() {
+            return None;
+        }
+        let names = inner.inode.ls();
+        let mut entries = Vec::new();
+        for name in names.iter().skip(inner.offset) {
+            if entries.len() == count {
+                break;
+            }
+            inner.offset += 1;
+            if name.len() > NAME_LENGTH_LIMIT {
+                continue;
+            }
+            if let Some(child) = inner.inode.find(name) {
+                entries.push(DirEntry::new(name, child.inode_id()));
+            }
+        }
+        Some(entries)
+    }
+}
+
+/// List the root directory, which is mounted on the way
+pub fn list_root() {
+    println!("/**** ROOT_DIR ****/");
+    for name in ROOT_INODE.ls() {
+        println!("{}", name);
+    }
+    println!("/******************/");
+}
@@ -0,0 +1,108 @@
+//! File trait and the files a task can hold in its fd table
+mod inode;
+mod pipe;
+mod procfs;
+mod stdio;
+
+use crate::mm::UserBuffer;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::*;
+use easy_fs::DirEntry;
+
+/// Something a task reads and writes through a file descriptor
+pub trait File: Send + Sync {
+    fn readable(&self) -> bool;
+    fn writable(&self) -> bool;
+    /// read into `buf`, return the number of bytes read
+    fn read(&self, buf: UserBuffer) -> usize;
+    /// write from `buf`, return the number of bytes written
+    fn write(&self, buf: UserBuffer) -> usize;
+    /// what fstat tells about the file
+    fn stat(&self) -> Stat;
+    /// the type of the file alone, known without locking it or reading its size
+    fn mode(&self) -> StatMode {
+        self.stat().mode
+    }
+    /// move the offset like lseek, return the new offset or -1 if it cannot move
+    fn seek(&self, _offset: isize, _whence: usize) -> isize {
+        -1
+    }
+    /// the next at most `count` entries of a directory, None if not a directory
+    fn getdents(&self, _count: usize) -> Option<Vec<DirEntry>> {
+        None
+    }
+}
+
+/// `whence` of lseek: from the start, the current offset, or the end
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// What fstat writes to the user, the same layout as in the user library
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    /// device the file is on, always 0
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// type of the file
+    pub mode: StatMode,
+    /// number of hard links
+    pub nlink: u32,
+    /// size in bytes
+    pub size: u64,
+}
+
+bitflags! {
+    /// types of file, as in the high bits of a unix mode
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+impl Stat {
+    /// a file that is not on any filesystem
+    pub fn without_inode(mode: StatMode) -> Self {
+        Self {
+            dev: 0,
+            ino: 0,
+            mode,
+            nlink: 1,
+            size: 0,
+        }
+    }
+
+    /// view the stat as raw bytes, to copy it out
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self as *const _ as *const u8,
+                core::mem::size_of::<Self>(),
+            )
+        }
+    }
+}
+
+/// An open file in the fd table of a task
+#[derive(Clone)]
+pub struct FdEntry {
+    pub file: Arc<dyn File>,
+    /// closed when the task execs another program
+    pub cloexec: bool,
+}
+
+impl FdEntry {
+    pub fn new(file: Arc<dyn File>, cloexec: bool) -> Self {
+        Self { file, cloexec }
+    }
+}
+
+pub use inode::{list_root, open_file, Cwd, OSInode, OpenFlags, ROOT_INODE};
+pub use pipe::{make_pipe, Pipe};
+pub use stdio::{Stdin, Stdout};
@@ -0,0 +1,249 @@
+//! A filesystem of files made on demand from the state of the kernel, mounted at `/proc`
+/*!
+  ```text
+  /proc/meminfo         frames of the frame allocator in use
+  /proc/uptime          seconds since boot
+  /proc/<pid>/status    status, parent, children, exit code and base_size
+  /proc/<pid>/maps      the areas of its memory set
+  /proc/<pid>/fds       its open fds
+  ```
+  A file is written out again on every read, so it always tells the
+  state at that moment. Nothing can be written or created.
+*/
+use super::{FdEntry, StatMode};
+use crate::mm::{frame_usage, MapPermission, MapType, VirtAddr};
+use crate::task::{all_tasks, TaskControlBlock};
+use crate::timer::get_time_ms;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use easy_fs::FsInode;
+
+/// Files of the root of procfs
+const ROOT_FILES: [&str; 2] = ["meminfo", "uptime"];
+/// Files of the directory of each task
+const TASK_FILES: [&str; 3] = ["status", "maps", "fds"];
+
+/// Inode numbers of procfs, far above what a disk holds
+const ROOT_ID: u32 = 0x8000_0000;
+
+/// What an inode of procfs stands for
+#[derive(Clone, Copy, PartialEq)]
+enum Node {
+    Root,
+    /// `ROOT_FILES[i]`
+    RootFile(usize),
+    Task(usize),
+    /// `TASK_FILES[i]` of a pid
+    TaskFile(usize, usize),
+}
+
+pub struct ProcInode {
+    node: Node,
+}
+
+impl ProcInode {
+    /// The root of procfs
+    pub fn root() -> Arc<dyn FsInode> {
+        Arc::new(Self { node: Node::Root })
+    }
+
+    fn new(node: Node) -> Arc<dyn FsInode> {
+        Arc::new(Self { node })
+    }
+
+    /// The data of a file, None for a directory or a task gone
+    fn contents(&self) -> Option<String> {
+        match self.node {
+            Node::Root | Node::Task(_) => None,
+            Node::RootFile(0) => Some(meminfo()),
+            Node::RootFile(_) => Some(uptime()),
+            Node::TaskFile(pid, i) => {
+                let task = find_task(pid)?;
+                Some(match i {
+                    0 => status(&task),
+                    1 => maps(&task),
+                    _ => fds(&task),
+                })
+            }
+        }
+    }
+}
+
+fn find_task(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    all_tasks().into_iter().find(|task| task.getpid() == pid)
+}
+
+fn meminfo() -> String {
+    let (used, total) = frame_usage();
+    format!(
+        "frames: {}\nused: {}\nfree: {}\n",
+        total,
+        used,
+        total - used
+    )
+}
+
+fn uptime() -> String {
+    let ms = get_time_ms();
+    format!("{}.{:03}\n", ms / 1000, ms % 1000)
+}
+
+fn status(task: &Arc<TaskControlBlock>) -> String {
+    let inner = task.inner_exclusive_access();
+    let parent = inner
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(String::from("-"), |parent| parent.getpid().to_string());
+    let mut children = String::new();
+    for child in inner.children.iter() {
+        write!(children, " {}", child.getpid()).unwrap();
+    }
+    format!(
+        "pid: {}\nstatus: {:?}\nparent: {}\nchildren:{}\nexit_code: {}\nbase_size: {:#x}\n",
+        task.getpid(),
+        inner.task_status,
+        parent,
+        children,
+        inner.exit_code,
+        inner.base_size
+    )
+}
+
+/// `start-end perm type frames` for each area
+fn maps(task: &Arc<TaskControlBlock>) -> String {
+    let inner = task.inner_exclusive_access();
+    let mut text = String::new();
+    for area in inner.memory_set.areas() {
+        let range = area.vpn_range();
+        let perm = area.map_perm();
+        let flag = |bit: MapPermission, c: char| if perm.contains(bit) { c } else { '-' };
+        writeln!(
+            text,
+            "{:#x}-{:#x} {}{}{}{} {} {}",
+            VirtAddr::from(range.get_start()).0,
+            VirtAddr::from(range.get_end()).0,
+            flag(MapPermission::R, 'r'),
+            flag(MapPermission::W, 'w'),
+            flag(MapPermission::X, 'x'),
+            flag(MapPermission::U, 'u'),
+            match area.map_type() {
+                MapType::Identical => "identical",
+                MapType::Framed => "framed",
+            },
+            area.frame_count()
+        )
+        .unwrap();
+    }
+    text
+}
+
+/// `fd type [cloexec]` for each open fd
+/**
+    Only the type of a file is asked, which locks nothing: the file being
+    read may be this one.
+*/
+fn fds(task: &Arc<TaskControlBlock>) -> String {
+    let fd_table: Vec<Option<FdEntry>> = task.inner_exclusive_access().fd_table.clone();
+    let mut text = String::new();
+    for (fd, entry) in fd_table.iter().enumerate() {
+        if let Some(entry) = entry {
+            let mode = entry.file.mode();
+            let kind = if mode.contains(StatMode::DIR) {
+                "dir"
+            } else if mode.contains(StatMode::FILE) {
+                "file"
+            } else if mode.contains(StatMode::FIFO) {
+                "pipe"
+            } else {
+                "chr"
+            };
+            let cloexec = if entry.cloexec { " cloexec" } else { "" };
+            writeln!(text, "{} {}{}", fd, kind, cloexec).unwrap();
+        }
+    }
+    text
+}
+
+impl FsInode for ProcInode {
+    /// the root, then a block of 256 numbers for each pid
+    fn inode_id(&self) -> u32 {
+        match self.node {
+            Node::Root => ROOT_ID,
+            Node::RootFile(i) => ROOT_ID + 1 + i as u32,
+            Node::Task(pid) => ROOT_ID + ((pid as u32 + 1) << 8),
+            Node::TaskFile(pid, i) => ROOT_ID + ((pid as u32 + 1) << 8) + 1 + i as u32,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.node, Node::Root | Node::Task(_))
+    }
+
+    fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+
+    fn size(&self) -> usize {
+        self.contents().map_or(0, |text| text.len())
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let text = match self.contents() {
+            Some(text) => text,
+            None => return 0,
+        };
+        let bytes = text.as_bytes();
+        if offset >= bytes.len() {
+            return 0;
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        len
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<dyn FsInode>> {
+        match self.node {
+            Node::Root => {
+                if let Some(i) = ROOT_FILES.iter().position(|file| *file == name) {
+                    return Some(Self::new(Node::RootFile(i)));
+                }
+                let pid = name.parse::<usize>().ok()?;
+                find_task(pid).map(|_| Self::new(Node::Task(pid)))
+            }
+            Node::Task(pid) => {
+                find_task(pid)?;
+                let i = TASK_FILES.iter().position(|file| *file == name)?;
+                Some(Self::new(Node::TaskFile(pid, i)))
+            }
+            _ => None,
+        }
+    }
+
+    fn ls(&self) -> Vec<String> {
+        match self.node {
+            Node::Root => {
+                let mut pids: Vec<usize> = all_tasks().iter().map(|task| task.getpid()).collect();
+                pids.sort_unstable();
+                ROOT_FILES
+                    .iter()
+                    .map(|file| String::from(*file))
+                    .chain(pids.into_iter().map(|pid| pid.to_string()))
+                    .collect()
+            }
+            Node::Task(_) => TASK_FILES.iter().map(|file| String::from(*file)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn nlink(&self) -> u32 {
+        match self.node {
+            Node::Root => 2 + all_tasks().len() as u32,
+            Node::Task(_) => 2,
+            _ => 1,
+        }
+    }
+}
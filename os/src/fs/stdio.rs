@@ -0,0 +1,74 @@
+//! Stdin and Stdout as files, on the SBI console
+use super::{File, Stat, StatMode};
+use crate::mm::UserBuffer;
+use crate::sbi::console_getchar;
+use crate::task::suspend_current_and_run_next;
+
+/// the console as fd 0
+pub struct Stdin;
+
+/// the console as fd 1 and 2
+pub struct Stdout;
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, mut user_buf: UserBuffer) -> usize {
+        assert_eq!(user_buf.len(), 1, "Only support len = 1 read from stdin!");
+        // wait for a char, letting the other tasks run meanwhile
+        let mut c: usize;
+        loop {
+            c = console_getchar();
+            if c == 0 {
+                suspend_current_and_run_next();
+                continue;
+            } else {
+                break;
+            }
+        }
+        let ch = c as u8;
+        unsafe {
+            user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
+        }
+        1
+    }
+
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        panic!("Cannot write to stdin!");
+    }
+
+    fn stat(&self) -> Stat {
+        Stat::without_inode(StatMode::CHR)
+    }
+}
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, _user_buf: UserBuffer) -> usize {
+        panic!("Cannot read from stdout!");
+    }
+
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        for buffer in user_buf.buffers.iter() {
+            print!("{}", core::str::from_utf8(buffer).unwrap());
+        }
+        user_buf.len()
+    }
+
+    fn stat(&self) -> Stat {
+        Stat::without_inode(StatMode::CHR)
+    }
+}
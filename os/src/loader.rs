@@ -1,7 +1,23 @@
 //! Loading user applications into memory
+/*!
+    Programs are read from the mounted filesystem. The apps linked into
+    the kernel by `link_app.S` are only a fallback, like an initramfs,
+    for names the filesystem does not have.
+*/
 
+use crate::config::{PAGE_SIZE, USER_SPACE_END, USER_STACK_SIZE};
+use crate::fs::{open_file, Cwd, OpenFlags};
+use alloc::borrow::Cow;
 use alloc::vec::Vec;
 use lazy_static::*;
+use xmas_elf::header::Class;
+use xmas_elf::program::Type;
+use xmas_elf::ElfFile;
+
+/// the first bytes of an ELF file
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+/// size of a program header of a 64-bit ELF file
+const PH64_SIZE: usize = 56;
 
 /// get the total number of applications
 pub fn get_num_app() -> usize {
@@ -76,3 +92,73 @@ pub fn list_apps() {
     }
     println!("/*******************/");
 }
+
+/// Get the ELF data of the program at `path`, relative to `cwd`
+/**
+    The filesystem is tried first, then the linked apps by name. Files
+    can be written by users, so anything `MemorySet::from_elf` could not
+    map is refused here rather than making the kernel panic.
+*/
+pub fn load_app(cwd: &Cwd, path: &str) -> Option<Cow<'static, [u8]>> {
+    let data = match open_file(cwd, path, OpenFlags::RDONLY) {
+        Some(inode) if inode.is_file() => Cow::Owned(inode.read_all()),
+        Some(_) => return None,
+        None => Cow::Borrowed(get_app_data_by_name(path)?),
+    };
+    if data.starts_with(ELF_MAGIC) && can_map(&data) {
+        Some(data)
+    } else {
+        None
+    }
+}
+
+/// Whether `MemorySet::from_elf` can map the ELF file `data`
+/**
+    Every program header must parse, and the loadable segments must lie
+    in the file, come in order without sharing a page, and leave room for
+    the user stack below `USER_SPACE_END`.
+*/
+fn can_map(data: &[u8]) -> bool {
+    let elf = match ElfFile::new(data) {
+        Ok(elf) => elf,
+        Err(_) => return false,
+    };
+    // xmas_elf slices the program header table without checking it
+    let pt2 = &elf.header.pt2;
+    let table_size = pt2.ph_count() as u64 * pt2.ph_entry_size() as u64;
+    let table_end = pt2.ph_offset().saturating_add(table_size);
+    if elf.header.pt1.class() != Class::SixtyFour
+        || pt2.ph_entry_size() as usize != PH64_SIZE
+        || pt2.ph_offset() % 8 != 0
+        || table_end > data.len() as u64
+    {
+        return false;
+    }
+    let mut end = 0usize;
+    for i in 0..elf.header.pt2.ph_count() {
+        let ph = match elf.program_header(i) {
+            Ok(ph) => ph,
+            Err(_) => return false,
+        };
+        match ph.get_type() {
+            Ok(Type::Load) => {}
+            Ok(_) => continue,
+            Err(_) => return false,
+        }
+        let in_file = ph
+            .offset()
+            .checked_add(ph.file_size())
+            .map_or(false, |file_end| file_end <= data.len() as u64);
+        let start = ph.virtual_addr() as usize;
+        let mem_end = match ph.virtual_addr().checked_add(ph.mem_size()) {
+            Some(mem_end) if mem_end <= USER_SPACE_END as u64 => mem_end as usize,
+            _ => return false,
+        };
+        if !in_file || ph.file_size() > ph.mem_size() || start / PAGE_SIZE < end {
+            return false;
+        }
+        end = (mem_end + PAGE_SIZE - 1) / PAGE_SIZE;
+    }
+    // a guard page, then the stack
+    (end + 1) * PAGE_SIZE + USER_STACK_SIZE <= USER_SPACE_END
+}
@@ -25,6 +25,8 @@ mod sync;
 mod loader;
 mod timer;
 mod mm;
+mod drivers;
+mod fs;
 pub mod syscall;
 pub mod trap;
 pub mod task;
@@ -86,6 +88,7 @@ pub fn rust_main() -> ! {
      
     info!("[kernel] run first task...");
     loader::list_apps();
+    fs::list_root();
     task::run_tasks();
 
     panic!("Unreachable in rust_main!");
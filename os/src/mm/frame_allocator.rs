@@ -49,6 +49,7 @@ trait FrameAllocator {
 
 /// an simple implementation for frame allocator
 pub struct StackFrameAllocator {
+    start: usize,
     current: usize,
     end: usize,
     
@@ -58,10 +59,16 @@ pub struct StackFrameAllocator {
 
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
         self.current = l.0;
         self.end = r.0;
     }
 
+    /// (frames in use, all frames)
+    pub fn usage(&self) -> (usize, usize) {
+        (self.current - self.start - self.recycled.len(), self.end - self.start)
+    }
+
     pub fn current1(&self) -> usize {
         self.current
     }
@@ -81,6 +88,7 @@ impl StackFrameAllocator {
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self {
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
@@ -145,6 +153,11 @@ pub fn print_allocator_vec() {
     FRAME_ALLOCATOR.exclusive_access().vec1();
 }
 
+/// (frames in use, all frames) of the frame allocator
+pub fn frame_usage() -> (usize, usize) {
+    FRAME_ALLOCATOR.exclusive_access().usage()
+}
+
 /// initiate the frame allocator using [ekernel, MEMORY_END)
 pub fn init_frame_allocator() {
     extern "C" {
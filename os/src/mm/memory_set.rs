@@ -17,7 +17,7 @@ use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
 use crate::config::{
-    PAGE_SIZE, MEMORY_END, TRAMPOLINE,
+    PAGE_SIZE, MEMORY_END, MMIO, TRAMPOLINE,
     USER_STACK_SIZE, TRAP_CONTEXT
 };
 
@@ -37,6 +37,11 @@ lazy_static! {
     });
 }
 
+/// token of the kernel address space
+pub fn kernel_token() -> usize {
+    KERNEL_SPACE.exclusive_access().token()
+}
+
 extern "C" {
     fn stext();
     fn etext();
@@ -90,6 +95,24 @@ impl MapArea {
         }
     }
 
+    /// the virtual pages of the area
+    pub fn vpn_range(&self) -> VPNRange {
+        self.vpn_range
+    }
+
+    pub fn map_type(&self) -> MapType {
+        self.map_type
+    }
+
+    pub fn map_perm(&self) -> MapPermission {
+        self.map_perm
+    }
+
+    /// the frames the area holds, none for an identical mapping
+    pub fn frame_count(&self) -> usize {
+        self.data_frames.len()
+    }
+
     pub fn from_another(another: &MapArea) -> Self {
         Self {
             vpn_range: VPNRange::new(
@@ -189,6 +212,11 @@ impl MemorySet {
         self.page_table.token()
     }
 
+    /// the areas mapped, the trampoline left out
+    pub fn areas(&self) -> &[MapArea] {
+        &self.areas
+    }
+
     pub fn push(&mut self, mut map_area: MapArea,
                 data: Option<&[u8]>) {
        map_area.map(&mut self.page_table);
@@ -299,7 +327,15 @@ impl MemorySet {
        ), None);
 
        // map MMIO registers
-       //TODO
+       println!("mapping memory-mapped registers");
+       for &(base, size) in MMIO {
+           memory_set.push(MapArea::new(
+                   base.into(),
+                   (base + size).into(),
+                   MapType::Identical,
+                   MapPermission::R | MapPermission::W
+           ), None);
+       }
        
        memory_set
     }
@@ -6,11 +6,11 @@ mod page_table;
 mod frame_allocator;
 mod memory_set;
 
-use address::VPNRange;
+pub use address::VPNRange;
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum, StepByOne};
 
 pub use frame_allocator::{
-    frame_alloc, FrameTracker,
+    frame_alloc, frame_usage, FrameTracker,
     get_current, get_end, print_allocator_vec
 };
 
@@ -20,9 +20,10 @@ pub use page_table::{
     translated_byte_buffer,
     translated_str,
     translated_refmut,
+    UserBuffer,
 };
 
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
+pub use memory_set::{MapArea, MapPermission, MapType, MemorySet, KERNEL_SPACE, kernel_token};
 pub use memory_set::remap_test;
 
 /// initiate heap_allocator/frame_allocator...
@@ -1,57 +1,245 @@
 //! File and filesystem-related syscalls
-use crate::mm::translated_byte_buffer;
-use crate::sbi::console_getchar;
-use crate::task::{
-    current_user_token,
-    suspend_current_and_run_next,
-};
+use crate::config::MAX_FD;
+use crate::fs::{make_pipe, open_file, Cwd, FdEntry, File, OpenFlags};
+use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
+use crate::task::{current_task, current_user_token};
+use alloc::sync::Arc;
+use easy_fs::DIRENT_SZ;
 
-const FD_STDIN: usize = 0;
-const FD_STDOUT: usize = 1;
+pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(entry) = &inner.fd_table[fd] {
+        if !entry.file.writable() {
+            return -1;
+        }
+        let file = entry.file.clone();
+        // release the task, writing may switch to another one
+        drop(inner);
+        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+    } else {
+        -1
+    }
+}
 
 pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDIN => {
-            assert_eq!(len, 1, "Only support len = 1 sys_read!");
-            let mut c: usize;
-            loop {
-                c = console_getchar();
-                if c == 0 {
-                    suspend_current_and_run_next();
-                    continue;
-                } else {
-                    break;
-                }
-            }
-            let ch = c as u8;
-            let mut buffers = translated_byte_buffer(
-                current_user_token(),
-                buf,
-                len
-            );
-            unsafe {
-                buffers[0].as_mut_ptr().write_volatile(ch);
-            }
-            1
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(entry) = &inner.fd_table[fd] {
+        if !entry.file.readable() {
+            return -1;
         }
-        _ => {
-            panic!("Unsupported fd in sys_read");
+        let file = entry.file.clone();
+        // release the task, reading stdin suspends it
+        drop(inner);
+        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+    } else {
+        -1
+    }
+}
+
+/// open a file, relative to the current directory, return its fd or -1
+pub fn sys_open(path: *const u8, flags: u32) -> isize {
+    let task = current_task().unwrap();
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    // walk the path with the task released, as /proc looks at every task
+    let cwd = task.inner_exclusive_access().cwd.clone();
+    if let Some(inode) = open_file(&cwd, path.as_str(), flags) {
+        let mut inner = task.inner_exclusive_access();
+        let fd = match inner.alloc_fd() {
+            Some(fd) => fd,
+            None => return -1,
+        };
+        inner.fd_table[fd] = Some(FdEntry::new(inode, flags.contains(OpenFlags::CLOEXEC)));
+        fd as isize
+    } else {
+        -1
+    }
+}
+
+pub fn sys_close(fd: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if inner.fd_table[fd].is_none() {
+        return -1;
+    }
+    inner.fd_table[fd].take();
+    0
+}
+
+/// make a pipe, write its read fd and write fd to `pipe[0]` and `pipe[1]`
+pub fn sys_pipe(pipe: *mut usize) -> isize {
+    let task = current_task().unwrap();
+    let token = current_user_token();
+    let mut inner = task.inner_exclusive_access();
+    let (pipe_read, pipe_write) = make_pipe();
+    let read_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[read_fd] = Some(FdEntry::new(pipe_read, false));
+    let write_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => {
+            inner.fd_table[read_fd] = None;
+            return -1;
         }
+    };
+    inner.fd_table[write_fd] = Some(FdEntry::new(pipe_write, false));
+    *translated_refmut(token, pipe) = read_fd;
+    *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
+    0
+}
+
+/// copy `fd` to the lowest free fd, which stays open across exec
+pub fn sys_dup(fd: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
     }
+    let file = match &inner.fd_table[fd] {
+        Some(entry) => entry.file.clone(),
+        None => return -1,
+    };
+    let new_fd = match inner.alloc_fd() {
+        Some(fd) => fd,
+        None => return -1,
+    };
+    inner.fd_table[new_fd] = Some(FdEntry::new(file, false));
+    new_fd as isize
 }
 
-pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
-            let token = current_user_token();
-            let buffers = translated_byte_buffer(token, buf, len);
-            for buf in buffers {
-                print!("{}", core::str::from_utf8(buf).unwrap());
-            }
-            len as isize
+/// copy `old_fd` to `new_fd`, closing what `new_fd` had open
+/**
+    Only `CLOEXEC` is allowed in `flags`, it marks `new_fd` close-on-exec.
+    Copying an fd onto itself does nothing with no flags, like `dup2`,
+    and fails with flags, like `dup3`.
+*/
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let cloexec = match OpenFlags::from_bits(flags) {
+        Some(flags) if (flags - OpenFlags::CLOEXEC).is_empty() => flags.contains(OpenFlags::CLOEXEC),
+        _ => return -1,
+    };
+    if old_fd >= inner.fd_table.len() || new_fd >= MAX_FD {
+        return -1;
+    }
+    let file = match &inner.fd_table[old_fd] {
+        Some(entry) => entry.file.clone(),
+        None => return -1,
+    };
+    if old_fd == new_fd {
+        return if flags == 0 { new_fd as isize } else { -1 };
+    }
+    if new_fd >= inner.fd_table.len() {
+        inner.fd_table.resize(new_fd + 1, None);
+    }
+    inner.fd_table[new_fd] = Some(FdEntry::new(file, cloexec));
+    new_fd as isize
+}
+
+/// the file open as `fd` in the current task
+fn get_file(fd: usize) -> Option<Arc<dyn File>> {
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let file = inner.fd_table.get(fd)?.as_ref().map(|entry| entry.file.clone());
+    file
+}
+
+/// copy `bytes` to user space at `ptr`, which may span pages
+fn copy_to_user(token: usize, ptr: *mut u8, bytes: &[u8]) {
+    let mut copied = 0;
+    for buffer in translated_byte_buffer(token, ptr, bytes.len()) {
+        buffer.copy_from_slice(&bytes[copied..copied + buffer.len()]);
+        copied += buffer.len();
+    }
+}
+
+/// move the offset of `fd`, return the new offset or -1
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    match get_file(fd) {
+        Some(file) => file.seek(offset, whence),
+        None => -1,
+    }
+}
+
+/// write what is known of `fd` to `st`
+pub fn sys_fstat(fd: usize, st: *mut u8) -> isize {
+    let token = current_user_token();
+    match get_file(fd) {
+        Some(file) => {
+            copy_to_user(token, st, file.stat().as_bytes());
+            0
         }
-        _ => {
-            panic!("Current rCore unsupported fd in sys_write!");
+        None => -1,
+    }
+}
+
+/// write the next at most `count` entries of the directory `fd` to `dirents`
+/**
+    Returns the number of entries written, 0 once all were read,
+    or -1 if `fd` is not an open directory.
+*/
+pub fn sys_getdents(fd: usize, dirents: *mut u8, count: usize) -> isize {
+    let token = current_user_token();
+    let entries = match get_file(fd).and_then(|file| file.getdents(count)) {
+        Some(entries) => entries,
+        None => return -1,
+    };
+    for (i, entry) in entries.iter().enumerate() {
+        copy_to_user(token, dirents.wrapping_add(i * DIRENT_SZ), entry.as_bytes());
+    }
+    entries.len() as isize
+}
+
+/// make the directory at `path` the current one
+pub fn sys_chdir(path: *const u8) -> isize {
+    let task = current_task().unwrap();
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    // walk the path with the task released, as /proc looks at every task
+    let cwd = task.inner_exclusive_access().cwd.clone();
+    match cwd.find(path.as_str()) {
+        Some(inode) if inode.is_dir() => {
+            let path = cwd.absolute(path.as_str());
+            task.inner_exclusive_access().cwd = Cwd { inode, path };
+            0
         }
+        _ => -1,
+    }
+}
+
+/// write the path of the current directory to `buf`, with a trailing '\0'
+/**
+    Returns the length written, '\0' included, or -1 if it does not fit in
+    `len` bytes.
+*/
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
+    let task = current_task().unwrap();
+    let token = current_user_token();
+    let mut path = task.inner_exclusive_access().cwd.path.clone().into_bytes();
+    path.push(0);
+    if path.len() > len {
+        return -1;
     }
+    copy_to_user(token, buf, &path);
+    path.len() as isize
 }
@@ -1,8 +1,18 @@
 //! Implementation of syscalls
 
 // syscall ID
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_OPEN: usize = 56;
+const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE: usize = 59;
+const SYSCALL_GETDENTS: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_GET_TIMER: usize = 169;
@@ -19,8 +29,18 @@ use process::{*};
 
 pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
    match syscall_id {
+       SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+       SYSCALL_DUP => sys_dup(args[0]),
+       SYSCALL_DUP3 => sys_dup3(args[0], args[1], args[2] as u32),
+       SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
+       SYSCALL_OPEN => sys_open(args[0] as *const u8, args[1] as u32),
+       SYSCALL_CLOSE => sys_close(args[0]),
+       SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
+       SYSCALL_GETDENTS => sys_getdents(args[0], args[1] as *mut u8, args[2]),
+       SYSCALL_LSEEK => sys_lseek(args[0], args[1] as isize, args[2]),
        SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
        SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+       SYSCALL_FSTAT => sys_fstat(args[0], args[1] as *mut u8),
        SYSCALL_EXIT => sys_exit(args[0] as i32),
        SYSCALL_YIELD => sys_yield(),
        SYSCALL_GET_TIMER => sys_get_timer(),
@@ -1,5 +1,5 @@
 //! Process management syscalls
-use crate::loader::get_app_data_by_name;
+use crate::loader::load_app;
 use crate::task::{
     add_task, current_task, current_user_token,
     exit_current_and_run_next, suspend_current_and_run_next,
@@ -56,10 +56,12 @@ pub fn sys_exec(path: *const u8) -> isize {
     // current `path` is va 
     let token = current_user_token();
     let path = translated_str(token, path);
-    // execute exec
-    if let Some(data) = get_app_data_by_name(path.as_str()) {
-        let task = current_task().unwrap();
-        task.exec(data);
+    let task = current_task().unwrap();
+    // walk the path with the task released, as /proc looks at every task
+    let cwd = task.inner_exclusive_access().cwd.clone();
+    // execute exec, with the program from the filesystem or the linked apps
+    if let Some(data) = load_app(&cwd, path.as_str()) {
+        task.exec(&data);
         0
     } else {
         -1
@@ -11,14 +11,16 @@ use crate::sbi::shutdown;
 use crate::sync::UPSafeCell;
 use crate::trap::TrapContext;
 use lazy_static::*;
-use task::{TaskControlBlock, TaskStatus};
+pub use task::{TaskControlBlock, TaskStatus};
 use switch::__switch;
 pub use context::TaskContext;
 
+use alloc::vec;
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 
-use crate::loader::get_app_data_by_name;
+use crate::fs::Cwd;
+use crate::loader::load_app;
 pub use pid::{pid_alloc, KernelStack, PidAllocator, PidHandle};
 pub use manager::{add_task, fetch_task, TaskManager};
 pub use processor::{
@@ -29,7 +31,22 @@ pub use processor::{
 
 lazy_static! {
     pub static ref INITPROC: Arc<TaskControlBlock> =
-        Arc::new(TaskControlBlock::new(get_app_data_by_name("initproc").unwrap()));
+        Arc::new(TaskControlBlock::new(&load_app(&Cwd::root(), "initproc").expect("No initproc!")));
+}
+
+/// Every task, zombies included, found down the tree under initproc
+/**
+    An exiting task gives its children to initproc, so none is left out.
+*/
+pub fn all_tasks() -> Vec<Arc<TaskControlBlock>> {
+    let mut tasks = vec![INITPROC.clone()];
+    let mut i = 0;
+    while i < tasks.len() {
+        let children = tasks[i].inner_exclusive_access().children.clone();
+        tasks.extend(children);
+        i += 1;
+    }
+    tasks
 }
 
 pub fn add_initproc() {
@@ -65,6 +82,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     }
 
     inner.children.clear();
+    // close the files now, a zombie may wait long to be reaped
+    inner.fd_table.clear();
     inner.memory_set.recycle_data_pages();
 
     drop(inner);
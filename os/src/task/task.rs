@@ -1,20 +1,22 @@
 //! Implementation of TaskControlBlock
 use super::TaskContext;
-use crate::config::{TRAP_CONTEXT, kernel_stack_position};
+use crate::config::{MAX_FD, TRAP_CONTEXT, kernel_stack_position};
 use crate::mm::{
     MemorySet, PhysPageNum, KERNEL_SPACE,
     MapPermission, VirtAddr,
 };
 use crate::trap::{trap_handler, TrapContext};
+use crate::fs::{Cwd, FdEntry, Stdin, Stdout};
 
 use super::{pid_alloc, KernelStack, PidHandle};
 
 use crate::sync::UPSafeCell;
 use alloc::sync::{Arc, Weak};
+use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefMut;
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 /// task status: Ready/Running/Zombie
 pub enum TaskStatus {
     Ready,
@@ -40,6 +42,9 @@ pub struct TaskControlBlockInner {
     pub parent: Option<Weak<TaskControlBlock>>,
     pub children: Vec<Arc<TaskControlBlock>>,
     pub exit_code: i32,
+    pub fd_table: Vec<Option<FdEntry>>,
+    /// where relative paths start from
+    pub cwd: Cwd,
 }
 
 impl TaskControlBlockInner {
@@ -58,6 +63,21 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
+
+    /// the lowest free fd, the table grows when all are taken
+    /**
+        None once the task has `MAX_FD` fds open.
+    */
+    pub fn alloc_fd(&mut self) -> Option<usize> {
+        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+            Some(fd)
+        } else if self.fd_table.len() < MAX_FD {
+            self.fd_table.push(None);
+            Some(self.fd_table.len() - 1)
+        } else {
+            None
+        }
+    }
 }
 
 impl TaskControlBlock {
@@ -100,6 +120,15 @@ impl TaskControlBlock {
                   parent: None,
                   children: Vec::new(),
                   exit_code: 0,
+                  fd_table: vec![
+                      // 0 -> stdin
+                      Some(FdEntry::new(Arc::new(Stdin), false)),
+                      // 1 -> stdout
+                      Some(FdEntry::new(Arc::new(Stdout), false)),
+                      // 2 -> stderr
+                      Some(FdEntry::new(Arc::new(Stdout), false)),
+                  ],
+                  cwd: Cwd::root(),
               })  
             },
         };
@@ -124,6 +153,10 @@ impl TaskControlBlock {
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
             .ppn();
+        // the child shares the open files of the parent
+        let fd_table = parent_inner.fd_table.clone();
+        // and starts in its directory
+        let cwd = parent_inner.cwd.clone();
         // alloc a pid and kernel_stack
         let pid_handle = pid_alloc();
         let kernel_stack = KernelStack::new(&pid_handle);
@@ -144,6 +177,8 @@ impl TaskControlBlock {
                            parent: Some(Arc::downgrade(self)),
                            children: Vec::new(),
                            exit_code: 0,
+                           fd_table,
+                           cwd,
                        }
                    )
                },
@@ -168,6 +203,12 @@ impl TaskControlBlock {
         let mut inner = self.inner_exclusive_access();
         inner.memory_set = memory_set;
         inner.trap_cx_ppn = trap_cx_ppn;
+        // close the files marked close-on-exec
+        for fd in inner.fd_table.iter_mut() {
+            if fd.as_ref().map_or(false, |entry| entry.cloexec) {
+                *fd = None;
+            }
+        }
         // initialize trap_cx
         let trap_cx = inner.get_trap_cx();
         *trap_cx = TrapContext::app_init_context(
@@ -0,0 +1,34 @@
+//! exec of files that only look like programs fails instead of crashing the kernel
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, exec, open, write, OpenFlags};
+
+/// write `data` to `path`, then try to run it
+fn exec_file(path: &str, data: &[u8]) -> isize {
+    let fd = open(path, OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, data);
+    close(fd as usize);
+    exec(path)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(exec_file("badelf_text\0", b"not a program"), -1);
+    // the magic number, then junk
+    assert_eq!(exec_file("badelf_magic\0", b"\x7fELF\x02\x01\x01junk"), -1);
+    // a whole 64-bit header with a program header table past the end
+    let mut header = [0u8; 64];
+    header[..7].copy_from_slice(b"\x7fELF\x02\x01\x01");
+    header[32] = 0xf0; // ph_offset
+    header[54] = 56; // ph_entry_size
+    header[56] = 4; // ph_count
+    assert_eq!(exec_file("badelf_table\0", &header), -1);
+    println!("badelf passed!");
+    0
+}
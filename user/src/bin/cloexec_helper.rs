@@ -0,0 +1,24 @@
+//! Run by duptest through exec: fd 10 must be open, fd 11 closed
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::read;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buffer = [0u8; 32];
+    if read(11, &mut buffer) != -1 {
+        println!("fd 11 is still open after exec!");
+        return -1;
+    }
+    let len = read(10, &mut buffer);
+    if len <= 0 || &buffer[..len as usize] != b"redirected\n" {
+        println!("fd 10 was not kept across exec!");
+        return -2;
+    }
+    0
+}
@@ -0,0 +1,59 @@
+//! chdir, getcwd, and paths relative to the current directory
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{chdir, close, exit, fork, getcwd, open, read, waitpid, write, OpenFlags};
+
+/// open `path` read-only, check it holds `expected`
+fn check_file(path: &str, expected: &[u8]) {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0, "cannot open {}", path);
+    let mut buffer = [0u8; 32];
+    let len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(&buffer[..len], expected);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buffer = [0u8; 64];
+    assert_eq!(getcwd(&mut buffer), Some("/"));
+    assert_eq!(getcwd(&mut buffer[..1]), None);
+
+    let fd = open("cwdtest_file\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd > 0);
+    write(fd as usize, b"cwd");
+    close(fd as usize);
+
+    // only directories can be entered
+    assert_eq!(chdir("no_such_dir\0"), -1);
+    assert_eq!(chdir("cwdtest_file\0"), -1);
+    // `.` and `..` are walked, the root is its own parent
+    assert_eq!(chdir("./..\0"), 0);
+    assert_eq!(getcwd(&mut buffer), Some("/"));
+    assert_eq!(chdir("/\0"), 0);
+
+    check_file("cwdtest_file\0", b"cwd");
+    check_file("./cwdtest_file\0", b"cwd");
+    check_file("../cwdtest_file\0", b"cwd");
+    check_file("/cwdtest_file\0", b"cwd");
+
+    // a child starts where its parent is
+    let pid = fork();
+    if pid == 0 {
+        let mut buffer = [0u8; 64];
+        assert_eq!(getcwd(&mut buffer), Some("/"));
+        check_file("cwdtest_file\0", b"cwd");
+        exit(0);
+        unreachable!();
+    }
+    let mut exit_code = 0;
+    assert_eq!(waitpid(pid as usize, &mut exit_code), pid);
+    assert_eq!(exit_code, 0);
+    println!("cwdtest passed!");
+    0
+}
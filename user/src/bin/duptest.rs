@@ -0,0 +1,87 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, dup, dup2, dup3, exec, fork, open, pipe, read, wait, write, OpenFlags};
+
+const FILE: &str = "dupfile\0";
+const LINE: &str = "redirected\n";
+
+/// fork, run `child` in the child, return its exit code
+fn in_child(child: fn() -> i32) -> i32 {
+    if fork() == 0 {
+        user_lib::exit(child());
+        unreachable!();
+    }
+    let mut exit_code: i32 = 0;
+    wait(&mut exit_code);
+    exit_code
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // a copy of stdout writes to the console
+    let fd = dup(1);
+    assert!(fd > 2);
+    let text = "dup of stdout\n";
+    assert_eq!(write(fd as usize, text.as_bytes()), text.len() as isize);
+    close(fd as usize);
+    assert_eq!(dup(fd as usize), -1);
+
+    // stdout of a child sent to a file
+    let exit_code = in_child(|| {
+        let fd = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY);
+        assert!(fd > 0);
+        assert_eq!(dup2(fd as usize, 1), 1);
+        close(fd as usize);
+        print!("{}", LINE);
+        0
+    });
+    assert_eq!(exit_code, 0);
+    let fd = open(FILE, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 32];
+    let len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(core::str::from_utf8(&buffer[..len]).unwrap(), LINE);
+
+    // exec keeps fd 10 and closes fd 11, cloexec_helper checks it
+    let exit_code = in_child(|| {
+        let fd = open(FILE, OpenFlags::RDONLY | OpenFlags::CLOEXEC);
+        assert!(fd > 0);
+        assert_eq!(dup2(fd as usize, 10), 10);
+        assert_eq!(dup3(fd as usize, 11, OpenFlags::CLOEXEC), 11);
+        // onto itself, flags are refused rather than left unset
+        assert_eq!(dup2(10, 10), 10);
+        assert_eq!(dup3(10, 10, OpenFlags::CLOEXEC), -1);
+        exec("cloexec_helper\0");
+        unreachable!();
+    });
+    assert_eq!(exit_code, 0);
+
+    // the table stops growing at 256 fds, whatever call asks for one
+    let exit_code = in_child(|| {
+        let mut last = 0;
+        loop {
+            let fd = dup(1);
+            if fd < 0 {
+                break;
+            }
+            last = fd;
+        }
+        if last != 255 || open(FILE, OpenFlags::RDONLY) != -1 {
+            return -1;
+        }
+        let mut pipe_fd = [0usize; 2];
+        close(255);
+        if pipe(&mut pipe_fd) != -1 || dup(1) != 255 {
+            return -2;
+        }
+        0
+    });
+    assert_eq!(exit_code, 0);
+    println!("duptest passed!");
+    0
+}
@@ -36,6 +36,13 @@ pub fn main() -> i32 {
         test_str,
         core::str::from_utf8(&buffer[..read_len]).unwrap(),
     );
+
+    // names of 28 and 29 bytes leave no room for the NUL of an entry
+    let too_long = "a_file_name_of_28_bytes_long\0";
+    assert_eq!(too_long.len(), 29);
+    assert_eq!(open(too_long, OpenFlags::CREATE | OpenFlags::WRONLY), -1);
+    let too_long = "a_file_name_of_29_bytes_long_\0";
+    assert_eq!(open(too_long, OpenFlags::CREATE | OpenFlags::WRONLY), -1);
     println!("file_test passed!");
     0
 }
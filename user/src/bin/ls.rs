@@ -0,0 +1,47 @@
+//! List the current directory with the size of each file
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use user_lib::{close, fstat, getdents, open, DirEntry, OpenFlags, Stat, StatMode};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(".\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("ls: cannot open the current directory");
+        return -1;
+    }
+    let fd = fd as usize;
+    let mut entries = [DirEntry::empty(); 8];
+    loop {
+        let count = getdents(fd, &mut entries);
+        if count <= 0 {
+            break;
+        }
+        for entry in entries[..count as usize].iter() {
+            let path = format!("{}\0", entry.name());
+            let mut stat = Stat::new();
+            let child = open(path.as_str(), OpenFlags::RDONLY);
+            if child < 0 {
+                println!("{:>10} {}", "?", entry.name());
+                continue;
+            }
+            fstat(child as usize, &mut stat);
+            close(child as usize);
+            if stat.mode.contains(StatMode::DIR) {
+                println!("{:>10} {}/", "-", entry.name());
+            } else {
+                println!("{:>10} {}", stat.size, entry.name());
+            }
+        }
+    }
+    close(fd);
+    0
+}
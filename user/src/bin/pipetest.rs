@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fork, pipe, read, wait, write};
+
+/// more than the kernel buffer of a pipe holds
+const LENGTH: usize = 1000;
+
+fn byte_at(i: usize) -> u8 {
+    (i * 7 % 251) as u8
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut pipe_fd = [0usize; 2];
+    assert_eq!(pipe(&mut pipe_fd), 0);
+    if fork() == 0 {
+        // child: read until the end of file
+        close(pipe_fd[1]);
+        let mut buffer = [0u8; 64];
+        let mut total = 0usize;
+        loop {
+            let len = read(pipe_fd[0], &mut buffer);
+            assert!(len >= 0);
+            if len == 0 {
+                break;
+            }
+            for (i, byte) in buffer[..len as usize].iter().enumerate() {
+                assert_eq!(*byte, byte_at(total + i));
+            }
+            total += len as usize;
+        }
+        close(pipe_fd[0]);
+        assert_eq!(total, LENGTH);
+        println!("Read OK, child process exited!");
+        0
+    } else {
+        // parent: write everything, blocking while the pipe is full
+        close(pipe_fd[0]);
+        let mut data = [0u8; LENGTH];
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = byte_at(i);
+        }
+        assert_eq!(write(pipe_fd[1], &data), LENGTH as isize);
+        close(pipe_fd[1]);
+        let mut child_exit_code: i32 = 0;
+        wait(&mut child_exit_code);
+        assert_eq!(child_exit_code, 0);
+        // nothing is written once no reader is left
+        assert_eq!(pipe(&mut pipe_fd), 0);
+        close(pipe_fd[0]);
+        assert_eq!(write(pipe_fd[1], b"lost"), 0);
+        close(pipe_fd[1]);
+        println!("pipetest passed!");
+        0
+    }
+}
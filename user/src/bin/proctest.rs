@@ -0,0 +1,106 @@
+//! What /proc tells of this task and of the kernel
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{
+    close, exit, fork, fstat, getdents, getpid, open, pipe, read, waitpid, write, DirEntry,
+    OpenFlags, Stat, StatMode,
+};
+
+fn read_file(path: &str) -> String {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0, "cannot open {}", path);
+    let mut text = String::new();
+    let mut buffer = [0u8; 64];
+    loop {
+        let len = read(fd as usize, &mut buffer);
+        if len <= 0 {
+            break;
+        }
+        text.push_str(core::str::from_utf8(&buffer[..len as usize]).unwrap());
+    }
+    close(fd as usize);
+    text
+}
+
+/// whether the directory at `path` lists `name`
+fn lists(path: &str, name: &str) -> bool {
+    let fd = open(path, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut entries = [DirEntry::empty(); 4];
+    let mut found = false;
+    loop {
+        let count = getdents(fd as usize, &mut entries);
+        if count <= 0 {
+            break;
+        }
+        found |= entries[..count as usize].iter().any(|entry| entry.name() == name);
+    }
+    close(fd as usize);
+    found
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid();
+    let dir = format!("/proc/{}", pid);
+    assert!(lists("/\0", "proc"));
+    assert!(lists("/proc\0", &format!("{}", pid)));
+    assert!(lists("/proc\0", "meminfo"));
+    assert!(lists(&format!("{}\0", dir), "status"));
+
+    let status = read_file(&format!("{}/status\0", dir));
+    assert!(status.starts_with(&format!("pid: {}\n", pid)));
+    assert!(status.contains("status: Running\n"));
+
+    // a child is listed until it is reaped
+    let child = fork();
+    if child == 0 {
+        exit(7);
+        unreachable!();
+    }
+    let status = read_file(&format!("{}/status\0", dir));
+    assert!(status.contains(&format!("children: {}\n", child)));
+    let mut exit_code = 0;
+    assert_eq!(waitpid(child as usize, &mut exit_code), child);
+    assert!(open(&format!("/proc/{}/status\0", child), OpenFlags::RDONLY) < 0);
+
+    // open fds, with their types
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    let fds = read_file(&format!("{}/fds\0", dir));
+    assert!(fds.starts_with("0 chr\n1 chr\n2 chr\n"));
+    assert!(fds.contains(&format!("{} pipe\n", pipe_fd[0])));
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+
+    // the code of the program, framed and executable for the user
+    let maps = read_file(&format!("{}/maps\0", dir));
+    assert!(maps.lines().any(|line| line.contains(" r-xu framed ")));
+
+    let meminfo = read_file("/proc/meminfo\0");
+    assert!(meminfo.starts_with("frames: "));
+    assert!(read_file("/proc/uptime\0").contains('.'));
+
+    // nothing can be changed
+    assert!(open("/proc/new\0", OpenFlags::CREATE | OpenFlags::WRONLY) < 0);
+    assert!(open("/proc/uptime\0", OpenFlags::WRONLY) < 0);
+    assert!(open("/proc/uptime\0", OpenFlags::TRUNC) < 0);
+    let fd = open("/proc/uptime\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, b"0"), -1);
+    let mut stat = Stat::new();
+    fstat(fd as usize, &mut stat);
+    assert!(stat.mode.contains(StatMode::FILE));
+    close(fd as usize);
+    println!("proctest passed!");
+    0
+}
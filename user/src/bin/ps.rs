@@ -0,0 +1,75 @@
+//! List the tasks, from what /proc tells of them
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{close, getdents, open, read, DirEntry, OpenFlags};
+
+/// the whole of a small file, None if it cannot be opened
+fn read_file(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut text = String::new();
+    let mut buffer = [0u8; 128];
+    loop {
+        let len = read(fd as usize, &mut buffer);
+        if len <= 0 {
+            break;
+        }
+        text.push_str(core::str::from_utf8(&buffer[..len as usize]).unwrap());
+    }
+    close(fd as usize);
+    Some(text)
+}
+
+/// the value of `key: value` in `status`
+fn field<'a>(status: &'a str, key: &str) -> &'a str {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(": "))
+        .unwrap_or("?")
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("/proc\0", OpenFlags::RDONLY);
+    if fd < 0 {
+        println!("ps: /proc is not there");
+        return -1;
+    }
+    let fd = fd as usize;
+    println!("{:>5} {:>5} {:<8} {}", "PID", "PPID", "STATUS", "CHILDREN");
+    let mut entries = [DirEntry::empty(); 8];
+    loop {
+        let count = getdents(fd, &mut entries);
+        if count <= 0 {
+            break;
+        }
+        for entry in entries[..count as usize].iter() {
+            if entry.name().parse::<usize>().is_err() {
+                continue;
+            }
+            // the task may have been reaped since
+            if let Some(status) = read_file(&format!("/proc/{}/status\0", entry.name())) {
+                println!(
+                    "{:>5} {:>5} {:<8} {}",
+                    field(&status, "pid"),
+                    field(&status, "parent"),
+                    field(&status, "status"),
+                    field(&status, "children").trim()
+                );
+            }
+        }
+    }
+    close(fd);
+    0
+}
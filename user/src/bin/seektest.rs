@@ -0,0 +1,35 @@
+//! Writing far past the end of a file writes nothing and leaves it as it was
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, lseek, open, read, write, OpenFlags, Stat, SEEK_SET};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open("seektest_file\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"data"), 4);
+
+    // no filesystem holds a file this large, the offset is still taken
+    for offset in [1isize << 32, 1 << 40, isize::MAX] {
+        assert_eq!(lseek(fd, offset, SEEK_SET), offset);
+        assert_eq!(write(fd, b"x"), 0);
+    }
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    assert_eq!(stat.size, 4);
+
+    // the file is still usable from the start
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let mut buffer = [0u8; 8];
+    assert_eq!(read(fd, &mut buffer), 4);
+    assert_eq!(&buffer[..4], b"data");
+    close(fd);
+    println!("seektest passed!");
+    0
+}
@@ -0,0 +1,79 @@
+//! lseek, fstat and getdents on a file, a directory and a pipe
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, fstat, getdents, lseek, open, pipe, read, write, DirEntry, OpenFlags, Stat, StatMode,
+    SEEK_CUR, SEEK_END, SEEK_SET,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let name = "stattest_file\0";
+    let fd = open(name, OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"0123456789"), 10);
+
+    // the offset moves from each origin, not before the start
+    assert_eq!(lseek(fd, 2, SEEK_SET), 2);
+    assert_eq!(lseek(fd, 3, SEEK_CUR), 5);
+    assert_eq!(lseek(fd, -1, SEEK_END), 9);
+    assert_eq!(lseek(fd, -20, SEEK_CUR), -1);
+    let mut buffer = [0u8; 4];
+    assert_eq!(lseek(fd, 3, SEEK_SET), 3);
+    assert_eq!(read(fd, &mut buffer), 4);
+    assert_eq!(&buffer, b"3456");
+
+    let mut stat = Stat::new();
+    assert_eq!(fstat(fd, &mut stat), 0);
+    assert!(stat.mode.contains(StatMode::FILE));
+    assert_eq!(stat.size, 10);
+    assert_eq!(stat.nlink, 1);
+    let ino = stat.ino;
+    assert_eq!(getdents(fd, &mut [DirEntry::empty()]), -1);
+    close(fd);
+
+    // the file is listed in the root, with the inode fstat told
+    let dir = open("/\0", OpenFlags::RDONLY);
+    assert!(dir > 0);
+    let dir = dir as usize;
+    assert_eq!(fstat(dir, &mut stat), 0);
+    assert!(stat.mode.contains(StatMode::DIR));
+    let mut entries = [DirEntry::empty(); 4];
+    let mut found = false;
+    loop {
+        let count = getdents(dir, &mut entries);
+        assert!(count >= 0);
+        if count == 0 {
+            break;
+        }
+        found |= entries[..count as usize]
+            .iter()
+            .any(|entry| entry.name() == "stattest_file" && entry.inode_number() as u64 == ino);
+    }
+    assert!(found);
+    // listing again from the start
+    assert_eq!(lseek(dir, 0, SEEK_SET), 0);
+    assert!(getdents(dir, &mut entries) > 0);
+    close(dir);
+
+    // a pipe and the console cannot seek
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(fstat(pipe_fd[0], &mut stat), 0);
+    assert!(stat.mode.contains(StatMode::FIFO));
+    assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    assert_eq!(fstat(1, &mut stat), 0);
+    assert!(stat.mode.contains(StatMode::CHR));
+    assert_eq!(lseek(1, 0, SEEK_SET), -1);
+    assert_eq!(fstat(pipe_fd[0], &mut stat), -1);
+    println!("stattest passed!");
+    0
+}
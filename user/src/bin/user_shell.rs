@@ -16,7 +16,69 @@ const BS: u8 = 0x08u8;
 
 use alloc::string::String;
 use user_lib::console::getchar;
-use user_lib::{fork, exec, waitpid};
+use user_lib::{chdir, close, dup2, exec, fork, open, waitpid, OpenFlags};
+
+/// A command line: a program, with its stdin and stdout maybe sent to files
+/**
+    Names are kept with a trailing '\0', the way the syscalls take them.
+*/
+struct Command {
+    program: String,
+    input: Option<String>,
+    output: Option<String>,
+}
+
+impl Command {
+    /// Parse `program [< input] [> output]`, None if it is not that
+    fn parse(line: &str) -> Option<Self> {
+        let mut program = None;
+        let mut input = None;
+        let mut output = None;
+        let mut words = line.split_whitespace();
+        while let Some(word) = words.next() {
+            let slot = match word {
+                "<" => &mut input,
+                ">" => &mut output,
+                _ => &mut program,
+            };
+            let name = if word == "<" || word == ">" { words.next()? } else { word };
+            if slot.is_some() {
+                return None;
+            }
+            let mut name = String::from(name);
+            name.push('\0');
+            *slot = Some(name);
+        }
+        Some(Self {
+            program: program?,
+            input,
+            output,
+        })
+    }
+
+    /// Open `path` with `flags` as the fd `fd`, false if that fails
+    fn redirect(path: &str, flags: OpenFlags, fd: usize) -> bool {
+        let file = open(path, flags);
+        if file < 0 {
+            return false;
+        }
+        if file as usize == fd {
+            return true;
+        }
+        let moved = dup2(file as usize, fd) == fd as isize;
+        close(file as usize);
+        moved
+    }
+}
+
+/// The `cd [dir]` builtin, run by the shell itself as a child cannot change its directory
+fn cd(dir: Option<&str>) {
+    let mut path = String::from(dir.unwrap_or("/"));
+    path.push('\0');
+    if chdir(path.as_str()) != 0 {
+        println!("cd: {}: not a directory", dir.unwrap_or("/"));
+    }
+}
 
 #[no_mangle]
 pub fn main() -> i32 {
@@ -28,12 +90,37 @@ pub fn main() -> i32 {
         match c {
             LF | CR => {
                 println!("");
-                if !line.is_empty() {
-                    line.push('\0');
+                let mut words = line.split_whitespace();
+                if words.next() == Some("cd") {
+                    cd(words.next());
+                    line.clear();
+                } else if !line.is_empty() {
+                    let command = match Command::parse(line.as_str()) {
+                        Some(command) => command,
+                        None => {
+                            println!("Usage: program [< input] [> output]");
+                            line.clear();
+                            print!(">> ");
+                            continue;
+                        }
+                    };
                     let pid = fork();
                     if pid == 0 {
                         // child process
-                        if exec(line.as_str()) == -1 {
+                        if let Some(input) = &command.input {
+                            if !Command::redirect(input, OpenFlags::RDONLY, 0) {
+                                println!("Error when opening {}!", input.trim_end_matches('\0'));
+                                return -4;
+                            }
+                        }
+                        if let Some(output) = &command.output {
+                            let flags = OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY;
+                            if !Command::redirect(output, flags, 1) {
+                                println!("Error when opening {}!", output.trim_end_matches('\0'));
+                                return -4;
+                            }
+                        }
+                        if exec(command.program.as_str()) == -1 {
                             println!("Error when executing! please try to input again after check path.");
                             return -4;
                         }
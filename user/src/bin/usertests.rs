@@ -5,20 +5,28 @@
 extern crate user_lib;
 
 // not in SUCC_TESTS & FAIL_TESTS
-// count_lines, infloop, user_shell, usertests
+// cloexec_helper, count_lines, infloop, ls, ps, user_shell, usertests
 
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
+    ("badelf\0", "\0", "\0", "\0", 0),
+    ("cwdtest\0", "\0", "\0", "\0", 0),
+    ("duptest\0", "\0", "\0", "\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
+    ("filetest_simple\0", "\0", "\0", "\0", 0),
     ("forktest_simple\0", "\0", "\0", "\0", 0),
     ("forktest\0", "\0", "\0", "\0", 0),
     ("forktest2\0", "\0", "\0", "\0", 0),
     ("forktree\0", "\0", "\0", "\0", 0),
     ("hello_world\0", "\0", "\0", "\0", 0),
     ("matrix\0", "\0", "\0", "\0", 0),
+    ("pipetest\0", "\0", "\0", "\0", 0),
+    ("proctest\0", "\0", "\0", "\0", 0),
+    ("seektest\0", "\0", "\0", "\0", 0),
     ("sleep_simple\0", "\0", "\0", "\0", 0),
     ("sleep\0", "\0", "\0", "\0", 0),
+    ("stattest\0", "\0", "\0", "\0", 0),
     ("yield\0", "\0", "\0", "\0", 0),
 ];
 
@@ -62,9 +62,25 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE =  1 << 9;
         const TRUNC = 1 << 10;
+        /// closed by exec
+        const CLOEXEC = 1 << 19;
     }
 }
 
+/// make `path` the current directory
+pub fn chdir(path: &str) -> isize {
+    sys_chdir(path)
+}
+
+/// the path of the current directory, written to `buf` and returned
+pub fn getcwd(buf: &mut [u8]) -> Option<&str> {
+    let len = sys_getcwd(buf);
+    if len <= 0 {
+        return None;
+    }
+    core::str::from_utf8(&buf[..len as usize - 1]).ok()
+}
+
 pub fn open(path: &str, flags: OpenFlags) -> isize {
     sys_open(path, flags.bits)
 }
@@ -73,6 +89,119 @@ pub fn close(fd: usize) -> isize {
     sys_close(fd)
 }
 
+/// copy `fd` to the lowest free fd
+pub fn dup(fd: usize) -> isize {
+    sys_dup(fd)
+}
+
+/// copy `old_fd` to `new_fd`, closing what `new_fd` had open
+pub fn dup2(old_fd: usize, new_fd: usize) -> isize {
+    sys_dup3(old_fd, new_fd, 0)
+}
+
+/// like `dup2`, `flags` may mark `new_fd` close-on-exec;
+/// with flags, copying an fd onto itself fails instead of doing nothing
+pub fn dup3(old_fd: usize, new_fd: usize, flags: OpenFlags) -> isize {
+    sys_dup3(old_fd, new_fd, flags.bits)
+}
+
+/// make a pipe, its read fd goes to `pipe_fd[0]`, its write fd to `pipe_fd[1]`
+pub fn pipe(pipe_fd: &mut [usize]) -> isize {
+    sys_pipe(pipe_fd)
+}
+
+/// `whence` of lseek: from the start, the current offset, or the end
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// move the offset of `fd`, return the new offset
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    sys_lseek(fd, offset, whence)
+}
+
+/// What fstat tells about a file
+#[repr(C)]
+#[derive(Debug)]
+pub struct Stat {
+    /// device the file is on, always 0
+    pub dev: u64,
+    /// inode number
+    pub ino: u64,
+    /// type of the file
+    pub mode: StatMode,
+    /// number of hard links
+    pub nlink: u32,
+    /// size in bytes
+    pub size: u64,
+}
+
+bitflags! {
+    pub struct StatMode: u32 {
+        const NULL = 0;
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+impl Stat {
+    pub fn new() -> Self {
+        Self {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::NULL,
+            nlink: 0,
+            size: 0,
+        }
+    }
+}
+
+impl Default for Stat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    sys_fstat(fd, st as *mut _ as *mut u8)
+}
+
+/// longest name a directory record holds
+pub const NAME_LENGTH_LIMIT: usize = 27;
+
+/// A record of getdents, the same as an easy-fs directory entry
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DirEntry {
+    name: [u8; NAME_LENGTH_LIMIT + 1],
+    inode_number: u32,
+}
+
+impl DirEntry {
+    pub fn empty() -> Self {
+        Self {
+            name: [0u8; NAME_LENGTH_LIMIT + 1],
+            inode_number: 0,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|byte| *byte == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap()
+    }
+
+    pub fn inode_number(&self) -> u32 {
+        self.inode_number
+    }
+}
+
+/// read the next entries of the directory `fd` into `dirents`, return how many
+pub fn getdents(fd: usize, dirents: &mut [DirEntry]) -> isize {
+    sys_getdents(fd, dirents.as_mut_ptr() as *mut u8, dirents.len())
+}
+
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
     sys_read(fd, buf)
 }
@@ -1,9 +1,17 @@
 use core::arch::asm;
 
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
+const SYSCALL_CHDIR: usize = 49;
 const SYSCALL_OPEN: usize = 56;
 const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_PIPE: usize = 59;
+const SYSCALL_GETDENTS: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_GET_TIME: usize = 169;
@@ -26,6 +34,57 @@ fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+/**
+    Function: Copy an open fd to the lowest free fd.
+
+    Return value: the new fd, -1 if fd is not open.
+
+    syscall ID：23
+*/
+pub fn sys_dup(fd: usize) -> isize {
+    syscall(SYSCALL_DUP, [fd, 0, 0])
+}
+
+/**
+    Function: Copy an open fd to new_fd, closing what new_fd had open.
+
+    Parameter: flags may only hold CLOEXEC, which marks new_fd to be
+               closed by exec.
+
+    Return value: new_fd, -1 if old_fd is not open or new_fd is too large.
+
+    syscall ID：24
+*/
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: u32) -> isize {
+    syscall(SYSCALL_DUP3, [old_fd, new_fd, flags as usize])
+}
+
+/**
+    Function: Tell the absolute path of the current directory.
+
+    Parameter: buf receives the path and a trailing '\0'.
+
+    Return value: the length written, '\0' included, -1 if buf is too short.
+
+    syscall ID：17
+*/
+pub fn sys_getcwd(buf: &mut [u8]) -> isize {
+    syscall(SYSCALL_GETCWD, [buf.as_mut_ptr() as usize, buf.len(), 0])
+}
+
+/**
+    Function: Change the current directory, where relative paths start.
+
+    Parameter: path ends with '\0', absolute or relative.
+
+    Return value: 0, or -1 if path is not a directory.
+
+    syscall ID：49
+*/
+pub fn sys_chdir(path: &str) -> isize {
+    syscall(SYSCALL_CHDIR, [path.as_ptr() as usize, 0, 0])
+}
+
 pub fn sys_open(path: &str, flags: u32) -> isize {
     syscall(SYSCALL_OPEN, [path.as_ptr() as usize, flags as usize, 0])
 }
@@ -34,6 +93,20 @@ pub fn sys_close(fd: usize) -> isize {
     syscall(SYSCALL_CLOSE, [fd, 0, 0])
 }
 
+/**
+    Function: Make a pipe, a read end and a write end sharing a buffer.
+
+    Parameter: pipe receives the fd of the read end in pipe[0], and
+               the fd of the write end in pipe[1].
+
+    Return value: 0 on success, -1 if an error occurs.
+
+    syscall ID：59
+*/
+pub fn sys_pipe(pipe: &mut [usize]) -> isize {
+    syscall(SYSCALL_PIPE, [pipe.as_mut_ptr() as usize, 0, 0])
+}
+
 pub fn sys_read(fd: usize, buffer: &mut [u8]) -> isize {
    syscall(
        SYSCALL_READ,
@@ -45,6 +118,46 @@ pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SYSCALL_WRITE, [fd, buffer.as_ptr() as usize, buffer.len()])
 }
 
+/**
+    Function: Move the offset of an open file.
+
+    Parameter: whence is SEEK_SET, SEEK_CUR or SEEK_END, what offset is
+               added to.
+
+    Return value: the new offset, -1 if fd cannot seek or the offset
+                  would be negative.
+
+    syscall ID：62
+*/
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    syscall(SYSCALL_LSEEK, [fd, offset as usize, whence])
+}
+
+/**
+    Function: Tell the inode number, type, size and links of an open file.
+
+    Return value: 0, or -1 if fd is not open.
+
+    syscall ID：80
+*/
+pub fn sys_fstat(fd: usize, st: *mut u8) -> isize {
+    syscall(SYSCALL_FSTAT, [fd, st as usize, 0])
+}
+
+/**
+    Function: Read the next entries of an open directory.
+
+    Parameter: dirents receives at most count records.
+
+    Return value: the number of records written, 0 once all were read,
+                  -1 if fd is not an open directory.
+
+    syscall ID：61
+*/
+pub fn sys_getdents(fd: usize, dirents: *mut u8, count: usize) -> isize {
+    syscall(SYSCALL_GETDENTS, [fd, dirents as usize, count])
+}
+
 pub fn sys_exit(exit_code: i32) -> isize {
     syscall(SYSCALL_EXIT, [exit_code as usize, 0, 0])
 }